@@ -0,0 +1,49 @@
+//! Compares single-threaded and parallel proof-of-work, run with `cargo bench`.
+use node::hashing::Sha256Hasher;
+use node::{Account, Block, Data, PublicKey, DEFAULT_MINING_LOG_INTERVAL};
+use std::sync::atomic::AtomicBool;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DIFFICULTY: usize = 16;
+const ROUNDS: u64 = 10;
+
+fn mine(threads: usize) -> Duration {
+    let start = Instant::now();
+    for round in 0..ROUNDS {
+        let data = Data::Account(Account {
+            address: round,
+            balance: 0,
+            pub_key: PublicKey([0x77; 32]),
+            nonce: 0,
+        });
+        Block::mine_block_parallel(
+            &Sha256Hasher,
+            0,
+            None,
+            1,
+            1665411301,
+            "hash",
+            &data,
+            DIFFICULTY,
+            u64::MAX,
+            threads,
+            DEFAULT_MINING_LOG_INTERVAL,
+            &AtomicBool::new(false),
+        )
+        .expect("Can mine within nonce space.");
+    }
+    start.elapsed() / ROUNDS as u32
+}
+
+fn main() {
+    // At least two threads, so the parallel path is measured even on a single core.
+    let threads = thread::available_parallelism()
+        .map(usize::from)
+        .unwrap_or(1)
+        .max(2);
+
+    println!("difficulty {}, mean of {} blocks", DIFFICULTY, ROUNDS);
+    println!("1 thread: {:?}", mine(1));
+    println!("{} threads: {:?}", threads, mine(threads));
+}
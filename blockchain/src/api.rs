@@ -0,0 +1,349 @@
+#[cfg(feature = "http-api")]
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+#[cfg(feature = "metrics")]
+use node::NodeMetrics;
+use node::{Account, Address, Block, Data, Node};
+#[cfg(feature = "http-api")]
+use node::{PublicKey, Signature};
+#[cfg(feature = "http-api")]
+use serde::Deserialize;
+use tokio::sync::oneshot;
+#[cfg(feature = "http-api")]
+use tokio::{net::TcpListener, sync::mpsc};
+
+#[cfg(feature = "http-api")]
+pub const API_TARGET: &str = "api";
+
+/// Request of the HTTP API, answered by the swarm task which owns the node.
+#[cfg_attr(not(feature = "http-api"), allow(dead_code))]
+pub enum ApiRequest {
+    Chain(oneshot::Sender<Vec<Block>>),
+    Account(Address, oneshot::Sender<Option<Account>>),
+    Peers(oneshot::Sender<Vec<String>>),
+    Transfer(Data, oneshot::Sender<bool>),
+    #[cfg(feature = "json-rpc")]
+    Block(u64, oneshot::Sender<Option<Block>>),
+    #[cfg(feature = "json-rpc")]
+    Height(oneshot::Sender<Option<u64>>),
+    #[cfg(feature = "metrics")]
+    Metrics(oneshot::Sender<NodeMetrics>),
+}
+
+impl ApiRequest {
+    pub fn respond(self, node: &mut Node, peers: Vec<String>) {
+        // Sending fails only when the HTTP client went away, nothing to do then.
+        match self {
+            ApiRequest::Chain(sender) => {
+                let _ = sender.send(node.blocks.clone());
+            }
+            ApiRequest::Account(address, sender) => {
+                let _ = sender.send(node.accounts.get(&address).cloned());
+            }
+            ApiRequest::Peers(sender) => {
+                let _ = sender.send(peers);
+            }
+            ApiRequest::Transfer(data, sender) => {
+                let _ = sender.send(node.add_to_mempool(data));
+            }
+            #[cfg(feature = "json-rpc")]
+            ApiRequest::Block(id, sender) => {
+                let _ = sender.send(node.get_block_by_id(id).cloned());
+            }
+            #[cfg(feature = "json-rpc")]
+            ApiRequest::Height(sender) => {
+                let _ = sender.send(node.height());
+            }
+            #[cfg(feature = "metrics")]
+            ApiRequest::Metrics(sender) => {
+                let mut metrics = node.metrics();
+                metrics.peers = peers.len();
+                let _ = sender.send(metrics);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "http-api")]
+#[derive(Debug, Deserialize)]
+pub struct TransferRequest {
+    pub sender: Address,
+    pub receiver: Address,
+    pub amount: u64,
+    #[serde(default)]
+    pub fee: u64,
+    #[serde(default)]
+    pub nonce: u64,
+    pub signature: Signature,
+    #[serde(default)]
+    pub receiver_pub_key: Option<PublicKey>,
+}
+
+#[cfg(feature = "http-api")]
+impl From<TransferRequest> for Data {
+    fn from(transfer: TransferRequest) -> Self {
+        Data::Transfer {
+            sender: transfer.sender,
+            receiver: transfer.receiver,
+            amount: transfer.amount,
+            fee: transfer.fee,
+            nonce: transfer.nonce,
+            signature: transfer.signature,
+            receiver_pub_key: transfer.receiver_pub_key,
+        }
+    }
+}
+
+#[cfg(feature = "http-api")]
+pub type ApiSender = mpsc::UnboundedSender<ApiRequest>;
+
+#[cfg(feature = "http-api")]
+pub fn router(sender: ApiSender) -> Router {
+    let router = Router::new()
+        .route("/chain", get(get_chain))
+        .route("/account/:address", get(get_account))
+        .route("/peers", get(get_peers))
+        .route("/transfer", post(post_transfer));
+    #[cfg(feature = "metrics")]
+    let router = router.route("/metrics", get(get_metrics));
+    #[cfg(feature = "json-rpc")]
+    let router = router.route("/rpc", post(crate::rpc::handle_rpc));
+    router.with_state(sender)
+}
+
+#[cfg(feature = "http-api")]
+pub async fn serve(port: u16, sender: ApiSender) -> std::io::Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    log::info!(target: API_TARGET, "Serving HTTP API on {}", listener.local_addr()?);
+    axum::serve(listener, router(sender)).await
+}
+
+#[cfg(feature = "http-api")]
+pub async fn ask<T>(
+    sender: &ApiSender,
+    request: impl FnOnce(oneshot::Sender<T>) -> ApiRequest,
+) -> Result<T, StatusCode> {
+    let (response_sender, response_receiver) = oneshot::channel();
+    sender
+        .send(request(response_sender))
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+    response_receiver
+        .await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)
+}
+
+#[cfg(feature = "http-api")]
+async fn get_chain(State(sender): State<ApiSender>) -> Result<Json<Vec<Block>>, StatusCode> {
+    ask(&sender, ApiRequest::Chain).await.map(Json)
+}
+
+#[cfg(feature = "http-api")]
+async fn get_account(
+    State(sender): State<ApiSender>,
+    Path(address): Path<Address>,
+) -> Result<Json<Account>, StatusCode> {
+    ask(&sender, |response| ApiRequest::Account(address, response))
+        .await?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[cfg(feature = "http-api")]
+async fn get_peers(State(sender): State<ApiSender>) -> Result<Json<Vec<String>>, StatusCode> {
+    ask(&sender, ApiRequest::Peers).await.map(Json)
+}
+
+#[cfg(feature = "http-api")]
+async fn post_transfer(
+    State(sender): State<ApiSender>,
+    Json(transfer): Json<TransferRequest>,
+) -> StatusCode {
+    match ask(&sender, |response| {
+        ApiRequest::Transfer(transfer.into(), response)
+    })
+    .await
+    {
+        Ok(true) => StatusCode::ACCEPTED,
+        Ok(false) => StatusCode::UNPROCESSABLE_ENTITY,
+        Err(status) => status,
+    }
+}
+
+#[cfg(feature = "metrics")]
+async fn get_metrics(State(sender): State<ApiSender>) -> Result<String, StatusCode> {
+    ask(&sender, ApiRequest::Metrics)
+        .await
+        .map(|metrics| prometheus_text(&metrics))
+}
+
+/// Renders the metrics as gauges in the Prometheus text format.
+#[cfg(feature = "metrics")]
+fn prometheus_text(metrics: &NodeMetrics) -> String {
+    let gauges = [
+        (
+            "chain_height",
+            "Id of the last block.",
+            Some(metrics.height as f64),
+        ),
+        (
+            "accounts",
+            "Number of accounts.",
+            Some(metrics.accounts as f64),
+        ),
+        (
+            "mempool_transfers",
+            "Transfers waiting to be mined.",
+            Some(metrics.mempool as f64),
+        ),
+        (
+            "difficulty",
+            "Difficulty of the next block.",
+            Some(metrics.difficulty as f64),
+        ),
+        (
+            "last_block_timestamp_seconds",
+            "Timestamp of the last block.",
+            metrics
+                .last_block_timestamp
+                .map(|timestamp| timestamp as f64),
+        ),
+        (
+            "transactions",
+            "Number of transfers in the chain.",
+            Some(metrics.total_transactions as f64),
+        ),
+        (
+            "average_tps",
+            "Transfers a second between the first and the last block.",
+            Some(metrics.average_tps),
+        ),
+        (
+            "peers",
+            "Number of known peers.",
+            Some(metrics.peers as f64),
+        ),
+    ];
+    let mut text = String::new();
+    for (name, help, value) in gauges {
+        if let Some(value) = value {
+            text.push_str(&format!(
+                "# HELP ch_rust_{0} {1}\n# TYPE ch_rust_{0} gauge\nch_rust_{0} {2}\n",
+                name, help, value
+            ));
+        }
+    }
+    text
+}
+
+/// Answers the requests of the tests from the node on a task of its own, as the swarm would.
+#[cfg(all(test, feature = "http-api"))]
+pub(crate) fn spawn_node(mut node: Node) -> ApiSender {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<ApiRequest>();
+    tokio::spawn(async move {
+        while let Some(request) = receiver.recv().await {
+            request.respond(&mut node, vec!["peer".to_string()]);
+        }
+    });
+    sender
+}
+
+#[cfg(all(test, feature = "http-api"))]
+mod api_tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use http_body_util::BodyExt;
+    use node::{sign_transfer, GENESIS_PRIVATE_KEY};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    async fn call(sender: &ApiSender, request: Request<Body>) -> (StatusCode, Value) {
+        let response = router(sender.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    fn get_request(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    fn post_transfer_request(transfer: &Data) -> Request<Body> {
+        let body = json!({
+            "sender": transfer.sender(),
+            "receiver": transfer.receiver(),
+            "amount": transfer.amount(),
+            "nonce": transfer.nonce(),
+            "signature": transfer.signature(),
+        });
+        Request::post("/transfer")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_chain_accounts_and_peers() {
+        let mut node = Node::new();
+        node.genesis();
+        let genesis_block = node.blocks[0].clone();
+        let sender = spawn_node(node);
+
+        let (status, chain) = call(&sender, get_request("/chain")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(chain, json!([genesis_block]));
+
+        let (status, account) = call(&sender, get_request("/account/0")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(account["balance"], json!(node::GENESIS_SUPPLY));
+
+        let (status, _) = call(&sender, get_request("/account/1")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, peers) = call(&sender, get_request("/peers")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(peers, json!(["peer"]));
+    }
+
+    #[tokio::test]
+    async fn queues_valid_transfer_and_rejects_replay() {
+        let mut node = Node::new();
+        node.genesis();
+        let receiver = node.add_account().0.address;
+        let sender = spawn_node(node);
+        let transfer = sign_transfer(&GENESIS_PRIVATE_KEY, 0, receiver, 10, 0, 0);
+
+        let (status, _) = call(&sender, post_transfer_request(&transfer)).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+
+        let (status, _) = call(&sender, post_transfer_request(&transfer)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn serves_prometheus_metrics() {
+        let mut node = Node::new();
+        node.genesis();
+        let sender = spawn_node(node);
+
+        let response = router(sender)
+            .oneshot(get_request("/metrics"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(text.contains("# TYPE ch_rust_chain_height gauge\nch_rust_chain_height 0\n"));
+        assert!(text.contains("ch_rust_accounts 1\n"));
+        assert!(text.contains("ch_rust_last_block_timestamp_seconds 1665411300\n"));
+        assert!(text.contains("ch_rust_transactions 0\n"));
+        assert!(text.contains("ch_rust_average_tps 0\n"));
+        assert!(text.contains("ch_rust_peers 1\n"));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::p2p::P2P_TARGET;
+use chrono::Utc;
+use log::error;
+use node::{Address, Block, Data};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// File the rejected blocks and transfers are appended to, one JSON record a line, so attacks
+/// can be investigated after the log lines are gone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub timestamp: i64,
+    #[serde(flatten)]
+    pub item: RejectedItem,
+    /// Peer the item came from, `None` for the local API and console.
+    pub peer: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RejectedItem {
+    Block {
+        id: u64,
+        hash: String,
+    },
+    Transfer {
+        sender: Option<Address>,
+        nonce: Option<u64>,
+    },
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn block(&self, block: &Block, peer: Option<String>, reason: &impl Display) {
+        let item = RejectedItem::Block {
+            id: block.id,
+            hash: block.hash.clone(),
+        };
+        self.record(item, peer, reason);
+    }
+
+    pub fn transfer(&self, transfer: &Data, peer: Option<String>, reason: &impl Display) {
+        let item = RejectedItem::Transfer {
+            sender: transfer.sender(),
+            nonce: transfer.nonce(),
+        };
+        self.record(item, peer, reason);
+    }
+
+    fn record(&self, item: RejectedItem, peer: Option<String>, reason: &impl Display) {
+        let record = AuditRecord {
+            timestamp: Utc::now().timestamp(),
+            item,
+            peer,
+            reason: reason.to_string(),
+        };
+        if let Err(e) = self.append(&record) {
+            error!(
+                target: P2P_TARGET,
+                "Error writing audit record to {}, {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    fn append(&self, record: &AuditRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&line)
+    }
+}
@@ -0,0 +1,5358 @@
+use crate::core::{
+    calculate_hash, difficulty_prefix, hash_to_binary_representation, total_fees, Account, Address,
+    Block, BlockError, BlockRules, Data, TransferError, BLOCK_VERSION, CANONICAL_BLOCK_VERSION,
+    DEFAULT_BLOCK_REWARD, DEFAULT_MAX_BLOCK_BYTES, LEGACY_BLOCK_VERSION, MERKLE_BLOCK_VERSION,
+};
+use crate::crypto::{self, PrivateKey, PublicKey, Signature};
+use crate::hashing::{Hasher, Sha256Hasher};
+use crate::policy::{AcceptAll, BlockPolicy};
+use crate::{CHAIN_TARGET, MINING_TARGET, SYNC_TARGET};
+use chrono::prelude::*;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{fmt, vec};
+use tokio::sync::broadcast;
+
+const DEFAULT_DIFFICULTY: usize = 2;
+const DEFAULT_TARGET_BLOCK_TIME_SECS: i64 = 10;
+const RETARGET_WINDOW: usize = 10;
+const DEFAULT_MAX_FUTURE_DRIFT_SECS: i64 = 2 * 60 * 60;
+const GENESIS_ADDRESS: u64 = 0;
+/// Coins of the default network, all held by the genesis account. Far below `u64::MAX`, so
+/// rewards minted on top of it do not overflow the total.
+pub const GENESIS_SUPPLY: u64 = 1_000_000_000;
+/// Well-known development key of the genesis account, lets anyone fund accounts on a local network.
+pub const GENESIS_PRIVATE_KEY: PrivateKey = PrivateKey([0x42; 32]);
+const GENESIS_PUB_KEY: PublicKey = PublicKey([
+    33, 82, 248, 209, 155, 121, 29, 36, 69, 50, 66, 225, 95, 46, 171, 108, 183, 207, 250, 123, 106,
+    94, 211, 0, 151, 150, 14, 6, 152, 129, 219, 18,
+]);
+const GENESIS_ACCOUNT: Account = Account {
+    address: GENESIS_ADDRESS,
+    balance: GENESIS_SUPPLY,
+    pub_key: GENESIS_PUB_KEY,
+    nonce: 0,
+};
+
+const INIT_BALANCE: u64 = 0;
+const MAX_REORG_HISTORY: usize = 32;
+/// Subscribers lagging further behind miss the oldest blocks.
+const BLOCK_EVENTS_CAPACITY: usize = 64;
+/// Default of `Node::max_txs_per_block`.
+pub const MAX_BLOCK_TRANSFERS: usize = 16;
+/// Default of `Node::max_orphans`.
+pub const MAX_ORPHAN_BLOCKS: usize = 64;
+/// States kept by `Node::state_at`, the least recently added is dropped first.
+const STATE_CACHE_CAPACITY: usize = 8;
+/// Default of `Node::mining_log_interval`.
+pub const DEFAULT_MINING_LOG_INTERVAL: u64 = 100_000;
+
+pub struct Node {
+    pub difficulty: usize,
+    pub target_block_time_secs: i64,
+    pub miner_address: Address,
+    /// Peer id the blocks mined by the node are tagged with, see `Block::miner`.
+    pub miner: Option<String>,
+    pub block_reward: u64,
+    /// Blocks after which the block reward halves, it rounds down to zero eventually. Never halved
+    /// if not set. Blocks are checked against the schedule, so all nodes of a network have to
+    /// use the same interval.
+    pub halving_interval: Option<u64>,
+    pub max_block_bytes: usize,
+    /// Most transfers the node mines into one block, the ones with the highest fees go first and
+    /// the rest wait in the mempool. Only a local policy, blocks of peers may hold more.
+    pub max_txs_per_block: usize,
+    /// Fee suggested by `estimate_fee` while the mempool has room to spare.
+    pub min_fee_estimate: u64,
+    /// How far ahead of the local clock a block may be timestamped, so clock skew between peers
+    /// is tolerated but a far future timestamp cannot skew difficulty retargeting.
+    pub max_future_drift_secs: i64,
+    /// Most blocks buffered until their parent arrives, so a block overtaking its parent in gossip
+    /// is not lost. When the pool is full the blocks furthest ahead of the chain are dropped.
+    pub max_orphans: usize,
+    /// Whether a transfer to an unknown address creates the receiver account, with the key given
+    /// in the transfer. The key is chosen by the sender and nothing proves the receiver holds its
+    /// private key, so funds sent with a wrong key are lost for good. All nodes of a network have
+    /// to agree on the policy, they reject each other's blocks otherwise.
+    pub allow_implicit_accounts: bool,
+    /// Whether the addresses of accounts registered after the genesis block, explicitly or by a
+    /// transfer, have to be derived from their keys with `Account::derive_address`. Otherwise
+    /// anyone may register any free address with their own key. All nodes of a network have to
+    /// agree on it, chains with accounts at other addresses are rejected.
+    pub require_derived_addresses: bool,
+    /// Smallest amount of a transfer, so dust does not waste block space. Like the other transfer
+    /// limits it is checked in blocks too, so all nodes of a network have to use the same value.
+    pub min_transfer: u64,
+    /// Largest amount of a transfer, unlimited if not set.
+    pub max_transfer: Option<u64>,
+    /// Whether the node mines blocks, a validator only adds the blocks of its peers.
+    pub mining_enabled: bool,
+    /// Nonces tried between the progress logs of mining.
+    pub mining_log_interval: u64,
+    /// Most blocks a chain replacing the local one may be ahead of it, so a single peer claiming
+    /// a far longer chain cannot take the node over at once. Unlimited if not set. A node without
+    /// a chain adopts any valid one.
+    pub max_reorg_depth: Option<u64>,
+    /// Network of the node, signed into transfers and hashed into blocks after the genesis block,
+    /// so neither is valid on a network with another id sharing the keys. 0, the default, keeps
+    /// the signatures and hashes of networks predating chain ids.
+    pub chain_id: u64,
+    pub blocks: Vec<Block>,
+    pub accounts: HashMap<Address, Account>,
+    pub pub_keys: HashMap<Address, PublicKey>,
+    pub mempool: Vec<Data>,
+    reorgs: VecDeque<Reorg>,
+    /// Blocks ahead of the chain by the hash of their missing parent.
+    orphans: HashMap<String, Block>,
+    /// Accounts replayed by `state_at`, by the hash of the block they are the state after.
+    state_cache: Mutex<VecDeque<(String, HashMap<Address, Account>)>>,
+    hasher: &'static dyn Hasher,
+    policy: Arc<dyn BlockPolicy>,
+    genesis_block: Block,
+    pub(crate) checkpoint: Option<Checkpoint>,
+    block_events: broadcast::Sender<Block>,
+}
+
+/// Initial accounts of a network, nodes started from different configs reject each other's chains.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GenesisConfig {
+    pub timestamp: i64,
+    #[serde(default = "default_difficulty")]
+    pub difficulty: usize,
+    pub accounts: Vec<Account>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainReport {
+    pub total_blocks: usize,
+    pub total_accounts: usize,
+    /// Index of the first invalid block in the chain, which is not its id on a pruned chain.
+    pub invalid_block: Option<usize>,
+    /// Whether replaying the transfers never overspends an account and gives the same balances.
+    pub balances_consistent: bool,
+}
+
+/// Snapshot of the node state for operators, `peers` is filled in by the p2p layer.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct NodeMetrics {
+    pub height: u64,
+    pub accounts: usize,
+    pub mempool: usize,
+    pub difficulty: usize,
+    pub last_block_timestamp: Option<i64>,
+    pub total_transactions: u64,
+    pub average_tps: f64,
+    pub peers: usize,
+}
+
+/// Read-only view of the tip and the balances, cheap to clone and send to another task, so
+/// queries do not wait for the node. Later blocks are not reflected in it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeSnapshot {
+    pub height: Option<u64>,
+    pub tip_hash: Option<String>,
+    balances: Arc<HashMap<Address, u64>>,
+}
+
+impl NodeSnapshot {
+    pub fn balance_of(&self, address: Address) -> Option<u64> {
+        self.balances.get(&address).copied()
+    }
+
+    pub fn accounts(&self) -> usize {
+        self.balances.len()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MiningError {
+    Exhausted,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainSelectionError {
+    BothInvalid,
+}
+
+/// Transfer settings of the node which blocks are validated with.
+#[derive(Clone, Copy)]
+struct TransferPolicy {
+    allow_implicit_accounts: bool,
+    require_derived_addresses: bool,
+    min_transfer: u64,
+    max_transfer: Option<u64>,
+    /// Off for the transfers of a block, whose signatures are checked up front by `verify_batch`.
+    verify_signatures: bool,
+    chain_id: u64,
+    /// On for the genesis block only, whose accounts hold the initial supply.
+    genesis: bool,
+}
+
+/// Effort spent on a nonce search so far, logged while mining.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MiningProgress {
+    pub attempts: u64,
+    pub elapsed: Duration,
+    pub difficulty: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GenesisError {
+    NoAccounts,
+    DuplicateAddress(Address),
+    /// The genesis balances add up to more than `u64::MAX`.
+    SupplyOverflow,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    EmptyChain,
+    /// The data of the block does not apply to the state, e.g. a transfer overspends.
+    InvalidData(u64),
+    /// No block at the height, it is past the tip or was pruned.
+    UnknownHeight(u64),
+}
+
+/// State of the chain at its first retained block once the older blocks are pruned. Later blocks
+/// are validated on top of it, a fork below it can no longer be adopted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub block: Block,
+    /// Difficulty retargeted over the pruned blocks.
+    pub difficulty: usize,
+    /// Accounts once the data of the checkpoint block is applied.
+    pub accounts: HashMap<Address, Account>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Reorg {
+    pub timestamp: i64,
+    pub old_tip: String,
+    pub new_tip: String,
+    pub depth: u64,
+    pub affected_accounts: Vec<Address>,
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Node {
+    pub fn new() -> Self {
+        Self::with_difficulty(DEFAULT_DIFFICULTY)
+    }
+
+    pub fn with_difficulty(difficulty: usize) -> Self {
+        Self {
+            difficulty,
+            target_block_time_secs: DEFAULT_TARGET_BLOCK_TIME_SECS,
+            miner_address: GENESIS_ADDRESS,
+            miner: None,
+            block_reward: DEFAULT_BLOCK_REWARD,
+            halving_interval: None,
+            max_block_bytes: DEFAULT_MAX_BLOCK_BYTES,
+            max_txs_per_block: MAX_BLOCK_TRANSFERS,
+            min_fee_estimate: 0,
+            max_future_drift_secs: DEFAULT_MAX_FUTURE_DRIFT_SECS,
+            max_orphans: MAX_ORPHAN_BLOCKS,
+            allow_implicit_accounts: false,
+            require_derived_addresses: false,
+            min_transfer: 0,
+            max_transfer: None,
+            mining_enabled: true,
+            mining_log_interval: DEFAULT_MINING_LOG_INTERVAL,
+            max_reorg_depth: None,
+            chain_id: 0,
+            blocks: vec![],
+            accounts: HashMap::new(),
+            pub_keys: HashMap::new(),
+            mempool: vec![],
+            reorgs: VecDeque::new(),
+            orphans: HashMap::new(),
+            state_cache: Mutex::new(VecDeque::new()),
+            hasher: &Sha256Hasher,
+            policy: Arc::new(AcceptAll),
+            genesis_block: Self::default_genesis_block(&Sha256Hasher),
+            checkpoint: None,
+            block_events: broadcast::channel(BLOCK_EVENTS_CAPACITY).0,
+        }
+    }
+
+    /// Node hashing blocks with the given function instead of SHA-256.
+    pub fn with_hasher(hasher: &'static dyn Hasher) -> Self {
+        let mut node = Self::new();
+        node.hasher = hasher;
+        node.genesis_block = Self::default_genesis_block(hasher);
+        node
+    }
+
+    pub fn hasher(&self) -> &'static dyn Hasher {
+        self.hasher
+    }
+
+    /// Node adding only the blocks the policy accepts, besides validating them.
+    pub fn with_policy(policy: Arc<dyn BlockPolicy>) -> Self {
+        let mut node = Self::new();
+        node.policy = policy;
+        node
+    }
+
+    pub fn genesis(&mut self) {
+        self.genesis_from_config(&GenesisConfig::default())
+            .expect("Default genesis config is valid.");
+    }
+
+    pub fn genesis_from_config(&mut self, config: &GenesisConfig) -> Result<(), GenesisError> {
+        self.set_genesis(config)?;
+        for account in &config.accounts {
+            self.pub_keys.insert(account.address, account.pub_key);
+            self.accounts.insert(account.address, account.clone());
+        }
+        self.blocks.push(self.genesis_block.clone());
+        Ok(())
+    }
+
+    /// Sets the genesis block the accepted chains have to start with, without adding it.
+    pub fn set_genesis(&mut self, config: &GenesisConfig) -> Result<(), GenesisError> {
+        config.validate()?;
+        self.genesis_block = config.block(self.hasher);
+        Ok(())
+    }
+
+    /// Genesis block of the default network, it was not mined. The hash is derived from the fields,
+    /// so it cannot drift from them.
+    fn default_genesis_block(hasher: &dyn Hasher) -> Block {
+        let mut block = Block {
+            version: LEGACY_BLOCK_VERSION,
+            id: 0,
+            previous_hash: String::from("genesis"),
+            timestamp: 1665411300,
+            data: Data::Account(GENESIS_ACCOUNT.clone()),
+            merkle_root: String::new(),
+            nonce: 420,
+            hash: String::new(),
+            miner: None,
+        };
+        block.hash = block.compute_hash(hasher, 0);
+        block
+    }
+
+    /// Adds an account with a fresh keypair, the private key is not kept by the node.
+    pub fn add_account(&mut self) -> (Account, PrivateKey) {
+        let (mut account, mut private_key) = Account::new();
+
+        loop {
+            if let Entry::Vacant(entry) = self.accounts.entry(account.address) {
+                entry.insert(account.clone());
+                self.pub_keys.insert(account.address, account.pub_key);
+                break;
+            }
+
+            (account, private_key) = Account::new();
+        }
+
+        (account, private_key)
+    }
+
+    pub fn try_add_block(&mut self, block: Block) -> Result<(), BlockError> {
+        // Before the genesis block there is no parent to extend.
+        let latest_block = self.tip().ok_or(BlockError::WrongPreviousHash)?;
+
+        let difficulty = self.compute_difficulty();
+        if let Err(e) = self.is_block_valid(&block, latest_block, difficulty) {
+            error!(target: CHAIN_TARGET, "Could not add block - invalid.");
+            return Err(e);
+        }
+        let items = match &block.data {
+            Data::Batch(items) => &items[..],
+            data => std::slice::from_ref(data),
+        };
+        if let Err(index) = self.verify_batch(items) {
+            warn!(
+                target: CHAIN_TARGET,
+                "Block with id: {} has invalid signature of item: {}", block.id, index
+            );
+            return Err(BlockError::InvalidTransfer(TransferError::BadSignature));
+        }
+        // Checked here rather than when the data is applied, as the genesis accounts predate it.
+        let mismatched = items.iter().find_map(|item| match item {
+            Data::Account(account)
+                if self.require_derived_addresses
+                    && account.address != Account::derive_address(&account.pub_key) =>
+            {
+                Some(account.address)
+            }
+            _ => None,
+        });
+        if let Some(address) = mismatched {
+            warn!(
+                target: CHAIN_TARGET,
+                "Block with id: {} has account {} not derived from its key", block.id, address
+            );
+            return Err(BlockError::AddressMismatch(address));
+        }
+        if let Err(e) = self.policy.accept(&block, self) {
+            warn!(
+                target: CHAIN_TARGET,
+                "Block with id: {} rejected by policy, {}", block.id, e
+            );
+            return Err(BlockError::Rejected(e));
+        }
+        self.apply_data(&block.data)?;
+        self.publish_block(&block);
+        self.blocks.push(block);
+        self.connect_orphans();
+        Ok(())
+    }
+
+    /// Buffers a block whose parent is not in the chain yet, it is added once the parent is.
+    /// Returns whether the block was buffered.
+    pub fn add_orphan(&mut self, block: Block) -> bool {
+        let next_id = match self.height() {
+            Some(height) => height + 1,
+            None => return false,
+        };
+        if block.id <= next_id || self.orphans.contains_key(&block.previous_hash) {
+            return false;
+        }
+        // The difficulty at the height of the block is not known before its parent.
+        if block
+            .validate_self(0, self.hasher, self.chain_id, self.max_future_drift_secs)
+            .is_err()
+        {
+            return false;
+        }
+        if self.orphans.len() >= self.max_orphans {
+            let furthest = self
+                .orphans
+                .values()
+                .max_by_key(|orphan| orphan.id)
+                .filter(|orphan| orphan.id > block.id)
+                .map(|orphan| orphan.previous_hash.clone());
+            match furthest {
+                Some(previous_hash) => self.orphans.remove(&previous_hash),
+                None => {
+                    info!(target: CHAIN_TARGET, "Orphan pool is full, dropping block {}", block.id);
+                    return false;
+                }
+            };
+        }
+        info!(target: CHAIN_TARGET, "Buffering block {} until its parent arrives", block.id);
+        self.orphans.insert(block.previous_hash.clone(), block);
+        true
+    }
+
+    pub fn orphan_count(&self) -> usize {
+        self.orphans.len()
+    }
+
+    /// Adds the buffered child of the tip, which in turn adds its own, and drops the orphans the
+    /// chain has moved past.
+    fn connect_orphans(&mut self) {
+        let (tip_id, tip_hash) = match self.tip() {
+            Some(tip) => (tip.id, tip.hash.clone()),
+            None => return,
+        };
+        self.orphans.retain(|_, orphan| orphan.id > tip_id);
+        if let Some(orphan) = self.orphans.remove(&tip_hash) {
+            let id = orphan.id;
+            if let Err(e) = self.try_add_block(orphan) {
+                warn!(target: CHAIN_TARGET, "Dropping orphan block {}, {}", id, e);
+            }
+        }
+    }
+
+    /// Receives every block added to the chain from now on, also those of an adopted chain.
+    pub fn subscribe_blocks(&self) -> broadcast::Receiver<Block> {
+        self.block_events.subscribe()
+    }
+
+    fn publish_block(&self, block: &Block) {
+        // Fails only when nobody is subscribed.
+        let _ = self.block_events.send(block.clone());
+    }
+
+    /// Checks the signatures of the transfers among the items, with the keys of the senders known
+    /// or created by earlier items. Returns the index of the first transfer whose signature does
+    /// not verify. Transfers from unknown senders are left to be rejected when applied.
+    pub fn verify_batch(&self, items: &[Data]) -> Result<(), usize> {
+        let mut new_keys: HashMap<Address, PublicKey> = HashMap::new();
+        for (index, item) in items.iter().enumerate() {
+            match item {
+                Data::Account(account) if !self.pub_keys.contains_key(&account.address) => {
+                    new_keys.insert(account.address, account.pub_key);
+                }
+                Data::Transfer {
+                    sender,
+                    receiver,
+                    amount,
+                    fee,
+                    nonce,
+                    signature,
+                    receiver_pub_key,
+                } => {
+                    let pub_key = new_keys.get(sender).or_else(|| self.pub_keys.get(sender));
+                    let message = transfer_message(
+                        self.chain_id,
+                        *sender,
+                        *receiver,
+                        *amount,
+                        *fee,
+                        *nonce,
+                        receiver_pub_key.as_ref(),
+                    );
+                    if matches!(pub_key, Some(pub_key) if !crypto::verify(pub_key, &message, signature))
+                    {
+                        return Err(index);
+                    }
+                    if let Some(receiver_pub_key) = receiver_pub_key {
+                        if !self.pub_keys.contains_key(receiver) {
+                            new_keys.entry(*receiver).or_insert(*receiver_pub_key);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_data(&mut self, data: &Data) -> Result<(), BlockError> {
+        // Stage the changes on copies, so an invalid payload leaves the state untouched.
+        let mut accounts = self.accounts.clone();
+        let mut pub_keys = self.pub_keys.clone();
+        let policy = TransferPolicy {
+            verify_signatures: false,
+            ..self.transfer_policy()
+        };
+        Self::stage_data(data, &mut accounts, &mut pub_keys, policy)?;
+        self.accounts = accounts;
+        self.pub_keys = pub_keys;
+        Ok(())
+    }
+
+    fn stage_data(
+        data: &Data,
+        accounts: &mut HashMap<Address, Account>,
+        pub_keys: &mut HashMap<Address, PublicKey>,
+        policy: TransferPolicy,
+    ) -> Result<(), BlockError> {
+        match data {
+            // An account is announced once, anything else would overwrite an existing account. A
+            // new key would let the announcer forge transfers.
+            Data::Account(account) => match (
+                pub_keys.get(&account.address),
+                accounts.contains_key(&account.address),
+            ) {
+                (Some(pub_key), _) if *pub_key != account.pub_key => {
+                    error!(target: CHAIN_TARGET, "Account: address is registered with another key!");
+                    Err(BlockError::PubKeyMismatch(account.address))
+                }
+                (Some(_), _) | (_, true) => {
+                    error!(target: CHAIN_TARGET, "Account: address is already taken!");
+                    Err(BlockError::DuplicateAccount(account.address))
+                }
+                // Coins are only minted by the genesis block and the rewards.
+                _ if !policy.genesis && (account.balance != INIT_BALANCE || account.nonce != 0) => {
+                    error!(target: CHAIN_TARGET, "Account: announced with a balance or nonce!");
+                    Err(BlockError::FundedAccount(account.address))
+                }
+                _ => {
+                    accounts.insert(account.address, account.clone());
+                    pub_keys.insert(account.address, account.pub_key);
+                    Ok(())
+                }
+            },
+            Data::Transfer { .. } => Self::apply_transfer(pub_keys, accounts, data, policy)
+                .map_err(BlockError::InvalidTransfer),
+            Data::Batch(items) => {
+                let result = items.iter().try_for_each(|item| match item {
+                    Data::Batch(_) => Err(BlockError::NestedBatch),
+                    item => Self::stage_data(item, accounts, pub_keys, policy),
+                });
+                if result.is_err() {
+                    error!(target: CHAIN_TARGET, "Batch: one of the items is invalid!");
+                }
+                result
+            }
+            Data::Reward(miner, reward) => match accounts.get_mut(miner) {
+                Some(account) => match account.balance.checked_add(*reward) {
+                    Some(balance) => {
+                        account.balance = balance;
+                        Ok(())
+                    }
+                    None => {
+                        error!(target: CHAIN_TARGET, "Reward: miner balance overflows!");
+                        Err(BlockError::BalanceOverflow(*miner))
+                    }
+                },
+                None => {
+                    error!(target: CHAIN_TARGET, "Reward: invalid miner address!");
+                    Err(BlockError::UnknownMiner)
+                }
+            },
+        }
+    }
+
+    pub fn add_to_mempool(&mut self, transfer: Data) -> bool {
+        self.try_add_to_mempool(transfer).is_ok()
+    }
+
+    pub fn try_add_to_mempool(&mut self, transfer: Data) -> Result<(), TransferError> {
+        if !matches!(transfer, Data::Transfer { .. }) {
+            error!(target: CHAIN_TARGET, "Mempool: only transfers can be queued!");
+            return Err(TransferError::NotATransfer);
+        }
+
+        // Validate against the state after all already queued transfers.
+        let mut accounts = self.accounts.clone();
+        let mut pub_keys = self.pub_keys.clone();
+        self.mempool
+            .iter()
+            .chain([&transfer])
+            .try_for_each(|pending| {
+                Self::apply_transfer(
+                    &mut pub_keys,
+                    &mut accounts,
+                    pending,
+                    self.transfer_policy(),
+                )
+            })?;
+
+        self.mempool.push(transfer);
+        Ok(())
+    }
+
+    /// Nonce of the next transfer from the address, counting the queued ones.
+    pub fn next_nonce(&self, address: Address) -> Option<u64> {
+        let queued = self
+            .mempool
+            .iter()
+            .filter(|transfer| transfer.sender() == Some(address))
+            .count() as u64;
+        self.accounts
+            .get(&address)
+            .map(|account| account.nonce + queued)
+    }
+
+    pub fn mine_pending(&mut self) -> Option<Block> {
+        if !self.mining_enabled {
+            info!(target: MINING_TARGET, "Mining is disabled, leaving transfers in the mempool.");
+            return None;
+        }
+        let (id, previous_hash) = self.tip().map(|tip| (tip.id + 1, tip.hash.clone()))?;
+        let data = self.take_pending()?;
+        let block = match Block::try_new(
+            id,
+            previous_hash,
+            data.clone(),
+            self.compute_difficulty(),
+            u64::MAX,
+            1,
+            self.mining_log_interval,
+            &AtomicBool::new(false),
+            self.hasher,
+            self.chain_id,
+            self.miner.clone(),
+        ) {
+            Ok(block) => block,
+            Err(e) => {
+                error!(target: MINING_TARGET, "Mempool: error mining block: {}", e);
+                self.requeue(data);
+                return None;
+            }
+        };
+
+        if self.try_add_block(block.clone()).is_err() {
+            error!(target: CHAIN_TARGET, "Mempool: mined block is no longer valid, dropping it.");
+            self.requeue(data);
+            return None;
+        }
+        Some(block)
+    }
+
+    /// Mines the data into a block on top of the tip, without adding it, but gives up once
+    /// `deadline` has passed, e.g. so that a regression does not hang the tests mining at a small
+    /// difficulty.
+    pub fn mine_block_with_deadline(&self, data: Data, deadline: Duration) -> Option<Block> {
+        let tip = self.tip()?;
+        let (id, timestamp) = (tip.id + 1, Utc::now().timestamp());
+        let miner = self.miner.as_deref();
+        let version = Block::version_mined_by(miner);
+        let (nonce, hash) = Block::mine_block_with_deadline(
+            self.hasher,
+            self.chain_id,
+            version,
+            miner,
+            id,
+            timestamp,
+            &tip.hash,
+            &data,
+            self.compute_difficulty(),
+            deadline,
+            self.mining_log_interval,
+        )?;
+        Some(Block {
+            version,
+            id,
+            hash,
+            previous_hash: tip.hash.clone(),
+            timestamp,
+            merkle_root: Block::merkle_root(&data, self.hasher),
+            data,
+            nonce,
+            miner: self.miner.clone(),
+        })
+    }
+
+    /// Fee outbidding the pending transfers which fill the next `target_blocks` blocks, so a
+    /// transfer paying it is mined within them unless higher fees arrive in the meantime.
+    pub fn estimate_fee(&self, target_blocks: u64) -> u64 {
+        let capacity = usize::try_from(target_blocks.max(1))
+            .unwrap_or(usize::MAX)
+            .saturating_mul(self.max_txs_per_block);
+        if self.mempool.len() < capacity {
+            return self.min_fee_estimate;
+        }
+        let mut fees: Vec<u64> = self
+            .mempool
+            .iter()
+            .map(|transfer| total_fees(transfer).unwrap_or_default())
+            .collect();
+        fees.sort_unstable_by_key(|fee| Reverse(*fee));
+        fees.get(capacity.saturating_sub(1))
+            .map_or(0, |fee| fee.saturating_add(1))
+            .max(self.min_fee_estimate)
+    }
+
+    /// Takes the transfers for the next block out of the mempool, with the miner reward prepended.
+    pub fn take_pending(&mut self) -> Option<Data> {
+        // Prefer higher fees, repeating the passes as a transfer may wait for a lower nonce.
+        self.mempool
+            .sort_by_key(|transfer| Reverse(total_fees(transfer).unwrap_or_default()));
+        let mut accounts = self.accounts.clone();
+        let mut pub_keys = self.pub_keys.clone();
+        let mut transfers = vec![];
+        loop {
+            let included = transfers.len();
+            for transfer in std::mem::take(&mut self.mempool) {
+                if transfers.len() < self.max_txs_per_block
+                    && Self::apply_transfer(
+                        &mut pub_keys,
+                        &mut accounts,
+                        &transfer,
+                        self.transfer_policy(),
+                    )
+                    .is_ok()
+                {
+                    transfers.push(transfer);
+                } else {
+                    self.mempool.push(transfer);
+                }
+            }
+            if transfers.len() == included {
+                break;
+            }
+        }
+        if transfers.is_empty() {
+            return None;
+        }
+
+        Some(self.with_reward(Data::Batch(transfers)))
+    }
+
+    /// Puts the transfers of a block which did not make it into the chain back in the mempool,
+    /// dropping the ones no longer valid.
+    pub fn requeue(&mut self, data: Data) {
+        let items = match data {
+            Data::Batch(items) => items,
+            data => vec![data],
+        };
+        let mut accounts = self.accounts.clone();
+        let mut pub_keys = self.pub_keys.clone();
+        let pending: Vec<Data> = items
+            .into_iter()
+            .filter(|item| matches!(item, Data::Transfer { .. }))
+            .chain(std::mem::take(&mut self.mempool))
+            .filter(|transfer| {
+                Self::apply_transfer(
+                    &mut pub_keys,
+                    &mut accounts,
+                    transfer,
+                    self.transfer_policy(),
+                )
+                .is_ok()
+            })
+            .collect();
+        self.mempool = pending;
+    }
+
+    /// Reward of the block with the given id, `block_reward` halved every `halving_interval`
+    /// blocks.
+    pub fn block_reward_at(&self, id: u64) -> u64 {
+        let halvings = match self
+            .halving_interval
+            .and_then(|interval| id.checked_div(interval))
+        {
+            Some(halvings) => halvings,
+            None => return self.block_reward,
+        };
+        u32::try_from(halvings)
+            .ok()
+            .and_then(|halvings| self.block_reward.checked_shr(halvings))
+            .unwrap_or(0)
+    }
+
+    /// Prepends the coinbase credit of the miner, the reward of the next block and fees, to the
+    /// block data.
+    pub fn with_reward(&self, data: Data) -> Data {
+        let fees = total_fees(&data).unwrap_or_default();
+        let next_id = self.height().map_or(0, |height| height + 1);
+        let reward = self.block_reward_at(next_id).saturating_add(fees);
+        if reward == 0 {
+            return data;
+        }
+
+        let reward = Data::Reward(self.miner_address, reward);
+        match data {
+            Data::Batch(mut items) => {
+                items.insert(0, reward);
+                Data::Batch(items)
+            }
+            data => Data::Batch(vec![reward, data]),
+        }
+    }
+
+    pub fn try_add_transfer(&mut self, transfer: &Data) -> Result<(), TransferError> {
+        let policy = self.transfer_policy();
+        Self::apply_transfer(&mut self.pub_keys, &mut self.accounts, transfer, policy)
+    }
+
+    fn transfer_policy(&self) -> TransferPolicy {
+        TransferPolicy {
+            allow_implicit_accounts: self.allow_implicit_accounts,
+            require_derived_addresses: self.require_derived_addresses,
+            min_transfer: self.min_transfer,
+            max_transfer: self.max_transfer,
+            verify_signatures: true,
+            chain_id: self.chain_id,
+            genesis: false,
+        }
+    }
+
+    /// Applies the transfer to `accounts`, which are left untouched if it is invalid.
+    fn apply_transfer(
+        pub_keys: &mut HashMap<Address, PublicKey>,
+        accounts: &mut HashMap<Address, Account>,
+        transfer: &Data,
+        policy: TransferPolicy,
+    ) -> Result<(), TransferError> {
+        if let Data::Transfer {
+            sender,
+            receiver,
+            amount,
+            fee,
+            nonce,
+            signature,
+            receiver_pub_key,
+        } = transfer
+        {
+            // It would only pay the fee, while counting as activity.
+            if sender == receiver {
+                error!(target: CHAIN_TARGET, "Transfer: sender and receiver are the same!");
+                return Err(TransferError::SelfTransfer);
+            }
+            if let Some(pub_key) = pub_keys.get(sender).filter(|_| policy.verify_signatures) {
+                let message = transfer_message(
+                    policy.chain_id,
+                    *sender,
+                    *receiver,
+                    *amount,
+                    *fee,
+                    *nonce,
+                    receiver_pub_key.as_ref(),
+                );
+                if !crypto::verify(pub_key, &message, signature) {
+                    error!(target: CHAIN_TARGET, "Transfer: signature verification failed");
+                    return Err(TransferError::BadSignature);
+                }
+            } else if !pub_keys.contains_key(sender) {
+                error!(target: CHAIN_TARGET, "Transfer: invalid sender address!");
+                return Err(TransferError::UnknownSender);
+            }
+
+            if !accounts.contains_key(sender) {
+                error!(target: CHAIN_TARGET, "Transfer: invalid sender address!");
+                return Err(TransferError::UnknownSender);
+            }
+            let new_receiver_key = match receiver_pub_key {
+                _ if accounts.contains_key(receiver) => None,
+                Some(pub_key)
+                    if policy.require_derived_addresses
+                        && *receiver != Account::derive_address(pub_key) =>
+                {
+                    error!(target: CHAIN_TARGET, "Transfer: receiver address is not derived from its key!");
+                    return Err(TransferError::AddressMismatch);
+                }
+                Some(pub_key) if policy.allow_implicit_accounts => Some(*pub_key),
+                Some(_) => {
+                    error!(target: CHAIN_TARGET, "Transfer: implicit accounts are not allowed!");
+                    return Err(TransferError::ImplicitAccountsNotAllowed);
+                }
+                None => {
+                    error!(target: CHAIN_TARGET, "Transfer: invalid receiver address!");
+                    return Err(TransferError::UnknownReceiver);
+                }
+            };
+
+            if *amount < policy.min_transfer {
+                error!(target: CHAIN_TARGET, "Transfer: amount is below the minimum!");
+                return Err(TransferError::BelowMinimum(policy.min_transfer));
+            }
+            if let Some(max_transfer) = policy.max_transfer.filter(|max| amount > max) {
+                error!(target: CHAIN_TARGET, "Transfer: amount is above the maximum!");
+                return Err(TransferError::AboveMaximum(max_transfer));
+            }
+            let total = match amount.checked_add(*fee) {
+                Some(total) => total,
+                None => {
+                    error!(target: CHAIN_TARGET, "Transfer: amount with fee overflows!");
+                    return Err(TransferError::AmountOverflow);
+                }
+            };
+            let receiver_balance = accounts.get(receiver).map_or(0, |to| to.balance);
+            if receiver_balance.checked_add(*amount).is_none() {
+                error!(target: CHAIN_TARGET, "Transfer to: receiver balance overflows!");
+                return Err(TransferError::ReceiverOverflow);
+            }
+            let from = accounts.get_mut(sender).expect("Sender exists.");
+            if from.nonce != *nonce {
+                error!(
+                    target: CHAIN_TARGET,
+                    "Transfer: expected nonce {}, got {}!", from.nonce, nonce
+                );
+                return Err(TransferError::WrongNonce {
+                    expected: from.nonce,
+                    got: *nonce,
+                });
+            }
+            if from.balance < total {
+                error!(target: CHAIN_TARGET, "Transfer from: insufficient balance!");
+                return Err(TransferError::InsufficientBalance);
+            }
+            // The fee is credited to the miner by the reward of the block.
+            from.balance -= total;
+            from.nonce += 1;
+            if let Some(pub_key) = new_receiver_key {
+                info!(target: CHAIN_TARGET, "Transfer: creating account {}", receiver);
+                pub_keys.insert(*receiver, pub_key);
+                accounts.insert(*receiver, Account::implicit(*receiver, pub_key));
+            }
+            let to = accounts.get_mut(receiver).expect("Receiver exists.");
+            to.balance += *amount;
+            return Ok(());
+        }
+
+        error!(target: CHAIN_TARGET, "Wrong transfer params!");
+        Err(TransferError::NotATransfer)
+    }
+
+    /// Compares the valid prefixes of the chains, so a chain with invalid blocks at its end is
+    /// not dropped as a whole. The chosen chain is cut to its valid prefix.
+    pub fn choose_chain(
+        &mut self,
+        mut local: Vec<Block>,
+        mut remote: Vec<Block>,
+    ) -> Result<Vec<Block>, ChainSelectionError> {
+        local.truncate(self.longest_valid_prefix(&local).len());
+        remote.truncate(self.longest_valid_prefix(&remote).len());
+
+        // A pruned chain starts at its checkpoint, so the chains are compared by height.
+        match (local.last(), remote.last()) {
+            (Some(local_tip), Some(remote_tip)) if remote_tip.id > local_tip.id => Ok(remote),
+            (Some(_), _) => Ok(local),
+            (None, Some(_)) => Ok(remote),
+            (None, None) => {
+                error!(target: SYNC_TARGET, "Local and remote chains both are invalid!");
+                Err(ChainSelectionError::BothInvalid)
+            }
+        }
+    }
+
+    /// Longest prefix of the chain with valid blocks and transfers, empty if the chain does not
+    /// start with the genesis block or the checkpoint.
+    pub fn longest_valid_prefix<'a>(&self, chain: &'a [Block]) -> &'a [Block] {
+        let mut len = self.first_invalid_block(chain).unwrap_or(chain.len());
+        if len > 0 {
+            if let Err(ReplayError::InvalidData(id)) = self.replay_blocks(&chain[..len]) {
+                len = usize::try_from(id - chain[0].id).map_or(len, |index| index.min(len));
+            }
+        }
+        &chain[..len]
+    }
+
+    pub fn import_chain(&mut self, chain: Vec<Block>) -> bool {
+        if !self.is_chain_valid(&chain) {
+            error!(target: SYNC_TARGET, "Import: imported chain is invalid, keeping local chain.");
+            return false;
+        }
+
+        let chosen = match self.choose_chain(self.blocks.clone(), chain) {
+            Ok(chosen) => chosen,
+            Err(_) => return false,
+        };
+        if chosen == self.blocks {
+            info!(
+                target: SYNC_TARGET,
+                "Import: imported chain is not longer than local one, keeping local chain.",
+            );
+            return false;
+        }
+
+        if !self.adopt_chain(chosen) {
+            error!(
+                target: SYNC_TARGET,
+                "Import: could not replay imported chain, keeping local chain.",
+            );
+            return false;
+        }
+        info!(target: SYNC_TARGET, "Import: adopted chain with {} blocks.", self.blocks.len());
+        true
+    }
+
+    pub fn adopt_chain(&mut self, chain: Vec<Block>) -> bool {
+        if let (Some(max_depth), Some(height), Some(new_height)) = (
+            self.max_reorg_depth,
+            self.height(),
+            chain.last().map(|block| block.id),
+        ) {
+            if new_height > height.saturating_add(max_depth) {
+                warn!(
+                    target: SYNC_TARGET,
+                    "Not adopting chain of height {}, more than {} blocks ahead of height {}",
+                    new_height,
+                    max_depth,
+                    height
+                );
+                return false;
+            }
+        }
+        match self.replay_chain(chain) {
+            Some(node) => {
+                if let Some(reorg) = self.detect_reorg(&node) {
+                    info!(
+                        target: SYNC_TARGET,
+                        "Reorg of depth {}: {} -> {}", reorg.depth, reorg.old_tip, reorg.new_tip
+                    );
+                    if self.reorgs.len() == MAX_REORG_HISTORY {
+                        self.reorgs.pop_front();
+                    }
+                    self.reorgs.push_back(reorg);
+                }
+                node.blocks[self.common_prefix_len(&node.blocks)..]
+                    .iter()
+                    .for_each(|block| self.publish_block(block));
+                self.blocks = node.blocks;
+                self.accounts = node.accounts;
+                self.pub_keys = node.pub_keys;
+                self.connect_orphans();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn metrics(&self) -> NodeMetrics {
+        let last_block = self.tip();
+        NodeMetrics {
+            height: self.height().unwrap_or_default(),
+            accounts: self.accounts.len(),
+            mempool: self.mempool.len(),
+            difficulty: self.compute_difficulty(),
+            last_block_timestamp: last_block.map(|block| block.timestamp),
+            total_transactions: self.total_transactions(),
+            average_tps: self.average_tps(),
+            peers: 0,
+        }
+    }
+
+    /// Transfers in the blocks of the chain, only the retained ones if it is pruned.
+    pub fn total_transactions(&self) -> u64 {
+        self.blocks
+            .iter()
+            .map(|block| count_transfers(&block.data))
+            .sum()
+    }
+
+    /// Transfers a second between the first and the last block, 0 until they are a second apart.
+    pub fn average_tps(&self) -> f64 {
+        let elapsed = match (self.blocks.first(), self.blocks.last()) {
+            (Some(first), Some(last)) => last.timestamp - first.timestamp,
+            _ => return 0.0,
+        };
+        if elapsed <= 0 {
+            return 0.0;
+        }
+        self.total_transactions() as f64 / elapsed as f64
+    }
+
+    /// Sum of the balances, kept by transfers as the fees go to the miner. Grows only by the
+    /// rewards of the mined blocks.
+    pub fn total_supply(&self) -> u128 {
+        self.accounts
+            .values()
+            .map(|account| u128::from(account.balance))
+            .sum()
+    }
+
+    pub fn balance_of(&self, address: Address) -> Option<u64> {
+        self.accounts.get(&address).map(|account| account.balance)
+    }
+
+    pub fn snapshot(&self) -> NodeSnapshot {
+        NodeSnapshot {
+            height: self.height(),
+            tip_hash: self.tip().map(|block| block.hash.clone()),
+            balances: Arc::new(
+                self.accounts
+                    .iter()
+                    .map(|(&address, account)| (address, account.balance))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Balance as of `confirmations` blocks below the tip, which a reorg of fewer blocks cannot
+    /// change. `None` if the chain is not that long or the block was pruned.
+    pub fn confirmed_balance_of(&self, address: Address, confirmations: u64) -> Option<u64> {
+        if confirmations == 0 {
+            return self.balance_of(address);
+        }
+        let height = self.height()?.checked_sub(confirmations)?;
+        match self.state_at(height) {
+            Ok(accounts) => accounts.get(&address).map(|account| account.balance),
+            Err(ReplayError::UnknownHeight(_)) => None,
+            Err(e) => {
+                error!(target: CHAIN_TARGET, "Chain state is inconsistent, {}", e);
+                None
+            }
+        }
+    }
+
+    /// Blocks with transfers sent or received by the address, oldest first.
+    pub fn history_of(&self, address: Address) -> Vec<&Block> {
+        self.blocks
+            .iter()
+            .filter(|block| has_transfer_of(&block.data, address))
+            .collect()
+    }
+
+    pub fn recent_reorgs(&self) -> &VecDeque<Reorg> {
+        &self.reorgs
+    }
+
+    fn detect_reorg(&self, adopted: &Node) -> Option<Reorg> {
+        let old_tip = self.tip()?;
+        let new_tip = adopted.tip()?;
+        let common_blocks = self.common_prefix_len(&adopted.blocks);
+        let ancestor = self.blocks.get(common_blocks.checked_sub(1)?)?;
+
+        if ancestor.id >= old_tip.id {
+            return None;
+        }
+
+        let addresses: HashSet<&Address> = self
+            .accounts
+            .keys()
+            .chain(adopted.accounts.keys())
+            .collect();
+        let mut affected_accounts: Vec<Address> = addresses
+            .into_iter()
+            .filter(|address| self.accounts.get(address) != adopted.accounts.get(address))
+            .copied()
+            .collect();
+        affected_accounts.sort_unstable();
+
+        Some(Reorg {
+            timestamp: Utc::now().timestamp(),
+            old_tip: old_tip.hash.clone(),
+            new_tip: new_tip.hash.clone(),
+            depth: old_tip.id - ancestor.id,
+            affected_accounts,
+        })
+    }
+
+    /// Rebuilds the accounts from the data of the blocks, starting from an empty state, or from
+    /// the checkpoint if the chain is pruned.
+    pub fn replay(&self) -> Result<HashMap<Address, Account>, ReplayError> {
+        self.replay_blocks(&self.blocks)
+    }
+
+    /// Accounts as they were after the block at the height, replayed from the genesis block or the
+    /// checkpoint. The states of recently queried blocks are cached by their hash, so a reorg
+    /// cannot return a stale one.
+    pub fn state_at(&self, height: u64) -> Result<HashMap<Address, Account>, ReplayError> {
+        let first = self.blocks.first().ok_or(ReplayError::EmptyChain)?.id;
+        let index = height
+            .checked_sub(first)
+            .and_then(|index| usize::try_from(index).ok())
+            .filter(|index| *index < self.blocks.len())
+            .ok_or(ReplayError::UnknownHeight(height))?;
+        let hash = &self.blocks[index].hash;
+
+        let mut cache = self
+            .state_cache
+            .lock()
+            .expect("State cache lock is not poisoned.");
+        if let Some((_, accounts)) = cache.iter().find(|(cached, _)| cached == hash) {
+            return Ok(accounts.clone());
+        }
+        let accounts = self.replay_blocks(&self.blocks[..=index])?;
+        if cache.len() == STATE_CACHE_CAPACITY {
+            cache.pop_front();
+        }
+        cache.push_back((hash.clone(), accounts.clone()));
+        Ok(accounts)
+    }
+
+    fn replay_blocks(&self, blocks: &[Block]) -> Result<HashMap<Address, Account>, ReplayError> {
+        if blocks.is_empty() {
+            return Err(ReplayError::EmptyChain);
+        }
+        let (mut accounts, applied) = match &self.checkpoint {
+            Some(checkpoint) if blocks[0] == checkpoint.block => (checkpoint.accounts.clone(), 1),
+            _ => (HashMap::new(), 0),
+        };
+        let mut pub_keys = accounts
+            .values()
+            .map(|account| (account.address, account.pub_key))
+            .collect();
+        for block in &blocks[applied..] {
+            let policy = TransferPolicy {
+                genesis: block.id == 0,
+                ..self.transfer_policy()
+            };
+            Self::stage_data(&block.data, &mut accounts, &mut pub_keys, policy)
+                .map_err(|_| ReplayError::InvalidData(block.id))?;
+        }
+        Ok(accounts)
+    }
+
+    /// Removes the latest block and restores the accounts from before it, by replaying the rest of
+    /// the chain. The genesis block, or the checkpoint of a pruned chain, is never removed.
+    pub fn rollback_last_block(&mut self) -> Option<Block> {
+        if self.blocks.len() <= 1 {
+            info!(target: CHAIN_TARGET, "Not rolling back, no block after the genesis block");
+            return None;
+        }
+        let accounts = match self.replay_blocks(&self.blocks[..self.blocks.len() - 1]) {
+            Ok(accounts) => accounts,
+            Err(e) => {
+                error!(target: CHAIN_TARGET, "Not rolling back, chain state is inconsistent, {}", e);
+                return None;
+            }
+        };
+        self.pub_keys = accounts
+            .values()
+            .map(|account| (account.address, account.pub_key))
+            .collect();
+        self.accounts = accounts;
+        let block = self.blocks.pop()?;
+        info!(target: CHAIN_TARGET, "Rolled back block {}", block.id);
+        Some(block)
+    }
+
+    /// Drops the blocks below `height`, keeping a snapshot of the accounts at the first retained
+    /// block which becomes the checkpoint. The height is rounded down to a retargeting window, so
+    /// the difficulty of later blocks can be computed without the dropped ones. Returns the height
+    /// of the checkpoint, `None` if no block was dropped.
+    pub fn prune_below(&mut self, height: u64) -> Option<u64> {
+        let first = self.blocks.first()?.id;
+        let height = height.min(self.height()?);
+        let height = height - height % RETARGET_WINDOW as u64;
+        if height <= first {
+            return None;
+        }
+
+        let index = usize::try_from(height - first).ok()?;
+        let difficulty = self.next_difficulty(&self.blocks[..=index]);
+        let accounts = match self.replay_blocks(&self.blocks[..=index]) {
+            Ok(accounts) => accounts,
+            Err(e) => {
+                error!(target: CHAIN_TARGET, "Not pruning, chain state is inconsistent, {}", e);
+                return None;
+            }
+        };
+        self.blocks.drain(..index);
+        self.checkpoint = Some(Checkpoint {
+            block: self.blocks[0].clone(),
+            difficulty,
+            accounts,
+        });
+        info!(
+            target: CHAIN_TARGET,
+            "Pruned {} blocks, checkpoint at height {}", index, height
+        );
+        Some(height)
+    }
+
+    pub fn checkpoint(&self) -> Option<&Checkpoint> {
+        self.checkpoint.as_ref()
+    }
+
+    /// Number of leading blocks the chain shares with the local one.
+    fn common_prefix_len(&self, chain: &[Block]) -> usize {
+        self.blocks
+            .iter()
+            .zip(chain)
+            .take_while(|(local, other)| local.hash == other.hash)
+            .count()
+    }
+
+    /// Rolls the state back to the common ancestor with the chain, then adds its later blocks.
+    pub(crate) fn replay_chain(&self, mut chain: Vec<Block>) -> Option<Node> {
+        if !self.is_chain_valid(&chain) {
+            return None;
+        }
+        // Only the state from the checkpoint on is known, so a full chain is cut there.
+        if let Some(checkpoint) = &self.checkpoint {
+            match chain.iter().position(|block| *block == checkpoint.block) {
+                Some(start) => {
+                    chain.drain(..start);
+                }
+                None => {
+                    warn!(target: SYNC_TARGET, "Chain does not contain the checkpoint block.");
+                    return None;
+                }
+            }
+        }
+
+        let mut node = self.with_same_settings();
+        // The first block was checked above, so there is always a common ancestor.
+        let ancestor = self.common_prefix_len(&chain).max(1);
+        let mut blocks = chain.into_iter();
+        node.blocks = blocks.by_ref().take(ancestor).collect();
+        if let Err(e) = node.restore_accounts() {
+            error!(target: SYNC_TARGET, "Chain state is inconsistent, {}", e);
+            return None;
+        }
+
+        for block in blocks {
+            if let Err(e) = node.try_add_block(block) {
+                error!(target: SYNC_TARGET, "Chain state is inconsistent, {}", e);
+                return None;
+            }
+        }
+        Some(node)
+    }
+
+    /// Replaces the accounts and keys of the node with the ones replayed from its blocks.
+    fn restore_accounts(&mut self) -> Result<(), ReplayError> {
+        let accounts = self.replay()?;
+        self.pub_keys = accounts
+            .values()
+            .map(|account| (account.address, account.pub_key))
+            .collect();
+        self.accounts = accounts;
+        Ok(())
+    }
+
+    /// Node without blocks, with the settings, genesis block and checkpoint of this one.
+    pub(crate) fn with_same_settings(&self) -> Node {
+        let mut node = Node::with_difficulty(self.difficulty);
+        node.target_block_time_secs = self.target_block_time_secs;
+        node.miner = self.miner.clone();
+        node.block_reward = self.block_reward;
+        node.halving_interval = self.halving_interval;
+        node.max_block_bytes = self.max_block_bytes;
+        node.max_txs_per_block = self.max_txs_per_block;
+        node.max_future_drift_secs = self.max_future_drift_secs;
+        node.max_orphans = self.max_orphans;
+        node.allow_implicit_accounts = self.allow_implicit_accounts;
+        node.require_derived_addresses = self.require_derived_addresses;
+        node.min_transfer = self.min_transfer;
+        node.max_transfer = self.max_transfer;
+        node.mining_enabled = self.mining_enabled;
+        node.mining_log_interval = self.mining_log_interval;
+        node.max_reorg_depth = self.max_reorg_depth;
+        node.chain_id = self.chain_id;
+        node.hasher = self.hasher;
+        node.policy = self.policy.clone();
+        node.genesis_block = self.genesis_block.clone();
+        node.checkpoint = self.checkpoint.clone();
+        node
+    }
+
+    /// Validates the chain with the genesis block and settings of this node, by adding its blocks
+    /// to a node without any.
+    pub fn validate_chain(&self, chain: &[Block]) -> ChainReport {
+        let mut report = ChainReport {
+            total_blocks: chain.len(),
+            total_accounts: 0,
+            invalid_block: None,
+            balances_consistent: false,
+        };
+
+        let mut node = self.with_same_settings();
+        if chain.first() != Some(&node.genesis_block) {
+            warn!(target: CHAIN_TARGET, "Chain does not start with the genesis block.");
+            report.invalid_block = Some(0);
+            return report;
+        }
+
+        node.blocks.push(node.genesis_block.clone());
+        if let Err(e) = node.restore_accounts() {
+            warn!(target: CHAIN_TARGET, "Genesis block is inconsistent, {}", e);
+            report.invalid_block = Some(0);
+            return report;
+        }
+        for (index, block) in chain.iter().enumerate().skip(1) {
+            if node.try_add_block(block.clone()).is_err() {
+                report.invalid_block = Some(index);
+                break;
+            }
+        }
+        report.total_accounts = node.accounts.len();
+        // Blocks are added with their transfers, so the balances are right once all are added.
+        report.balances_consistent = report.invalid_block.is_none();
+        report
+    }
+
+    /// Checks the local chain and replays it against the accounts of the node, e.g. after a crash
+    /// or a manual edit of the chain file.
+    pub fn verify(&self) -> ChainReport {
+        let mut report = ChainReport {
+            total_blocks: self.blocks.len(),
+            total_accounts: self.accounts.len(),
+            invalid_block: self.first_invalid_block(&self.blocks),
+            balances_consistent: false,
+        };
+        match self.replay() {
+            Ok(accounts) => report.balances_consistent = accounts == self.accounts,
+            Err(ReplayError::EmptyChain | ReplayError::UnknownHeight(_)) => {
+                report.invalid_block = Some(0)
+            }
+            Err(ReplayError::InvalidData(id)) => {
+                // A pruned chain starts at its checkpoint, so the id is past the index.
+                let offset = id.saturating_sub(self.blocks[0].id);
+                let index = usize::try_from(offset).unwrap_or(usize::MAX);
+                report.invalid_block = Some(report.invalid_block.map_or(index, |i| i.min(index)));
+            }
+        }
+        report
+    }
+
+    /// Panics before the genesis block is added, see `tip` for a fallible variant.
+    pub fn get_last_block(&self) -> &Block {
+        self.tip().expect("There is at least one block")
+    }
+
+    /// Latest block of the chain, `None` before the genesis block is added.
+    pub fn tip(&self) -> Option<&Block> {
+        self.blocks.last()
+    }
+
+    /// Id of the latest block, `None` before the genesis block is added.
+    pub fn height(&self) -> Option<u64> {
+        self.tip().map(|block| block.id)
+    }
+
+    /// Whether the block is already in the chain, at its height.
+    pub fn has_block(&self, block: &Block) -> bool {
+        self.get_block_by_id(block.id)
+            .is_some_and(|known| known.hash == block.hash)
+    }
+
+    /// Block ids are their heights in the chain, pruned blocks are not found.
+    pub fn get_block_by_id(&self, id: u64) -> Option<&Block> {
+        let first = self.blocks.first()?.id;
+        usize::try_from(id.checked_sub(first)?)
+            .ok()
+            .and_then(|index| self.blocks.get(index))
+    }
+
+    /// Scans the chain, `blocks` is replaced as a whole on reorgs so an index could go stale.
+    pub fn get_block_by_hash(&self, hash: &str) -> Option<&Block> {
+        self.blocks.iter().find(|block| block.hash == hash)
+    }
+
+    /// Difficulty required for the next block of the local chain.
+    pub fn compute_difficulty(&self) -> usize {
+        self.next_difficulty(&self.blocks)
+    }
+
+    /// Starts at the node difficulty and is retargeted every `RETARGET_WINDOW` blocks,
+    /// the genesis block is left out as its timestamp is arbitrary. A chain starting at the
+    /// checkpoint continues from the difficulty retargeted over the pruned blocks.
+    fn next_difficulty(&self, chain: &[Block]) -> usize {
+        let (mut difficulty, first) = self.initial_difficulty(chain);
+        // The checkpoint is at a window boundary, so the later windows are all retained.
+        let retargets = (2 * RETARGET_WINDOW..=first + chain.len())
+            .step_by(RETARGET_WINDOW)
+            .filter(|height| *height > first);
+        for height in retargets {
+            difficulty = self.retarget(
+                difficulty,
+                &chain[height - RETARGET_WINDOW - first..height - first],
+            );
+        }
+        difficulty
+    }
+
+    /// Difficulty the chain starts at and the height of its first block.
+    fn initial_difficulty(&self, chain: &[Block]) -> (usize, usize) {
+        match &self.checkpoint {
+            Some(checkpoint) if chain.first() == Some(&checkpoint.block) => {
+                (checkpoint.difficulty, checkpoint.block.id as usize)
+            }
+            _ => (self.difficulty, 0),
+        }
+    }
+
+    /// Difficulty after the window of `RETARGET_WINDOW` blocks, raised if they were mined in less
+    /// than half of the target time and lowered if in more than twice of it.
+    fn retarget(&self, difficulty: usize, window: &[Block]) -> usize {
+        let elapsed = window[RETARGET_WINDOW - 1].timestamp - window[0].timestamp;
+        let expected = self.target_block_time_secs * (RETARGET_WINDOW as i64 - 1);
+
+        if elapsed * 2 < expected {
+            difficulty + 1
+        } else if elapsed > expected * 2 && difficulty > 1 {
+            difficulty - 1
+        } else {
+            difficulty
+        }
+    }
+
+    pub fn is_chain_valid(&self, chain: &[Block]) -> bool {
+        self.first_invalid_block(chain).is_none()
+    }
+
+    /// Index of the first block of the chain failing validation, without applying its transfers.
+    fn first_invalid_block(&self, chain: &[Block]) -> Option<usize> {
+        self.first_invalid_block_from(chain, 1)
+    }
+
+    /// Like `first_invalid_block`, but the blocks before `start` are known to be valid, e.g. the
+    /// chunks of a chain validated as they were fetched.
+    pub fn first_invalid_block_from(&self, chain: &[Block], start: usize) -> Option<usize> {
+        let is_checkpoint = |block: &Block| {
+            self.checkpoint
+                .as_ref()
+                .is_some_and(|checkpoint| checkpoint.block == *block)
+        };
+        match chain.first() {
+            Some(first) if *first == self.genesis_block || is_checkpoint(first) => {}
+            _ => {
+                warn!(
+                    target: CHAIN_TARGET,
+                    "Chain does not start with the genesis block or the checkpoint."
+                );
+                return Some(0);
+            }
+        }
+        // The difficulty is carried forward and retargeted at the window boundaries, as
+        // `next_difficulty` would for each prefix of the chain.
+        let start = start.max(1);
+        let (_, first_height) = self.initial_difficulty(chain);
+        let mut difficulty = self.next_difficulty(&chain[..start.min(chain.len())]);
+        for (index, pair) in chain.windows(2).enumerate().skip(start - 1) {
+            let (previous_block, block) = (&pair[0], &pair[1]);
+            let index = index + 1;
+            let height = first_height + index;
+            if index > start && height >= 2 * RETARGET_WINDOW && height % RETARGET_WINDOW == 0 {
+                difficulty = self.retarget(difficulty, &chain[index - RETARGET_WINDOW..index]);
+            }
+            if self
+                .is_block_valid(block, previous_block, difficulty)
+                .is_err()
+            {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    fn is_block_valid(
+        &self,
+        block: &Block,
+        previous_block: &Block,
+        difficulty: usize,
+    ) -> Result<(), BlockError> {
+        let rules = BlockRules {
+            difficulty,
+            chain_id: self.chain_id,
+            max_block_bytes: self.max_block_bytes,
+            block_reward: self.block_reward_at(block.id),
+            max_timestamp: Some(Utc::now().timestamp() + self.max_future_drift_secs),
+        };
+        crate::core::is_block_valid(block, previous_block, &rules, self.hasher)
+    }
+}
+
+impl Block {
+    /// Checks of the block which do not need the chain before it: the proof of work, the hash
+    /// and the timestamp not being too far ahead of the clock.
+    pub fn validate_self(
+        &self,
+        difficulty: usize,
+        hasher: &dyn Hasher,
+        chain_id: u64,
+        max_future_drift_secs: i64,
+    ) -> Result<(), BlockError> {
+        let rules = BlockRules {
+            difficulty,
+            chain_id,
+            max_timestamp: Some(Utc::now().timestamp() + max_future_drift_secs),
+            ..BlockRules::default()
+        };
+        self.validate_header(&rules, hasher)
+    }
+
+    /// Blocks without a miner are mined at the version before `Block::miner` existed.
+    fn version_mined_by(miner: Option<&str>) -> u32 {
+        match miner {
+            Some(_) => BLOCK_VERSION,
+            None => CANONICAL_BLOCK_VERSION,
+        }
+    }
+
+    /// Mines the block with SHA-256, the hash function of the default network.
+    pub fn new(id: u64, previous_hash: String, data: Data, difficulty: usize) -> Self {
+        Self::new_with_hasher(id, previous_hash, data, difficulty, &Sha256Hasher, 0)
+    }
+
+    pub fn new_with_hasher(
+        id: u64,
+        previous_hash: String,
+        data: Data,
+        difficulty: usize,
+        hasher: &dyn Hasher,
+        chain_id: u64,
+    ) -> Self {
+        Self::try_new(
+            id,
+            previous_hash,
+            data,
+            difficulty,
+            u64::MAX,
+            1,
+            DEFAULT_MINING_LOG_INTERVAL,
+            &AtomicBool::new(false),
+            hasher,
+            chain_id,
+            None,
+        )
+        .expect("Can mine within nonce space.")
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn try_new(
+        id: u64,
+        previous_hash: String,
+        data: Data,
+        difficulty: usize,
+        max_nonce: u64,
+        threads: usize,
+        log_interval: u64,
+        cancel: &AtomicBool,
+        hasher: &dyn Hasher,
+        chain_id: u64,
+        miner: Option<String>,
+    ) -> Result<Self, MiningError> {
+        let mine_at = |timestamp| {
+            Block::mine_block_parallel(
+                hasher,
+                chain_id,
+                miner.as_deref(),
+                id,
+                timestamp,
+                &previous_hash,
+                &data,
+                difficulty,
+                max_nonce,
+                threads,
+                log_interval,
+                cancel,
+            )
+        };
+        let now = || Utc::now().timestamp();
+        // Only a search of the whole nonce space moves on to the next timestamp, a smaller
+        // budget is a limit on the work.
+        let (timestamp, (nonce, hash)) = if max_nonce == u64::MAX {
+            mine_rolling_timestamp(now(), now, mine_at)?
+        } else {
+            let timestamp = now();
+            (timestamp, mine_at(timestamp)?)
+        };
+        Ok(Self {
+            version: Block::version_mined_by(miner.as_deref()),
+            id,
+            hash,
+            previous_hash,
+            timestamp,
+            merkle_root: Block::merkle_root(&data, hasher),
+            data,
+            nonce,
+            miner,
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn mine_block(
+        hasher: &dyn Hasher,
+        chain_id: u64,
+        version: u32,
+        miner: Option<&str>,
+        id: u64,
+        timestamp: i64,
+        previous_hash: &str,
+        data: &Data,
+        difficulty: usize,
+        max_nonce: u64,
+        log_interval: u64,
+        cancel: &AtomicBool,
+    ) -> Result<(u64, String), MiningError> {
+        Block::mine_block_until(
+            hasher,
+            chain_id,
+            version,
+            miner,
+            id,
+            timestamp,
+            previous_hash,
+            data,
+            difficulty,
+            max_nonce,
+            log_interval,
+            || cancel.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Mines like `mine_block`, but gives up once `deadline` has passed.
+    #[allow(clippy::too_many_arguments)]
+    fn mine_block_with_deadline(
+        hasher: &dyn Hasher,
+        chain_id: u64,
+        version: u32,
+        miner: Option<&str>,
+        id: u64,
+        timestamp: i64,
+        previous_hash: &str,
+        data: &Data,
+        difficulty: usize,
+        deadline: Duration,
+        log_interval: u64,
+    ) -> Option<(u64, String)> {
+        let started = Instant::now();
+        let result = Block::mine_block_until(
+            hasher,
+            chain_id,
+            version,
+            miner,
+            id,
+            timestamp,
+            previous_hash,
+            data,
+            difficulty,
+            u64::MAX,
+            log_interval,
+            || started.elapsed() >= deadline,
+        );
+        if result == Err(MiningError::Cancelled) {
+            warn!(target: MINING_TARGET, "Gave up mining after deadline: {:?}", deadline);
+        }
+        result.ok()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn mine_block_until(
+        hasher: &dyn Hasher,
+        chain_id: u64,
+        version: u32,
+        miner: Option<&str>,
+        id: u64,
+        timestamp: i64,
+        previous_hash: &str,
+        data: &Data,
+        difficulty: usize,
+        max_nonce: u64,
+        log_interval: u64,
+        should_stop: impl Fn() -> bool,
+    ) -> Result<(u64, String), MiningError> {
+        info!(target: MINING_TARGET, "Mining block ...");
+        let merkle_root = if version >= MERKLE_BLOCK_VERSION {
+            Block::merkle_root(data, hasher)
+        } else {
+            String::new()
+        };
+
+        let result = search_nonces(
+            |nonce| {
+                calculate_hash(
+                    hasher,
+                    chain_id,
+                    version,
+                    id,
+                    timestamp,
+                    previous_hash,
+                    data,
+                    &merkle_root,
+                    nonce,
+                    miner,
+                )
+            },
+            difficulty,
+            log_interval,
+            0..=max_nonce,
+            should_stop,
+        );
+        if result == Err(MiningError::Exhausted) {
+            warn!(
+                target: MINING_TARGET,
+                "Gave up mining after exhausting nonce budget: {}", max_nonce
+            );
+        }
+        result
+    }
+
+    /// Mines with the nonce space split between `threads` workers, the first solution found wins
+    /// and stops the other workers.
+    #[allow(clippy::too_many_arguments)]
+    pub fn mine_block_parallel(
+        hasher: &dyn Hasher,
+        chain_id: u64,
+        miner: Option<&str>,
+        id: u64,
+        timestamp: i64,
+        previous_hash: &str,
+        data: &Data,
+        difficulty: usize,
+        max_nonce: u64,
+        threads: usize,
+        log_interval: u64,
+        cancel: &AtomicBool,
+    ) -> Result<(u64, String), MiningError> {
+        let version = Block::version_mined_by(miner);
+        if threads <= 1 {
+            return Block::mine_block(
+                hasher,
+                chain_id,
+                version,
+                miner,
+                id,
+                timestamp,
+                previous_hash,
+                data,
+                difficulty,
+                max_nonce,
+                log_interval,
+                cancel,
+            );
+        }
+
+        info!(target: MINING_TARGET, "Mining block with {} threads ...", threads);
+        let merkle_root = Block::merkle_root(data, hasher);
+        let found = AtomicBool::new(false);
+        let solution = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads as u64)
+                .map(|first_nonce| {
+                    let (merkle_root, found) = (&merkle_root, &found);
+                    scope.spawn(move || {
+                        let result = search_nonces(
+                            |nonce| {
+                                calculate_hash(
+                                    hasher,
+                                    chain_id,
+                                    version,
+                                    id,
+                                    timestamp,
+                                    previous_hash,
+                                    data,
+                                    merkle_root,
+                                    nonce,
+                                    miner,
+                                )
+                            },
+                            difficulty,
+                            log_interval,
+                            (first_nonce..=max_nonce).step_by(threads),
+                            || found.load(Ordering::Relaxed) || cancel.load(Ordering::Relaxed),
+                        );
+                        if result.is_ok() {
+                            found.store(true, Ordering::Relaxed);
+                        }
+                        result
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .filter_map(|worker| worker.join().expect("Mining thread does not panic.").ok())
+                .next()
+        });
+
+        match solution {
+            Some(solution) => Ok(solution),
+            None if cancel.load(Ordering::Relaxed) => Err(MiningError::Cancelled),
+            None => {
+                warn!(
+                    target: MINING_TARGET,
+                    "Gave up mining after exhausting nonce budget: {}", max_nonce
+                );
+                Err(MiningError::Exhausted)
+            }
+        }
+    }
+}
+
+impl Default for GenesisConfig {
+    fn default() -> Self {
+        Self {
+            timestamp: 1665411300,
+            difficulty: DEFAULT_DIFFICULTY,
+            accounts: vec![GENESIS_ACCOUNT],
+        }
+    }
+}
+
+impl GenesisConfig {
+    /// Sum of the genesis balances, `None` if it overflows `u64`.
+    pub fn total_supply(&self) -> Option<u64> {
+        self.accounts
+            .iter()
+            .try_fold(0u64, |total, account| total.checked_add(account.balance))
+    }
+
+    /// Checks the accounts can start a chain: there is one at least, their addresses are unique
+    /// and the balances do not overflow the total supply.
+    pub fn validate(&self) -> Result<(), GenesisError> {
+        if self.accounts.is_empty() {
+            return Err(GenesisError::NoAccounts);
+        }
+        let mut addresses = HashSet::new();
+        if let Some(account) = self
+            .accounts
+            .iter()
+            .find(|account| !addresses.insert(account.address))
+        {
+            return Err(GenesisError::DuplicateAddress(account.address));
+        }
+        match self.total_supply() {
+            Some(_) => Ok(()),
+            None => Err(GenesisError::SupplyOverflow),
+        }
+    }
+
+    /// Mines the genesis block, deterministically as the nonces are tried in order on one thread.
+    pub fn block(&self, hasher: &dyn Hasher) -> Block {
+        if *self == GenesisConfig::default() {
+            return Node::default_genesis_block(hasher);
+        }
+
+        let data = Data::Batch(self.accounts.iter().cloned().map(Data::Account).collect());
+        let previous_hash = String::from("genesis");
+        // The genesis block does not depend on the chain id, the blocks after it do.
+        let (nonce, hash) = Block::mine_block(
+            hasher,
+            0,
+            CANONICAL_BLOCK_VERSION,
+            None,
+            0,
+            self.timestamp,
+            &previous_hash,
+            &data,
+            self.difficulty,
+            u64::MAX,
+            DEFAULT_MINING_LOG_INTERVAL,
+            &AtomicBool::new(false),
+        )
+        .expect("Can mine within nonce space.");
+        Block {
+            version: CANONICAL_BLOCK_VERSION,
+            id: 0,
+            hash,
+            previous_hash,
+            timestamp: self.timestamp,
+            merkle_root: Block::merkle_root(&data, hasher),
+            data,
+            nonce,
+            miner: None,
+        }
+    }
+}
+
+impl MiningProgress {
+    /// A hash meets the difficulty with a chance of 2^-difficulty, as it has to start with that
+    /// many zero bits.
+    pub fn expected_attempts(&self) -> f64 {
+        2f64.powi(self.difficulty as i32)
+    }
+
+    /// Share of the expected attempts made, over 100 for an unlucky search.
+    pub fn percent(&self) -> f64 {
+        self.attempts as f64 / self.expected_attempts() * 100.0
+    }
+
+    /// Hashes a second, 0 before any time has elapsed.
+    pub fn hash_rate(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.attempts as f64 / secs,
+            _ => 0.0,
+        }
+    }
+
+    /// Time to make the rest of the expected attempts at the current rate, `None` before the rate
+    /// is known or once they are made.
+    pub fn eta(&self) -> Option<Duration> {
+        let (rate, remaining) = (
+            self.hash_rate(),
+            self.expected_attempts() - self.attempts as f64,
+        );
+        if rate > 0.0 && remaining > 0.0 {
+            Duration::try_from_secs_f64(remaining / rate).ok()
+        } else {
+            None
+        }
+    }
+}
+
+impl ChainReport {
+    pub fn is_valid(&self) -> bool {
+        self.invalid_block.is_none() && self.balances_consistent
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        if self.is_valid() {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+impl fmt::Display for ChainReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Total blocks: {}", self.total_blocks)?;
+        writeln!(f, "Total accounts: {}", self.total_accounts)?;
+        if self.balances_consistent {
+            writeln!(f, "Balances are consistent with the chain")?;
+        } else {
+            writeln!(f, "Balances are inconsistent with the chain")?;
+        }
+        match self.invalid_block {
+            Some(index) => write!(
+                f,
+                "Chain is invalid, first invalid block at index: {}",
+                index
+            ),
+            None => write!(f, "Chain is valid"),
+        }
+    }
+}
+
+impl fmt::Display for MiningError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MiningError::Exhausted => write!(f, "nonce budget exhausted"),
+            MiningError::Cancelled => write!(f, "mining cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for MiningError {}
+
+impl fmt::Display for ChainSelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChainSelectionError::BothInvalid => {
+                write!(f, "local and remote chains are both invalid")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChainSelectionError {}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::EmptyChain => write!(f, "chain has no blocks"),
+            ReplayError::InvalidData(id) => {
+                write!(f, "data of block {} does not apply to the state", id)
+            }
+            ReplayError::UnknownHeight(height) => write!(f, "no block at height {}", height),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl fmt::Display for GenesisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GenesisError::NoAccounts => write!(f, "genesis has no accounts"),
+            GenesisError::DuplicateAddress(address) => {
+                write!(f, "genesis account {} is not unique", address)
+            }
+            GenesisError::SupplyOverflow => write!(f, "genesis balances overflow the total supply"),
+        }
+    }
+}
+
+impl std::error::Error for GenesisError {}
+
+impl fmt::Display for MiningProgress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.1}% of the {:.0} expected attempts, {:.0} hashes/s",
+            self.percent(),
+            self.expected_attempts(),
+            self.hash_rate()
+        )?;
+        match self.eta() {
+            Some(eta) => write!(f, ", about {}s left", eta.as_secs()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Account {
+    pub fn new() -> (Self, PrivateKey) {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let account = Self {
+            address: Account::derive_address(&pub_key),
+            balance: INIT_BALANCE,
+            pub_key,
+            nonce: 0,
+        };
+        (account, private_key)
+    }
+
+    /// First 8 bytes of the SHA-256 hash of the key, so only the holder of the key can register
+    /// the address. See `Node::require_derived_addresses`.
+    pub fn derive_address(pub_key: &PublicKey) -> Address {
+        let hash = Sha256Hasher.digest(&pub_key.0);
+        Address::from_be_bytes(hash[..8].try_into().expect("Hash has 32 bytes."))
+    }
+
+    fn implicit(address: Address, pub_key: PublicKey) -> Self {
+        Self {
+            address,
+            balance: INIT_BALANCE,
+            pub_key,
+            nonce: 0,
+        }
+    }
+}
+
+impl Data {
+    /// Transfer to an existing account, the signature has to cover all the other fields.
+    pub fn transfer(
+        sender: Address,
+        receiver: Address,
+        amount: u64,
+        fee: u64,
+        nonce: u64,
+        signature: Signature,
+    ) -> Self {
+        Data::Transfer {
+            sender,
+            receiver,
+            amount,
+            fee,
+            nonce,
+            signature,
+            receiver_pub_key: None,
+        }
+    }
+
+    pub fn sender(&self) -> Option<Address> {
+        match self {
+            Data::Transfer { sender, .. } => Some(*sender),
+            _ => None,
+        }
+    }
+
+    pub fn receiver(&self) -> Option<Address> {
+        match self {
+            Data::Transfer { receiver, .. } => Some(*receiver),
+            _ => None,
+        }
+    }
+
+    pub fn amount(&self) -> Option<u64> {
+        match self {
+            Data::Transfer { amount, .. } => Some(*amount),
+            _ => None,
+        }
+    }
+
+    pub fn fee(&self) -> Option<u64> {
+        match self {
+            Data::Transfer { fee, .. } => Some(*fee),
+            _ => None,
+        }
+    }
+
+    pub fn nonce(&self) -> Option<u64> {
+        match self {
+            Data::Transfer { nonce, .. } => Some(*nonce),
+            _ => None,
+        }
+    }
+
+    pub fn signature(&self) -> Option<&Signature> {
+        match self {
+            Data::Transfer { signature, .. } => Some(signature),
+            _ => None,
+        }
+    }
+}
+
+pub fn sign_transfer(
+    private_key: &PrivateKey,
+    sender: Address,
+    receiver: Address,
+    amount: u64,
+    fee: u64,
+    nonce: u64,
+) -> Data {
+    sign_transfer_on_chain(0, private_key, sender, receiver, amount, fee, nonce)
+}
+
+/// Signs a transfer which is only valid on the nodes with the chain id.
+pub fn sign_transfer_on_chain(
+    chain_id: u64,
+    private_key: &PrivateKey,
+    sender: Address,
+    receiver: Address,
+    amount: u64,
+    fee: u64,
+    nonce: u64,
+) -> Data {
+    sign_transfer_with_key(
+        chain_id,
+        private_key,
+        sender,
+        receiver,
+        None,
+        amount,
+        fee,
+        nonce,
+    )
+}
+
+/// Signs a transfer which creates the receiver account with the key if it does not exist, it is
+/// only valid on the nodes with the chain id.
+#[allow(clippy::too_many_arguments)]
+pub fn sign_transfer_to_new_account(
+    chain_id: u64,
+    private_key: &PrivateKey,
+    sender: Address,
+    receiver: Address,
+    receiver_pub_key: PublicKey,
+    amount: u64,
+    fee: u64,
+    nonce: u64,
+) -> Data {
+    sign_transfer_with_key(
+        chain_id,
+        private_key,
+        sender,
+        receiver,
+        Some(receiver_pub_key),
+        amount,
+        fee,
+        nonce,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn sign_transfer_with_key(
+    chain_id: u64,
+    private_key: &PrivateKey,
+    sender: Address,
+    receiver: Address,
+    receiver_pub_key: Option<PublicKey>,
+    amount: u64,
+    fee: u64,
+    nonce: u64,
+) -> Data {
+    let signature = crypto::sign(
+        private_key,
+        &transfer_message(
+            chain_id,
+            sender,
+            receiver,
+            amount,
+            fee,
+            nonce,
+            receiver_pub_key.as_ref(),
+        ),
+    );
+    Data::Transfer {
+        sender,
+        receiver,
+        amount,
+        fee,
+        nonce,
+        signature,
+        receiver_pub_key,
+    }
+}
+
+/// Canonical bytes of a transfer covered by its signature.
+pub fn transfer_message(
+    chain_id: u64,
+    sender: Address,
+    receiver: Address,
+    amount: u64,
+    fee: u64,
+    nonce: u64,
+    receiver_pub_key: Option<&PublicKey>,
+) -> Vec<u8> {
+    let mut message = [
+        sender.to_be_bytes(),
+        receiver.to_be_bytes(),
+        amount.to_be_bytes(),
+        fee.to_be_bytes(),
+        nonce.to_be_bytes(),
+    ]
+    .concat();
+    if let Some(pub_key) = receiver_pub_key {
+        message.extend_from_slice(&pub_key.0);
+    }
+    if chain_id != 0 {
+        message.extend_from_slice(&chain_id.to_be_bytes());
+    }
+    message
+}
+
+fn count_transfers(data: &Data) -> u64 {
+    match data {
+        Data::Transfer { .. } => 1,
+        Data::Batch(items) => items.iter().map(count_transfers).sum(),
+        _ => 0,
+    }
+}
+
+fn has_transfer_of(data: &Data, address: Address) -> bool {
+    match data {
+        Data::Transfer { .. } => data.sender() == Some(address) || data.receiver() == Some(address),
+        Data::Batch(items) => items.iter().any(|item| has_transfer_of(item, address)),
+        _ => false,
+    }
+}
+
+fn default_difficulty() -> usize {
+    DEFAULT_DIFFICULTY
+}
+
+/// Tries the nonces until one gives a hash with `difficulty` leading zero bits.
+fn search_nonces(
+    hash_with_nonce: impl Fn(u64) -> Vec<u8>,
+    difficulty: usize,
+    log_interval: u64,
+    nonces: impl Iterator<Item = u64>,
+    should_stop: impl Fn() -> bool,
+) -> Result<(u64, String), MiningError> {
+    let difficulty_prefix = difficulty_prefix(difficulty);
+    let log_interval = log_interval.max(1);
+    let started = Instant::now();
+    for nonce in nonces {
+        if nonce % log_interval == 0 {
+            // The workers of a parallel search share the nonces below this one.
+            let progress = MiningProgress {
+                attempts: nonce,
+                elapsed: started.elapsed(),
+                difficulty,
+            };
+            info!(target: MINING_TARGET, "Nonce: {}, {}", nonce, progress);
+        }
+        if should_stop() {
+            info!(target: MINING_TARGET, "Mining stopped at nonce: {}", nonce);
+            return Err(MiningError::Cancelled);
+        }
+
+        let hash = hash_with_nonce(nonce);
+        let binary_hash = hash_to_binary_representation(&hash);
+        if binary_hash.starts_with(&difficulty_prefix) {
+            info!(
+                target: MINING_TARGET,
+                "Mined! Nonce: {}, hash: {}, binary_hash: {}",
+                nonce,
+                hex::encode(&hash),
+                binary_hash
+            );
+            return Ok((nonce, hex::encode(hash)));
+        }
+    }
+    Err(MiningError::Exhausted)
+}
+
+/// Mines at later timestamps for as long as `mine_at` exhausts the nonces, so the search space is
+/// the 2^64 nonces of every timestamp. The timestamp is taken from the clock, but moves by at least
+/// a second, as the clock may not have moved on during the search.
+fn mine_rolling_timestamp<T>(
+    mut timestamp: i64,
+    now: impl Fn() -> i64,
+    mut mine_at: impl FnMut(i64) -> Result<T, MiningError>,
+) -> Result<(i64, T), MiningError> {
+    loop {
+        match mine_at(timestamp) {
+            Err(MiningError::Exhausted) => {
+                timestamp = now().max(timestamp + 1);
+                info!(
+                    target: MINING_TARGET,
+                    "Nonces exhausted, mining at timestamp {}", timestamp
+                );
+            }
+            result => return result.map(|solution| (timestamp, solution)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod node_tests {
+    use super::*;
+    use crate::core::canonical_encode;
+    use crate::policy::PolicyError;
+    use crate::storage::read_chain;
+    use libp2p::PeerId;
+    use log::Level;
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+    use std::fs;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn get_genesis_block() -> Block {
+        Block {
+            version: LEGACY_BLOCK_VERSION,
+            id: 0,
+            previous_hash: String::from("genesis"),
+            timestamp: 1665411300,
+            data: Data::Account(GENESIS_ACCOUNT.clone()),
+            merkle_root: String::new(),
+            nonce: 420,
+            miner: None,
+            hash: "b91e4d682913d25a96653aff32c60b07c1ecfb138273e44fc20126916b13bc17".to_string(),
+        }
+    }
+
+    fn get_first_block() -> Block {
+        Block {
+            version: LEGACY_BLOCK_VERSION,
+            id: 1,
+            previous_hash: "b91e4d682913d25a96653aff32c60b07c1ecfb138273e44fc20126916b13bc17"
+                .to_string(),
+            timestamp: 1665411301,
+            data: Data::Account(Account {
+                address: 1,
+                balance: INIT_BALANCE,
+                pub_key: PublicKey([0x11; 32]),
+                nonce: 0,
+            }),
+            merkle_root: String::new(),
+            nonce: 0,
+            miner: None,
+            hash: "3cbd67788e35051d7e2209f193b5ab8238954a94155d4ee73cf73f7c9c51d7bd".to_string(),
+        }
+    }
+
+    fn get_temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("ch-rust-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn pads_each_byte_to_eight_bits() {
+        assert_eq!(
+            hash_to_binary_representation(&[0x03, 0x00]),
+            "0000001100000000"
+        );
+    }
+
+    fn get_genesis_config(balance: u64) -> GenesisConfig {
+        GenesisConfig {
+            timestamp: 1700000000,
+            difficulty: DEFAULT_DIFFICULTY,
+            accounts: vec![
+                Account {
+                    balance,
+                    ..GENESIS_ACCOUNT
+                },
+                Account {
+                    address: 1,
+                    balance,
+                    pub_key: PublicKey([0x11; 32]),
+                    nonce: 0,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn default_genesis_config_keeps_original_genesis_block() {
+        assert_eq!(
+            GenesisConfig::default().block(&Sha256Hasher),
+            get_genesis_block()
+        );
+    }
+
+    #[cfg(feature = "sha3")]
+    #[test]
+    fn validates_chain_only_with_its_hash_function() {
+        use crate::hashing::Sha3Hasher;
+        let mut node = Node::with_hasher(&Sha3Hasher);
+        node.genesis();
+        let (account, _) = Account::new();
+        let data = node.with_reward(Data::Account(account));
+        let block = Block::new_with_hasher(
+            1,
+            node.get_last_block().hash.clone(),
+            data.clone(),
+            node.compute_difficulty(),
+            &Sha3Hasher,
+            0,
+        );
+        assert_eq!(node.try_add_block(block), Ok(()));
+        assert!(node.is_chain_valid(&node.blocks));
+
+        let mut other = Node::new();
+        other.genesis();
+        assert_ne!(other.blocks[0], node.blocks[0]);
+        assert!(!other.is_chain_valid(&node.blocks));
+        let block = Block::new_with_hasher(
+            1,
+            other.get_last_block().hash.clone(),
+            data,
+            other.compute_difficulty(),
+            &Sha3Hasher,
+            0,
+        );
+        assert_eq!(
+            other.try_add_block(block),
+            Err(BlockError::MerkleRootMismatch)
+        );
+    }
+
+    #[test]
+    fn genesis_blocks_are_self_consistent() {
+        for block in [
+            Node::default_genesis_block(&Sha256Hasher),
+            get_genesis_config(1000).block(&Sha256Hasher),
+        ] {
+            assert_eq!(block.compute_hash(&Sha256Hasher, 0), block.hash);
+            assert_eq!(block.merkle_root, block.expected_merkle_root(&Sha256Hasher));
+        }
+    }
+
+    #[test]
+    fn mines_genesis_block_from_config() {
+        let config = get_genesis_config(1000);
+        let mut node = Node::new();
+
+        node.genesis_from_config(&config).unwrap();
+
+        assert_eq!(node.blocks, vec![config.block(&Sha256Hasher)]);
+        assert_eq!(node.accounts[&0].balance, 1000);
+        assert_eq!(node.accounts[&1].balance, 1000);
+        let genesis = node.get_last_block();
+        assert!(
+            hash_to_binary_representation(&hex::decode(&genesis.hash).unwrap())
+                .starts_with(&difficulty_prefix(DEFAULT_DIFFICULTY))
+        );
+    }
+
+    #[test]
+    fn chains_from_different_genesis_configs_do_not_merge() {
+        let mut node = Node::new();
+        node.genesis_from_config(&get_genesis_config(1000)).unwrap();
+        let mut other = Node::new();
+        other
+            .genesis_from_config(&get_genesis_config(2000))
+            .unwrap();
+        let data = other.with_reward(Data::Account(Account::implicit(5, PublicKey([0x55; 32]))));
+        let block = Block::new(
+            1,
+            other.get_last_block().hash.clone(),
+            data,
+            other.compute_difficulty(),
+        );
+        assert!(other.try_add_block(block).is_ok());
+
+        assert_ne!(node.blocks[0].hash, other.blocks[0].hash);
+        assert!(!node.import_chain(other.blocks.clone()));
+        assert_eq!(
+            node.choose_chain(node.blocks.clone(), other.blocks.clone()),
+            Ok(node.blocks.clone())
+        );
+        assert_eq!(node.blocks.len(), 1);
+    }
+
+    #[test]
+    fn creates_genesis_block() {
+        let mut node = Node::new();
+        let genesis_block = get_genesis_block();
+
+        node.genesis();
+
+        assert_eq!(node.blocks.len(), 1);
+        assert_eq!(node.blocks.first().unwrap(), &genesis_block);
+    }
+
+    #[test]
+    fn validates_first_block() {
+        let mut node = Node::new();
+        let first_block = get_first_block();
+
+        node.genesis();
+        assert_eq!(node.try_add_block(first_block.clone()), Ok(()));
+
+        assert_eq!(node.blocks.len(), 2);
+        assert_eq!(node.blocks.get(1).unwrap(), &first_block);
+    }
+
+    #[test]
+    fn does_not_validate_with_wrong_previous_hash() {
+        let mut node = Node::new();
+        let mut first_block = get_first_block();
+        first_block.previous_hash.replace_range(0..1, "f");
+
+        testing_logger::setup();
+
+        node.genesis();
+        assert_eq!(
+            node.try_add_block(first_block),
+            Err(BlockError::WrongPreviousHash)
+        );
+
+        assert_eq!(node.blocks.len(), 1);
+        testing_logger::validate(|captured_logs| {
+            assert_eq!(captured_logs.len(), 2);
+            assert_eq!(
+                captured_logs[0].body,
+                "Block with id: 1 has wrong previous hash"
+            );
+            assert_eq!(captured_logs[0].level, Level::Warn);
+            assert_eq!(captured_logs[1].body, "Could not add block - invalid.");
+            assert_eq!(captured_logs[1].level, Level::Error);
+        })
+    }
+
+    #[test]
+    fn does_not_validate_with_wrong_difficulty() {
+        let mut node = Node::new();
+        let mut first_block = get_first_block();
+        first_block.hash.replace_range(0..2, "ff");
+
+        testing_logger::setup();
+
+        node.genesis();
+        assert_eq!(
+            node.try_add_block(first_block),
+            Err(BlockError::BadDifficulty)
+        );
+
+        assert_eq!(node.blocks.len(), 1);
+        testing_logger::validate(|captured_logs| {
+            assert_eq!(captured_logs.len(), 2);
+            assert_eq!(
+                captured_logs[0].body,
+                "Block with id: 1 has invalid difficulty."
+            );
+            assert_eq!(captured_logs[0].level, Level::Warn);
+            assert_eq!(captured_logs[1].body, "Could not add block - invalid.");
+            assert_eq!(captured_logs[1].level, Level::Error);
+        })
+    }
+
+    #[test]
+    fn does_not_validate_with_wrong_id() {
+        let mut node = Node::new();
+        let mut first_block = get_first_block();
+        first_block.id = 2;
+
+        testing_logger::setup();
+
+        node.genesis();
+        assert_eq!(
+            node.try_add_block(first_block),
+            Err(BlockError::NonSequentialId)
+        );
+
+        assert_eq!(node.blocks.len(), 1);
+        testing_logger::validate(|captured_logs| {
+            assert_eq!(captured_logs.len(), 2);
+            assert_eq!(
+                captured_logs[0].body,
+                "Block with id: 2 is not the next block after the latest: 0"
+            );
+            assert_eq!(captured_logs[0].level, Level::Warn);
+            assert_eq!(captured_logs[1].body, "Could not add block - invalid.");
+            assert_eq!(captured_logs[1].level, Level::Error);
+        })
+    }
+
+    #[test]
+    fn does_not_validate_with_wrong_hash() {
+        let mut node = Node::new();
+        let mut first_block = get_first_block();
+        first_block.data = Data::Account(Account {
+            address: 1,
+            balance: 0,
+            pub_key: PublicKey([0x22; 32]),
+            nonce: 0,
+        });
+        testing_logger::setup();
+
+        node.genesis();
+        assert_eq!(
+            node.try_add_block(first_block),
+            Err(BlockError::HashMismatch)
+        );
+
+        assert_eq!(node.blocks.len(), 1);
+        testing_logger::validate(|captured_logs| {
+            assert_eq!(captured_logs.len(), 2);
+            assert_eq!(captured_logs[0].body, "Block with id: 1 has invalid hash");
+            assert_eq!(captured_logs[0].level, Level::Warn);
+            assert_eq!(captured_logs[1].body, "Could not add block - invalid.");
+            assert_eq!(captured_logs[1].level, Level::Error);
+        })
+    }
+
+    #[test]
+    fn validates_block_without_its_parent() {
+        let validate =
+            |block: &Block, difficulty| block.validate_self(difficulty, &Sha256Hasher, 0, 60);
+        let block = get_first_block();
+        assert_eq!(validate(&block, DEFAULT_DIFFICULTY), Ok(()));
+        assert_eq!(
+            validate(&block, DEFAULT_DIFFICULTY + 1),
+            Err(BlockError::BadDifficulty)
+        );
+
+        let mut wrong_hash = block.clone();
+        wrong_hash.nonce = 1;
+        assert_eq!(
+            validate(&wrong_hash, DEFAULT_DIFFICULTY),
+            Err(BlockError::HashMismatch)
+        );
+        wrong_hash.hash = "not hex".to_string();
+        assert_eq!(validate(&wrong_hash, 0), Err(BlockError::HashMismatch));
+
+        let mut future = Block::new(
+            1,
+            block.previous_hash.clone(),
+            block.data.clone(),
+            DEFAULT_DIFFICULTY,
+        );
+        assert_eq!(validate(&future, DEFAULT_DIFFICULTY), Ok(()));
+        future.timestamp += 3600;
+        future.hash = future.compute_hash(&Sha256Hasher, 0);
+        assert_eq!(validate(&future, 0), Err(BlockError::TimestampInFuture));
+    }
+
+    #[test]
+    fn validates_chain() {
+        let node = Node::new();
+        let is_valid =
+            node.is_chain_valid((vec![get_genesis_block(), get_first_block()]).as_slice());
+
+        assert!(is_valid);
+    }
+
+    #[test]
+    fn does_not_validate_chain() {
+        let node = Node::new();
+        let is_valid = node.is_chain_valid(
+            (vec![get_genesis_block(), get_genesis_block(), get_first_block()]).as_slice(),
+        );
+
+        assert!(!is_valid);
+    }
+
+    #[test]
+    fn validates_chain_with_settings_of_node() {
+        let config = get_genesis_config(100);
+        let mut node = Node::new();
+        node.genesis_from_config(&config).unwrap();
+        node.chain_id = 7;
+        let data = node.with_reward(Data::Batch(vec![]));
+        let block = node
+            .mine_block_with_deadline(data, Duration::from_secs(60))
+            .unwrap();
+        assert!(node.try_add_block(block).is_ok());
+        let mut validator = Node::new();
+        validator.set_genesis(&config).unwrap();
+        validator.chain_id = 7;
+
+        let report = validator.validate_chain(&node.blocks);
+
+        assert!(report.is_valid());
+        assert_eq!(report.total_accounts, 2);
+        assert_eq!(
+            Node::new().validate_chain(&node.blocks).invalid_block,
+            Some(0)
+        );
+        validator.chain_id = 0;
+        assert_eq!(
+            validator.validate_chain(&node.blocks).invalid_block,
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn imports_longer_chain_from_file() {
+        let mut node = Node::new();
+        node.genesis();
+
+        let genesis_block = get_genesis_block();
+        let account = Account {
+            address: 7,
+            balance: INIT_BALANCE,
+            pub_key: PublicKey([0x77; 32]),
+            nonce: 0,
+        };
+        let block = Block::new(
+            1,
+            genesis_block.hash.clone(),
+            Data::Account(account.clone()),
+            DEFAULT_DIFFICULTY,
+        );
+        let chain = vec![genesis_block, block];
+
+        let path = get_temp_path("import.json");
+        fs::write(&path, serde_json::to_string(&chain).unwrap()).unwrap();
+        let imported = read_chain(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(node.import_chain(imported));
+        assert_eq!(node.blocks, chain);
+        assert_eq!(node.accounts.get(&7), Some(&account));
+    }
+
+    #[test]
+    fn chooses_valid_prefix_of_longer_chain() {
+        let mut node = Node::new();
+        node.genesis();
+        let mut remote = Node::new();
+        remote.genesis();
+        for address in 7..10 {
+            let account = Account::implicit(address, PublicKey([address as u8; 32]));
+            let latest_block = remote.get_last_block();
+            let block = Block::new(
+                latest_block.id + 1,
+                latest_block.hash.clone(),
+                Data::Account(account),
+                remote.compute_difficulty(),
+            );
+            assert!(remote.try_add_block(block).is_ok());
+        }
+        let mut chain = remote.blocks.clone();
+        chain[3].nonce += 1;
+
+        assert_eq!(node.longest_valid_prefix(&chain), &remote.blocks[..3]);
+        let chosen = node.choose_chain(node.blocks.clone(), chain).unwrap();
+        assert_eq!(chosen, remote.blocks[..3]);
+        assert!(node.adopt_chain(chosen));
+        assert_eq!(node.height(), Some(2));
+    }
+
+    #[test]
+    fn does_not_import_invalid_chain() {
+        let mut node = Node::new();
+        node.genesis();
+
+        let is_imported = node.import_chain(vec![
+            get_genesis_block(),
+            get_genesis_block(),
+            get_first_block(),
+        ]);
+
+        assert!(!is_imported);
+        assert_eq!(node.blocks, vec![get_genesis_block()]);
+    }
+
+    #[test]
+    fn routes_mining_and_sync_logs_to_distinct_targets() {
+        let mut node = Node::new();
+        node.genesis();
+
+        testing_logger::setup();
+
+        let block = Block::new(
+            1,
+            get_genesis_block().hash,
+            Data::Account(Account {
+                address: 7,
+                balance: INIT_BALANCE,
+                pub_key: PublicKey([0x77; 32]),
+                nonce: 0,
+            }),
+            DEFAULT_DIFFICULTY,
+        );
+        node.import_chain(vec![get_genesis_block(), block]);
+
+        testing_logger::validate(|captured_logs| {
+            let mining_log = captured_logs
+                .iter()
+                .find(|log| log.body.starts_with("Mined!"))
+                .expect("Mining log was captured.");
+            let sync_log = captured_logs
+                .iter()
+                .find(|log| log.body.starts_with("Import: adopted"))
+                .expect("Sync log was captured.");
+
+            assert_eq!(mining_log.target, MINING_TARGET);
+            assert_eq!(sync_log.target, SYNC_TARGET);
+        })
+    }
+
+    #[test]
+    fn does_not_adopt_chain_beyond_reorg_depth() {
+        let mut remote = Node::new();
+        remote.genesis();
+        for address in 7..11 {
+            let account = Account::implicit(address, PublicKey([address as u8; 32]));
+            let latest_block = remote.get_last_block();
+            let block = Block::new(
+                latest_block.id + 1,
+                latest_block.hash.clone(),
+                Data::Account(account),
+                remote.compute_difficulty(),
+            );
+            assert!(remote.try_add_block(block).is_ok());
+        }
+        let mut node = Node::new();
+        node.genesis();
+        node.max_reorg_depth = Some(3);
+
+        assert!(!node.adopt_chain(remote.blocks.clone()));
+        assert!(!node.import_chain(remote.blocks.clone()));
+        assert_eq!(node.height(), Some(0));
+
+        assert!(node.adopt_chain(remote.blocks[..4].to_vec()));
+        assert!(node.adopt_chain(remote.blocks.clone()));
+        assert_eq!(node.height(), Some(4));
+
+        let mut empty = Node::new();
+        empty.max_reorg_depth = Some(3);
+        assert!(empty.adopt_chain(remote.blocks.clone()));
+    }
+
+    #[test]
+    fn records_reorg_when_switching_forks() {
+        let mut node = Node::new();
+        node.genesis();
+
+        let genesis_hash = get_genesis_block().hash;
+        let account = |address| {
+            Data::Account(Account {
+                address,
+                balance: INIT_BALANCE,
+                pub_key: PublicKey([address as u8; 32]),
+                nonce: 0,
+            })
+        };
+        let local = Block::new(1, genesis_hash.clone(), account(7), DEFAULT_DIFFICULTY);
+        let remote_first = Block::new(1, genesis_hash, account(8), DEFAULT_DIFFICULTY);
+        let remote_second =
+            Block::new(2, remote_first.hash.clone(), account(9), DEFAULT_DIFFICULTY);
+
+        assert!(node.adopt_chain(vec![get_genesis_block(), local.clone()]));
+        assert!(node.recent_reorgs().is_empty());
+
+        assert!(node.adopt_chain(vec![
+            get_genesis_block(),
+            remote_first,
+            remote_second.clone()
+        ]));
+
+        assert_eq!(node.recent_reorgs().len(), 1);
+        let reorg = node.recent_reorgs().back().unwrap();
+        assert_eq!(reorg.depth, 1);
+        assert_eq!(reorg.old_tip, local.hash);
+        assert_eq!(reorg.new_tip, remote_second.hash);
+        assert_eq!(reorg.affected_accounts, vec![7, 8, 9]);
+    }
+
+    #[test]
+    fn gives_up_mining_after_nonce_budget() {
+        let data = Data::Account(Account {
+            address: 7,
+            balance: INIT_BALANCE,
+            pub_key: PublicKey([0x77; 32]),
+            nonce: 0,
+        });
+
+        let result = Block::mine_block(
+            &Sha256Hasher,
+            0,
+            BLOCK_VERSION,
+            None,
+            1,
+            1665411301,
+            "hash",
+            &data,
+            256,
+            1000,
+            DEFAULT_MINING_LOG_INTERVAL,
+            &AtomicBool::new(false),
+        );
+
+        assert_eq!(result, Err(MiningError::Exhausted));
+    }
+
+    #[test]
+    fn rolls_timestamp_after_exhausting_nonces() {
+        let data = Data::Account(Account::implicit(7, PublicKey([0x77; 32])));
+        let mut timestamps = vec![];
+        let result = mine_rolling_timestamp(
+            1665411301,
+            || 1665411301,
+            |timestamp| {
+                timestamps.push(timestamp);
+                if timestamps.len() == 1 {
+                    // As if all u64::MAX nonces were tried at the first timestamp.
+                    return Err(MiningError::Exhausted);
+                }
+                Block::mine_block(
+                    &Sha256Hasher,
+                    0,
+                    CANONICAL_BLOCK_VERSION,
+                    None,
+                    1,
+                    timestamp,
+                    "hash",
+                    &data,
+                    1,
+                    u64::MAX,
+                    DEFAULT_MINING_LOG_INTERVAL,
+                    &AtomicBool::new(false),
+                )
+            },
+        );
+
+        assert_eq!(timestamps, vec![1665411301, 1665411302]);
+        let (timestamp, (nonce, hash)) = result.unwrap();
+        let block = Block {
+            version: CANONICAL_BLOCK_VERSION,
+            id: 1,
+            hash,
+            previous_hash: "hash".to_string(),
+            timestamp,
+            merkle_root: Block::merkle_root(&data, &Sha256Hasher),
+            data,
+            nonce,
+            miner: None,
+        };
+        assert_eq!(block.validate_self(1, &Sha256Hasher, 0, 0), Ok(()));
+        assert_eq!(
+            mine_rolling_timestamp(0, || 0, |_| Err::<(), _>(MiningError::Cancelled)),
+            Err(MiningError::Cancelled)
+        );
+    }
+
+    #[test]
+    fn estimates_mining_progress() {
+        let progress = MiningProgress {
+            attempts: 1024,
+            elapsed: Duration::from_secs(2),
+            difficulty: 12,
+        };
+
+        assert_eq!(progress.expected_attempts(), 4096.0);
+        assert_eq!(progress.percent(), 25.0);
+        assert_eq!(progress.hash_rate(), 512.0);
+        assert_eq!(progress.eta(), Some(Duration::from_secs(6)));
+        assert_eq!(
+            progress.to_string(),
+            "25.0% of the 4096 expected attempts, 512 hashes/s, about 6s left"
+        );
+
+        let unlucky = MiningProgress {
+            attempts: 8192,
+            ..progress
+        };
+        assert_eq!(unlucky.eta(), None);
+        let started = MiningProgress {
+            attempts: 0,
+            elapsed: Duration::ZERO,
+            difficulty: 12,
+        };
+        assert_eq!((started.hash_rate(), started.eta()), (0.0, None));
+    }
+
+    #[test]
+    fn gives_up_mining_after_deadline() {
+        // No hash has that many leading zero bits.
+        let mut node = Node::with_difficulty(256);
+        node.genesis();
+        let data = Data::Account(Account::implicit(7, PublicKey([0x77; 32])));
+
+        let started = Instant::now();
+        let result = node.mine_block_with_deadline(data.clone(), Duration::from_millis(20));
+
+        assert_eq!(result, None);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        node.difficulty = 4;
+        node.miner = Some(PeerId::random().to_string());
+        let block = node
+            .mine_block_with_deadline(data, Duration::from_secs(60))
+            .expect("Can mine at a small difficulty.");
+        assert_eq!(block.miner, node.miner);
+        assert_eq!(node.try_add_block(block), Ok(()));
+    }
+
+    #[test]
+    fn stops_mining_when_cancelled() {
+        let data = Data::Account(Account {
+            address: 7,
+            balance: INIT_BALANCE,
+            pub_key: PublicKey([0x77; 32]),
+            nonce: 0,
+        });
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let miner = {
+            let cancel = cancel.clone();
+            thread::spawn(move || {
+                Block::try_new(
+                    1,
+                    "hash".to_string(),
+                    data,
+                    256,
+                    u64::MAX,
+                    4,
+                    DEFAULT_MINING_LOG_INTERVAL,
+                    &cancel,
+                    &Sha256Hasher,
+                    0,
+                    None,
+                )
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        cancel.store(true, Ordering::Relaxed);
+
+        assert_eq!(miner.join().unwrap(), Err(MiningError::Cancelled));
+    }
+
+    #[test]
+    fn mines_within_nonce_budget() {
+        let data = Data::Account(Account {
+            address: 7,
+            balance: INIT_BALANCE,
+            pub_key: PublicKey([0x77; 32]),
+            nonce: 0,
+        });
+
+        let (nonce, hash) = Block::mine_block(
+            &Sha256Hasher,
+            0,
+            BLOCK_VERSION,
+            None,
+            1,
+            1665411301,
+            "hash",
+            &data,
+            1,
+            1000,
+            DEFAULT_MINING_LOG_INTERVAL,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+
+        assert!(nonce <= 1000);
+        assert_eq!(
+            hex::encode(calculate_hash(
+                &Sha256Hasher,
+                0,
+                BLOCK_VERSION,
+                1,
+                1665411301,
+                "hash",
+                &data,
+                &Block::merkle_root(&data, &Sha256Hasher),
+                nonce,
+                None
+            )),
+            hash
+        );
+    }
+
+    fn get_legacy_block_json(data: &Data) -> String {
+        let genesis_hash = get_genesis_block().hash;
+        let (nonce, hash) = Block::mine_block(
+            &Sha256Hasher,
+            0,
+            LEGACY_BLOCK_VERSION,
+            None,
+            1,
+            1665411301,
+            &genesis_hash,
+            data,
+            DEFAULT_DIFFICULTY,
+            u64::MAX,
+            DEFAULT_MINING_LOG_INTERVAL,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+
+        json!({
+            "id": 1,
+            "hash": hash,
+            "previous_hash": genesis_hash,
+            "timestamp": 1665411301,
+            "data": data,
+            "nonce": nonce
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn deserializes_and_validates_legacy_block() {
+        let mut node = Node::new();
+        node.genesis();
+        let data = Data::Account(Account {
+            address: 7,
+            balance: INIT_BALANCE,
+            pub_key: PublicKey([0x77; 32]),
+            nonce: 0,
+        });
+
+        let block = serde_json::from_str::<Block>(&get_legacy_block_json(&data)).unwrap();
+
+        assert_eq!(block.version, LEGACY_BLOCK_VERSION);
+        assert!(node.try_add_block(block).is_ok());
+    }
+
+    #[test]
+    fn does_not_validate_versioned_block_without_version() {
+        let mut node = Node::new();
+        node.genesis();
+        let mut block = Block::new(
+            1,
+            get_genesis_block().hash,
+            Data::Account(Account {
+                address: 7,
+                balance: INIT_BALANCE,
+                pub_key: PublicKey([0x77; 32]),
+                nonce: 0,
+            }),
+            DEFAULT_DIFFICULTY,
+        );
+        block.version = LEGACY_BLOCK_VERSION;
+
+        assert_eq!(
+            node.try_add_block(block),
+            Err(BlockError::MerkleRootMismatch)
+        );
+    }
+
+    #[test]
+    fn does_not_validate_unsupported_version() {
+        let mut node = Node::new();
+        node.genesis();
+        let mut block = get_first_block();
+        block.version = BLOCK_VERSION + 1;
+
+        assert_eq!(
+            node.try_add_block(block),
+            Err(BlockError::UnsupportedVersion(BLOCK_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn does_not_validate_block_below_node_difficulty() {
+        let genesis_hash = get_genesis_block().hash;
+        let data = Data::Account(Account {
+            address: 7,
+            balance: INIT_BALANCE,
+            pub_key: PublicKey([0x77; 32]),
+            nonce: 0,
+        });
+        let (nonce, hash) = Block::mine_block(
+            &Sha256Hasher,
+            0,
+            CANONICAL_BLOCK_VERSION,
+            None,
+            1,
+            1665411301,
+            &genesis_hash,
+            &data,
+            4,
+            u64::MAX,
+            DEFAULT_MINING_LOG_INTERVAL,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        let block = Block {
+            version: CANONICAL_BLOCK_VERSION,
+            id: 1,
+            hash,
+            previous_hash: genesis_hash,
+            timestamp: 1665411301,
+            merkle_root: Block::merkle_root(&data, &Sha256Hasher),
+            data,
+            nonce,
+            miner: None,
+        };
+
+        let mut easy_node = Node::with_difficulty(4);
+        easy_node.genesis();
+        let mut hard_node = Node::with_difficulty(8);
+        hard_node.genesis();
+
+        assert!(hard_node.try_add_block(block.clone()).is_err());
+        assert!(easy_node.try_add_block(block).is_ok());
+    }
+
+    #[test]
+    fn parallel_miner_produces_valid_block() {
+        let mut node = Node::with_difficulty(8);
+        node.genesis();
+        let genesis = node.get_last_block().clone();
+        let data = Data::Account(Account {
+            address: 7,
+            balance: INIT_BALANCE,
+            pub_key: PublicKey([0x77; 32]),
+            nonce: 0,
+        });
+
+        let (nonce, hash) = Block::mine_block_parallel(
+            &Sha256Hasher,
+            0,
+            None,
+            1,
+            1665411301,
+            &genesis.hash,
+            &data,
+            8,
+            u64::MAX,
+            4,
+            DEFAULT_MINING_LOG_INTERVAL,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        let block = Block {
+            version: CANONICAL_BLOCK_VERSION,
+            id: 1,
+            hash,
+            previous_hash: genesis.hash.clone(),
+            timestamp: 1665411301,
+            merkle_root: Block::merkle_root(&data, &Sha256Hasher),
+            data,
+            nonce,
+            miner: None,
+        };
+
+        assert!(node.is_block_valid(&block, &genesis, 8).is_ok());
+    }
+
+    fn get_node_with_sender(pub_key: PublicKey, balance: u64) -> Node {
+        let mut node = Node::new();
+        node.genesis();
+        for account in [
+            Account {
+                address: 1,
+                balance,
+                pub_key,
+                nonce: 0,
+            },
+            Account {
+                address: 2,
+                balance: INIT_BALANCE,
+                pub_key: PublicKey([0x22; 32]),
+                nonce: 0,
+            },
+        ] {
+            node.pub_keys.insert(account.address, account.pub_key);
+            node.accounts.insert(account.address, account);
+        }
+        node
+    }
+
+    #[test]
+    fn derives_genesis_public_key() {
+        assert_eq!(
+            crypto::derive_public_key(&GENESIS_PRIVATE_KEY),
+            GENESIS_PUB_KEY
+        );
+    }
+
+    #[test]
+    fn accepts_signed_transfer() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        let signature = crypto::sign(&private_key, &transfer_message(0, 1, 2, 10, 0, 0, None));
+
+        assert!(node
+            .try_add_transfer(&Data::Transfer {
+                sender: 1,
+                receiver: 2,
+                amount: 10,
+                fee: 0,
+                nonce: 0,
+                signature,
+                receiver_pub_key: None,
+            })
+            .is_ok());
+        assert_eq!(node.accounts[&1].balance, 90);
+        assert_eq!(node.accounts[&2].balance, 10);
+    }
+
+    #[test]
+    fn rejects_transfer_to_sender() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        let accounts = node.accounts.clone();
+        let transfer = sign_transfer(&private_key, 1, 1, 10, 1, 0);
+
+        assert_eq!(
+            node.try_add_transfer(&transfer),
+            Err(TransferError::SelfTransfer)
+        );
+        assert_eq!(
+            node.try_add_to_mempool(transfer.clone()),
+            Err(TransferError::SelfTransfer)
+        );
+        let block = get_batch_block(&node, vec![transfer]);
+        assert_eq!(
+            node.try_add_block(block),
+            Err(BlockError::InvalidTransfer(TransferError::SelfTransfer))
+        );
+        assert_eq!(node.accounts, accounts);
+        assert!(node.mempool.is_empty());
+    }
+
+    #[test]
+    fn rejects_transfer_to_unknown_address_by_default() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let (_, receiver_pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+
+        assert_eq!(
+            node.try_add_transfer(&sign_transfer(&private_key, 1, 9, 10, 0, 0)),
+            Err(TransferError::UnknownReceiver)
+        );
+        assert_eq!(
+            node.try_add_transfer(&sign_transfer_to_new_account(
+                0,
+                &private_key,
+                1,
+                9,
+                receiver_pub_key,
+                10,
+                0,
+                0
+            )),
+            Err(TransferError::ImplicitAccountsNotAllowed)
+        );
+        assert_eq!(node.accounts[&1].balance, 100);
+        assert!(!node.accounts.contains_key(&9));
+    }
+
+    #[test]
+    fn creates_receiver_account_when_implicit_accounts_allowed() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let (receiver_private_key, receiver_pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        node.allow_implicit_accounts = true;
+
+        // Without a key there is nothing to create the account with.
+        assert!(node
+            .try_add_transfer(&sign_transfer(&private_key, 1, 9, 10, 0, 0))
+            .is_err());
+        assert!(node
+            .try_add_transfer(&sign_transfer_to_new_account(
+                0,
+                &private_key,
+                1,
+                9,
+                receiver_pub_key,
+                10,
+                0,
+                0
+            ))
+            .is_ok());
+        assert_eq!(node.accounts[&1].balance, 90);
+        assert_eq!(node.accounts[&9].balance, 10);
+        assert_eq!(node.pub_keys[&9], receiver_pub_key);
+
+        assert!(node
+            .try_add_transfer(&sign_transfer(&receiver_private_key, 9, 1, 5, 0, 0))
+            .is_ok());
+        assert_eq!(node.accounts[&9].balance, 5);
+    }
+
+    #[test]
+    fn receiver_key_is_covered_by_signature() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let (_, receiver_pub_key) = crypto::generate_keypair();
+        let (_, other_pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        node.allow_implicit_accounts = true;
+
+        let mut transfer =
+            sign_transfer_to_new_account(0, &private_key, 1, 9, receiver_pub_key, 10, 0, 0);
+        if let Data::Transfer {
+            receiver_pub_key, ..
+        } = &mut transfer
+        {
+            *receiver_pub_key = Some(other_pub_key);
+        }
+
+        assert_eq!(
+            node.try_add_transfer(&transfer),
+            Err(TransferError::BadSignature)
+        );
+        assert!(!node.accounts.contains_key(&9));
+    }
+
+    #[test]
+    fn leaves_missing_receiver_key_out_of_json() {
+        let (private_key, _) = crypto::generate_keypair();
+        let transfer = sign_transfer(&private_key, 1, 2, 10, 0, 0);
+
+        let json = serde_json::to_string(&transfer).unwrap();
+
+        assert!(!json.contains("receiver_pub_key"));
+        assert_eq!(serde_json::from_str::<Data>(&json).unwrap(), transfer);
+    }
+
+    #[test]
+    fn does_not_accept_transfer_signed_by_wrong_key() {
+        let (_, pub_key) = crypto::generate_keypair();
+        let (other_private_key, _) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        let signature = crypto::sign(
+            &other_private_key,
+            &transfer_message(0, 1, 2, 10, 0, 0, None),
+        );
+
+        assert!(node
+            .try_add_transfer(&Data::Transfer {
+                sender: 1,
+                receiver: 2,
+                amount: 10,
+                fee: 0,
+                nonce: 0,
+                signature,
+                receiver_pub_key: None,
+            })
+            .is_err());
+        assert_eq!(node.accounts[&1].balance, 100);
+        assert_eq!(node.accounts[&2].balance, INIT_BALANCE);
+    }
+
+    #[test]
+    fn does_not_accept_transfer_with_tampered_amount() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        let signature = crypto::sign(&private_key, &transfer_message(0, 1, 2, 10, 0, 0, None));
+
+        assert_eq!(
+            node.try_add_transfer(&Data::Transfer {
+                sender: 1,
+                receiver: 2,
+                amount: 20,
+                fee: 0,
+                nonce: 0,
+                signature,
+                receiver_pub_key: None,
+            }),
+            Err(TransferError::BadSignature)
+        );
+        assert_eq!(node.accounts[&1].balance, 100);
+        assert_eq!(node.accounts[&2].balance, INIT_BALANCE);
+    }
+
+    #[test]
+    fn signed_transfer_passes_validation() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+
+        let transfer = sign_transfer(&private_key, 1, 2, 10, 0, 0);
+
+        assert!(node.try_add_transfer(&transfer).is_ok());
+        assert_eq!(node.accounts[&1].balance, 90);
+        assert_eq!(node.accounts[&2].balance, 10);
+    }
+
+    #[test]
+    fn mines_queued_transfers_into_one_block() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+
+        assert!(node.add_to_mempool(sign_transfer(&private_key, 1, 2, 10, 0, 0)));
+        assert!(node.add_to_mempool(sign_transfer(&private_key, 1, 2, 20, 0, 1)));
+        assert_eq!(node.accounts[&1].balance, 100);
+
+        node.block_reward = 0;
+        let block = node.mine_pending().unwrap();
+
+        assert_eq!(
+            block.data,
+            Data::Batch(vec![
+                sign_transfer(&private_key, 1, 2, 10, 0, 0),
+                sign_transfer(&private_key, 1, 2, 20, 0, 1),
+            ])
+        );
+        assert_eq!(node.blocks.len(), 2);
+        assert!(node.mempool.is_empty());
+        assert_eq!(node.accounts[&1].balance, 70);
+        assert_eq!(node.accounts[&2].balance, 30);
+        assert!(node.mine_pending().is_none());
+    }
+
+    #[test]
+    fn mines_highest_fee_transfers_up_to_block_limit() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        node.block_reward = 0;
+        node.max_txs_per_block = 2;
+        let mut senders = vec![(0, GENESIS_PRIVATE_KEY), (1, private_key)];
+        for _ in 0..3 {
+            let (account, private_key) = node.add_account();
+            node.accounts.get_mut(&account.address).unwrap().balance = 100;
+            senders.push((account.address, private_key));
+        }
+        let transfers: Vec<Data> = senders
+            .iter()
+            .zip([3, 1, 5, 2, 4])
+            .map(|((sender, private_key), fee)| sign_transfer(private_key, *sender, 2, 10, fee, 0))
+            .collect();
+        for transfer in &transfers {
+            assert!(node.add_to_mempool(transfer.clone()));
+        }
+
+        let block = node.mine_pending().unwrap();
+
+        assert_eq!(
+            block.data,
+            Data::Batch(vec![
+                Data::Reward(GENESIS_ADDRESS, 9),
+                transfers[2].clone(),
+                transfers[4].clone(),
+            ])
+        );
+        assert_eq!(node.mempool.len(), 3);
+        assert!([0, 1, 3]
+            .iter()
+            .all(|&i| node.mempool.contains(&transfers[i])));
+    }
+
+    #[test]
+    fn requeues_transfers_of_dropped_block() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        let first = sign_transfer(&private_key, 1, 2, 10, 0, 0);
+        let second = sign_transfer(&private_key, 1, 2, 20, 0, 1);
+        assert!(node.add_to_mempool(first.clone()));
+        assert!(node.add_to_mempool(second.clone()));
+
+        let data = node.take_pending().unwrap();
+        assert!(node.mempool.is_empty());
+        assert!(node.add_to_mempool(sign_transfer(&private_key, 1, 2, 30, 0, 0)));
+
+        // The transfer queued meanwhile reuses a nonce of the dropped block and is dropped.
+        node.requeue(data);
+
+        assert_eq!(node.mempool, vec![first, second]);
+    }
+
+    #[test]
+    fn requeues_transfers_of_rejected_mined_block() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender_block(pub_key, 100);
+        let transfer = sign_transfer(&private_key, 1, 2, 10, 0, 0);
+        assert!(node.add_to_mempool(transfer.clone()));
+        // Every block is over the size limit, so the mined one is not added.
+        node.max_block_bytes = 1;
+
+        assert_eq!(node.mine_pending(), None);
+        assert_eq!(node.height(), Some(1));
+        assert_eq!(node.mempool, vec![transfer]);
+    }
+
+    #[test]
+    fn does_not_queue_invalid_transfer() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let (other_private_key, _) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+
+        assert!(node.add_to_mempool(sign_transfer(&private_key, 1, 2, 60, 0, 0)));
+        assert!(!node.add_to_mempool(sign_transfer(&private_key, 1, 2, 60, 0, 1)));
+        assert!(!node.add_to_mempool(sign_transfer(&other_private_key, 1, 2, 10, 0, 1)));
+
+        assert_eq!(node.mempool.len(), 1);
+        assert_eq!(node.accounts[&1].balance, 100);
+    }
+
+    fn get_batch_block(node: &Node, transfers: Vec<Data>) -> Block {
+        let latest_block = node.get_last_block();
+        Block::new(
+            latest_block.id + 1,
+            latest_block.hash.clone(),
+            Data::Batch(transfers),
+            node.compute_difficulty(),
+        )
+    }
+
+    #[test]
+    fn verifies_batch_signatures_up_front() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let (new_private_key, new_pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        let new_account = Account {
+            address: 3,
+            balance: INIT_BALANCE,
+            pub_key: new_pub_key,
+            nonce: 0,
+        };
+        let mut items = vec![
+            sign_transfer(&private_key, 1, 2, 10, 0, 0),
+            Data::Account(new_account),
+            sign_transfer(&private_key, 1, 3, 20, 0, 1),
+            sign_transfer(&new_private_key, 3, 2, 5, 0, 0),
+        ];
+        assert_eq!(node.verify_batch(&items), Ok(()));
+
+        items.push(sign_transfer(&new_private_key, 1, 2, 10, 0, 2));
+        items.push(sign_transfer(&private_key, 3, 2, 5, 0, 1));
+        assert_eq!(node.verify_batch(&items), Err(4));
+        // Sender 3 is unknown without the earlier items, the transfer is rejected when applied.
+        assert_eq!(node.verify_batch(&items[5..]), Ok(()));
+
+        let accounts = node.accounts.clone();
+        let block = get_batch_block(&node, items);
+        assert_eq!(
+            node.try_add_block(block),
+            Err(BlockError::InvalidTransfer(TransferError::BadSignature))
+        );
+        assert_eq!(node.accounts, accounts);
+        assert_eq!(node.blocks.len(), 1);
+    }
+
+    #[test]
+    fn rejects_transfers_and_blocks_of_other_chains() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let transfer = sign_transfer_on_chain(1, &private_key, 1, 2, 10, 0, 0);
+        let mut node = get_node_with_sender(pub_key, 100);
+        node.chain_id = 2;
+        assert_eq!(
+            node.try_add_transfer(&transfer),
+            Err(TransferError::BadSignature)
+        );
+        node.chain_id = 0;
+        assert_eq!(
+            node.try_add_transfer(&transfer),
+            Err(TransferError::BadSignature)
+        );
+        node.chain_id = 1;
+        assert_eq!(node.try_add_transfer(&transfer), Ok(()));
+        node.allow_implicit_accounts = true;
+        let (_, receiver_pub_key) = crypto::generate_keypair();
+        for (chain_id, result) in [(0, Err(TransferError::BadSignature)), (1, Ok(()))] {
+            let transfer = sign_transfer_to_new_account(
+                chain_id,
+                &private_key,
+                1,
+                9,
+                receiver_pub_key,
+                10,
+                0,
+                1,
+            );
+            assert_eq!(node.try_add_transfer(&transfer), result);
+        }
+
+        let mut node = get_node_with_sender(pub_key, 100);
+        let latest_block = node.get_last_block();
+        let block = Block::new_with_hasher(
+            latest_block.id + 1,
+            latest_block.hash.clone(),
+            Data::Batch(vec![transfer]),
+            node.compute_difficulty(),
+            &Sha256Hasher,
+            1,
+        );
+        node.chain_id = 2;
+        assert_eq!(
+            node.try_add_block(block.clone()),
+            Err(BlockError::HashMismatch)
+        );
+        node.chain_id = 1;
+        assert_eq!(node.try_add_block(block), Ok(()));
+        assert_eq!(node.balance_of(2), Some(INIT_BALANCE + 10));
+    }
+
+    #[test]
+    fn applies_valid_batch() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        let block = get_batch_block(
+            &node,
+            vec![
+                sign_transfer(&private_key, 1, 2, 10, 0, 0),
+                sign_transfer(&private_key, 1, 2, 20, 0, 1),
+                sign_transfer(&private_key, 1, 2, 30, 0, 2),
+            ],
+        );
+
+        assert!(node.try_add_block(block).is_ok());
+        assert_eq!(node.accounts[&1].balance, 40);
+        assert_eq!(node.accounts[&2].balance, 60);
+    }
+
+    #[test]
+    fn rejects_batch_with_invalid_transfer() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        let block = get_batch_block(
+            &node,
+            vec![
+                sign_transfer(&private_key, 1, 2, 10, 0, 0),
+                sign_transfer(&private_key, 1, 2, 20, 0, 1),
+                sign_transfer(&private_key, 1, 2, 80, 0, 2),
+            ],
+        );
+
+        assert_eq!(
+            node.try_add_block(block),
+            Err(BlockError::InvalidTransfer(
+                TransferError::InsufficientBalance
+            ))
+        );
+        assert_eq!(node.blocks.len(), 1);
+        assert_eq!(node.accounts[&1].balance, 100);
+        assert_eq!(node.accounts[&2].balance, INIT_BALANCE);
+    }
+
+    #[test]
+    fn failed_transfer_leaves_balances_unchanged() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        let accounts = node.accounts.clone();
+        let latest_block = node.get_last_block();
+        let block = Block::new(
+            latest_block.id + 1,
+            latest_block.hash.clone(),
+            sign_transfer(&private_key, 1, 3, 10, 0, 0),
+            node.difficulty,
+        );
+
+        assert!(node.try_add_block(block).is_err());
+        assert!(node
+            .try_add_block(get_batch_block(
+                &node,
+                vec![
+                    sign_transfer(&private_key, 1, 2, 50, 0, 0),
+                    sign_transfer(&private_key, 1, 3, 10, 0, 1),
+                ],
+            ))
+            .is_err());
+        assert_eq!(node.accounts, accounts);
+        assert_eq!(node.blocks.len(), 1);
+    }
+
+    #[test]
+    fn credits_block_reward_to_miner() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        node.miner_address = 2;
+
+        assert!(node.add_to_mempool(sign_transfer(&private_key, 1, 2, 10, 0, 0)));
+        let block = node.mine_pending().unwrap();
+
+        assert_eq!(
+            block.data,
+            Data::Batch(vec![
+                Data::Reward(2, DEFAULT_BLOCK_REWARD),
+                sign_transfer(&private_key, 1, 2, 10, 0, 0),
+            ])
+        );
+        assert_eq!(node.accounts[&1].balance, 90);
+        assert_eq!(node.accounts[&2].balance, 10 + DEFAULT_BLOCK_REWARD);
+    }
+
+    #[test]
+    fn rejects_reward_overflowing_miner_balance() {
+        let mut node = get_node_with_sender(PublicKey([0x11; 32]), 100);
+        node.miner_address = 1;
+        node.accounts.get_mut(&1).unwrap().balance = u64::MAX - 1;
+        let accounts = node.accounts.clone();
+        let tip = node.get_last_block().clone();
+        let data = node.with_reward(Data::Batch(vec![]));
+        let block = Block::new(tip.id + 1, tip.hash, data, node.compute_difficulty());
+
+        assert_eq!(
+            node.try_add_block(block),
+            Err(BlockError::BalanceOverflow(1))
+        );
+        assert_eq!(node.accounts, accounts);
+        assert_eq!(node.get_last_block().id, tip.id);
+    }
+
+    #[test]
+    fn halves_block_reward_every_interval() {
+        let mut node = get_node_with_sender(PublicKey([0x11; 32]), 100);
+        assert_eq!(node.block_reward_at(u64::MAX), DEFAULT_BLOCK_REWARD);
+
+        node.halving_interval = Some(3);
+        assert_eq!(node.block_reward_at(2), DEFAULT_BLOCK_REWARD);
+        assert_eq!(node.block_reward_at(3), DEFAULT_BLOCK_REWARD / 2);
+        assert_eq!(node.block_reward_at(5), DEFAULT_BLOCK_REWARD / 2);
+        assert_eq!(node.block_reward_at(6), DEFAULT_BLOCK_REWARD / 4);
+        assert_eq!(node.block_reward_at(3 * 63), 0);
+        assert_eq!(node.block_reward_at(u64::MAX), 0);
+
+        node.miner_address = 2;
+        while node.get_last_block().id < 2 {
+            let block = get_batch_block(&node, vec![]);
+            assert!(node.try_add_block(block).is_ok());
+        }
+        let latest_block = node.get_last_block().clone();
+        let block = get_batch_block(&node, vec![Data::Reward(2, DEFAULT_BLOCK_REWARD)]);
+        assert_eq!(
+            node.is_block_valid(&block, &latest_block, node.difficulty),
+            Err(BlockError::RewardTooHigh)
+        );
+
+        let block = get_batch_block(&node, vec![Data::Reward(2, DEFAULT_BLOCK_REWARD / 2)]);
+        assert!(node.try_add_block(block).is_ok());
+        assert_eq!(
+            node.accounts[&2].balance,
+            INIT_BALANCE + DEFAULT_BLOCK_REWARD / 2
+        );
+        assert_eq!(
+            node.with_reward(Data::Batch(vec![])),
+            Data::Batch(vec![Data::Reward(2, DEFAULT_BLOCK_REWARD / 2)])
+        );
+    }
+
+    #[test]
+    fn does_not_validate_block_with_inflated_reward() {
+        let mut node = get_node_with_sender(PublicKey([0x11; 32]), 100);
+        let latest_block = node.get_last_block().clone();
+        let block = get_batch_block(&node, vec![Data::Reward(2, DEFAULT_BLOCK_REWARD + 1)]);
+
+        assert_eq!(
+            node.is_block_valid(&block, &latest_block, node.difficulty),
+            Err(BlockError::RewardTooHigh)
+        );
+        assert!(node.try_add_block(block).is_err());
+        assert_eq!(node.accounts[&2].balance, INIT_BALANCE);
+    }
+
+    #[test]
+    fn does_not_accept_transfer_without_balance_for_fee() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+
+        assert_eq!(
+            node.try_add_transfer(&sign_transfer(&private_key, 1, 2, 95, 10, 0)),
+            Err(TransferError::InsufficientBalance)
+        );
+        assert_eq!(
+            node.try_add_transfer(&sign_transfer(&private_key, 1, 2, u64::MAX, 1, 0)),
+            Err(TransferError::AmountOverflow)
+        );
+        assert!(node
+            .try_add_transfer(&sign_transfer(&private_key, 1, 2, 90, 10, 0))
+            .is_ok());
+        assert_eq!(node.accounts[&1].balance, 0);
+        assert_eq!(node.accounts[&2].balance, 90);
+    }
+
+    #[test]
+    fn conserves_total_supply_across_transfers() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender_block(pub_key, 100);
+        let supply = node.total_supply();
+        assert_eq!(supply, u128::from(GENESIS_SUPPLY));
+
+        node.block_reward = 0;
+        for nonce in 0..3 {
+            assert!(node.add_to_mempool(sign_transfer(&private_key, 1, 2, 10, 2, nonce)));
+        }
+        assert!(node.add_to_mempool(sign_transfer(&GENESIS_PRIVATE_KEY, 0, 1, 500, 1, 1)));
+        assert!(node.mine_pending().is_some());
+        assert_eq!(node.total_supply(), supply);
+
+        node.block_reward = DEFAULT_BLOCK_REWARD;
+        assert!(node.add_to_mempool(sign_transfer(&private_key, 1, 2, 10, 3, 3)));
+        assert!(node.mine_pending().is_some());
+        assert_eq!(
+            node.total_supply(),
+            supply + u128::from(DEFAULT_BLOCK_REWARD)
+        );
+
+        // An account cannot be announced with coins of its own.
+        let supply = node.total_supply();
+        let (_, pub_key) = crypto::generate_keypair();
+        let funded = get_batch_block(
+            &node,
+            vec![Data::Account(Account {
+                balance: u64::MAX,
+                ..Account::implicit(3, pub_key)
+            })],
+        );
+        assert_eq!(
+            node.try_add_block(funded),
+            Err(BlockError::FundedAccount(3))
+        );
+        assert_eq!(node.balance_of(3), None);
+        assert_eq!(node.total_supply(), supply);
+    }
+
+    #[test]
+    fn detects_overflowing_genesis_supply() {
+        let mut config = get_genesis_config(u64::MAX / 2);
+        assert_eq!(config.total_supply(), Some(u64::MAX - 1));
+        config.accounts[1].balance += 2;
+        assert_eq!(config.total_supply(), None);
+        assert_eq!(
+            Node::new().genesis_from_config(&config),
+            Err(GenesisError::SupplyOverflow)
+        );
+        assert_eq!(
+            Node::new().set_genesis(&config),
+            Err(GenesisError::SupplyOverflow)
+        );
+        assert_eq!(
+            GenesisConfig::default().total_supply(),
+            Some(GENESIS_SUPPLY)
+        );
+    }
+
+    #[test]
+    fn pays_summed_fees_to_miner() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        node.miner_address = 2;
+
+        assert!(node.add_to_mempool(sign_transfer(&private_key, 1, 2, 10, 1, 0)));
+        assert!(node.add_to_mempool(sign_transfer(&GENESIS_PRIVATE_KEY, 0, 2, 10, 5, 0)));
+        let block = node.mine_pending().unwrap();
+
+        assert_eq!(
+            block.data,
+            Data::Batch(vec![
+                Data::Reward(2, DEFAULT_BLOCK_REWARD + 6),
+                sign_transfer(&GENESIS_PRIVATE_KEY, 0, 2, 10, 5, 0),
+                sign_transfer(&private_key, 1, 2, 10, 1, 0),
+            ])
+        );
+        assert_eq!(node.accounts[&1].balance, 89);
+        assert_eq!(node.accounts[&2].balance, 20 + DEFAULT_BLOCK_REWARD + 6);
+    }
+
+    fn get_node_with_block_interval(interval: i64, count: usize) -> Node {
+        let mut node = Node::new();
+        node.genesis();
+        for id in 1..=count as u64 {
+            let previous = node.get_last_block();
+            let block = Block {
+                id,
+                previous_hash: previous.hash.clone(),
+                timestamp: previous.timestamp + interval,
+                ..previous.clone()
+            };
+            node.blocks.push(block);
+        }
+        node
+    }
+
+    #[test]
+    fn keeps_difficulty_until_first_retarget() {
+        let node = get_node_with_block_interval(1, 2 * RETARGET_WINDOW - 2);
+
+        assert_eq!(node.compute_difficulty(), DEFAULT_DIFFICULTY);
+    }
+
+    #[test]
+    fn raises_difficulty_for_fast_blocks() {
+        let node = get_node_with_block_interval(1, 2 * RETARGET_WINDOW - 1);
+        assert_eq!(node.compute_difficulty(), DEFAULT_DIFFICULTY + 1);
+
+        let node = get_node_with_block_interval(1, 3 * RETARGET_WINDOW - 1);
+        assert_eq!(node.compute_difficulty(), DEFAULT_DIFFICULTY + 2);
+    }
+
+    #[test]
+    fn lowers_difficulty_for_slow_blocks() {
+        let node = get_node_with_block_interval(100, 2 * RETARGET_WINDOW - 1);
+        assert_eq!(node.compute_difficulty(), DEFAULT_DIFFICULTY - 1);
+
+        let node = get_node_with_block_interval(100, 3 * RETARGET_WINDOW - 1);
+        assert_eq!(node.compute_difficulty(), 1);
+    }
+
+    #[test]
+    fn keeps_difficulty_for_blocks_near_target_time() {
+        let node =
+            get_node_with_block_interval(DEFAULT_TARGET_BLOCK_TIME_SECS, 3 * RETARGET_WINDOW);
+
+        assert_eq!(node.compute_difficulty(), DEFAULT_DIFFICULTY);
+    }
+
+    #[test]
+    fn validates_block_against_difficulty_at_its_height() {
+        let mut node = get_node_with_block_interval(1, 2 * RETARGET_WINDOW - 1);
+        let latest_block = node.get_last_block();
+        let easy_block = loop {
+            let block = Block::new(
+                latest_block.id + 1,
+                latest_block.hash.clone(),
+                Data::Batch(vec![]),
+                DEFAULT_DIFFICULTY,
+            );
+            if !hash_to_binary_representation(&hex::decode(&block.hash).unwrap())
+                .starts_with(&difficulty_prefix(DEFAULT_DIFFICULTY + 1))
+            {
+                break block;
+            }
+        };
+
+        assert!(node.try_add_block(easy_block).is_err());
+        let block = get_batch_block(&node, vec![]);
+        assert!(node.try_add_block(block).is_ok());
+    }
+
+    #[test]
+    fn validates_chain_across_retargets() {
+        let mut node = Node::new();
+        node.genesis();
+        for _ in 0..2 * RETARGET_WINDOW + 1 {
+            let block = get_batch_block(&node, vec![]);
+            assert!(node.try_add_block(block).is_ok());
+        }
+        assert_eq!(node.compute_difficulty(), DEFAULT_DIFFICULTY + 1);
+        assert!(node.is_chain_valid(&node.blocks));
+        assert_eq!(
+            node.first_invalid_block_from(&node.blocks, RETARGET_WINDOW + 3),
+            None
+        );
+
+        let mut chain = node.blocks[..2 * RETARGET_WINDOW].to_vec();
+        let latest_block = chain.last().unwrap().clone();
+        let easy_block = loop {
+            let block = Block::new(
+                latest_block.id + 1,
+                latest_block.hash.clone(),
+                Data::Batch(vec![]),
+                DEFAULT_DIFFICULTY,
+            );
+            if !hash_to_binary_representation(&hex::decode(&block.hash).unwrap())
+                .starts_with(&difficulty_prefix(DEFAULT_DIFFICULTY + 1))
+            {
+                break block;
+            }
+        };
+        chain.push(easy_block);
+        assert_eq!(node.first_invalid_block(&chain), Some(2 * RETARGET_WINDOW));
+        assert_eq!(
+            node.first_invalid_block_from(&chain, RETARGET_WINDOW + 3),
+            Some(2 * RETARGET_WINDOW)
+        );
+    }
+
+    #[test]
+    fn computes_merkle_root_over_batch() {
+        let leaf =
+            |data: &Data| Sha256::digest(serde_json::to_string(data).unwrap().as_bytes()).to_vec();
+        let pair = |left: &[u8], right: &[u8]| Sha256::digest(&[left, right].concat()).to_vec();
+        let items: Vec<Data> = (1..=3).map(|amount| Data::Reward(1, amount)).collect();
+
+        let left = pair(&leaf(&items[0]), &leaf(&items[1]));
+        let right = pair(&leaf(&items[2]), &leaf(&items[2]));
+
+        assert_eq!(
+            Block::merkle_root(&Data::Batch(items.clone()), &Sha256Hasher),
+            hex::encode(pair(&left, &right))
+        );
+        assert_eq!(
+            Block::merkle_root(&items[0], &Sha256Hasher),
+            hex::encode(leaf(&items[0]))
+        );
+    }
+
+    #[test]
+    fn reordering_batch_invalidates_block() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        let transfers = vec![
+            sign_transfer(&private_key, 1, 2, 10, 0, 0),
+            sign_transfer(&private_key, 1, 2, 20, 0, 1),
+        ];
+        let mut block = get_batch_block(&node, transfers.clone());
+
+        let reordered = Data::Batch(transfers.into_iter().rev().collect());
+        assert_ne!(
+            Block::merkle_root(&reordered, &Sha256Hasher),
+            block.merkle_root
+        );
+
+        block.data = reordered;
+        assert_eq!(
+            node.try_add_block(block),
+            Err(BlockError::MerkleRootMismatch)
+        );
+    }
+
+    #[test]
+    fn does_not_choose_between_two_invalid_chains() {
+        let mut node = Node::new();
+        node.genesis();
+        let mut local = vec![get_genesis_block(), get_first_block()];
+        local[0].nonce += 1;
+        let mut remote = vec![get_genesis_block(), get_first_block()];
+        remote[0].id += 1;
+
+        assert_eq!(
+            node.choose_chain(local, remote),
+            Err(ChainSelectionError::BothInvalid)
+        );
+    }
+
+    #[test]
+    fn does_not_import_chain_with_forged_genesis() {
+        let mut node = Node::new();
+        node.genesis();
+        let mut forged_genesis = get_genesis_block();
+        forged_genesis.data = Data::Account(Account {
+            address: GENESIS_ADDRESS,
+            balance: u64::MAX,
+            pub_key: PublicKey([0x66; 32]),
+            nonce: 0,
+        });
+        let mut chain = vec![forged_genesis];
+        for address in [7, 8] {
+            let latest_block = chain.last().unwrap();
+            chain.push(Block::new(
+                latest_block.id + 1,
+                latest_block.hash.clone(),
+                Data::Account(Account {
+                    address,
+                    balance: INIT_BALANCE,
+                    pub_key: PublicKey([0x77; 32]),
+                    nonce: 0,
+                }),
+                node.difficulty,
+            ));
+        }
+
+        assert!(!node.import_chain(chain));
+        assert_eq!(node.blocks, vec![get_genesis_block()]);
+        assert_eq!(node.pub_keys[&GENESIS_ADDRESS], GENESIS_PUB_KEY);
+    }
+
+    fn get_block_at(node: &Node, timestamp: i64) -> Block {
+        let latest_block = node.get_last_block();
+        let data = Data::Batch(vec![]);
+        let (nonce, hash) = Block::mine_block_parallel(
+            &Sha256Hasher,
+            0,
+            None,
+            latest_block.id + 1,
+            timestamp,
+            &latest_block.hash,
+            &data,
+            node.compute_difficulty(),
+            u64::MAX,
+            1,
+            DEFAULT_MINING_LOG_INTERVAL,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        Block {
+            version: CANONICAL_BLOCK_VERSION,
+            id: latest_block.id + 1,
+            hash,
+            previous_hash: latest_block.hash.clone(),
+            timestamp,
+            merkle_root: Block::merkle_root(&data, &Sha256Hasher),
+            data,
+            nonce,
+            miner: None,
+        }
+    }
+
+    #[test]
+    fn does_not_validate_block_before_parent() {
+        let mut node = Node::new();
+        node.genesis();
+        let parent_timestamp = node.get_last_block().timestamp;
+
+        assert_eq!(
+            node.try_add_block(get_block_at(&node, parent_timestamp - 1)),
+            Err(BlockError::TimestampBeforeParent)
+        );
+        assert!(node
+            .try_add_block(get_block_at(&node, parent_timestamp))
+            .is_ok());
+    }
+
+    #[test]
+    fn does_not_validate_block_far_in_future() {
+        let mut node = Node::new();
+        node.genesis();
+        let now = Utc::now().timestamp();
+
+        assert_eq!(
+            node.try_add_block(get_block_at(&node, now + 365 * 24 * 60 * 60)),
+            Err(BlockError::TimestampInFuture)
+        );
+        node.max_future_drift_secs = 0;
+        assert_eq!(
+            node.try_add_block(get_block_at(&node, now + 60)),
+            Err(BlockError::TimestampInFuture)
+        );
+        assert!(node.try_add_block(get_block_at(&node, now)).is_ok());
+        assert_eq!(node.blocks.len(), 2);
+    }
+
+    #[test]
+    fn looks_up_blocks_by_id_and_hash() {
+        let mut node = Node::new();
+        node.genesis();
+        let block = get_batch_block(&node, vec![]);
+        assert!(node.try_add_block(block.clone()).is_ok());
+        let genesis = node.blocks[0].clone();
+
+        assert_eq!(node.get_block_by_id(0), Some(&genesis));
+        assert_eq!(node.get_block_by_id(1), Some(&block));
+        assert_eq!(node.get_block_by_id(2), None);
+        assert_eq!(node.get_block_by_id(u64::MAX), None);
+        assert_eq!(node.get_block_by_hash(&genesis.hash), Some(&genesis));
+        assert_eq!(node.get_block_by_hash(&block.hash), Some(&block));
+        assert_eq!(node.get_block_by_hash("unknown"), None);
+    }
+
+    #[test]
+    fn validates_block_at_size_limit() {
+        let mut node = Node::new();
+        node.genesis();
+        let block = get_batch_block(&node, vec![]);
+        node.max_block_bytes = serde_json::to_vec(&block).unwrap().len();
+
+        assert!(node.try_add_block(block).is_ok());
+    }
+
+    #[test]
+    fn does_not_validate_block_over_size_limit() {
+        let mut node = Node::new();
+        node.genesis();
+        let block = get_batch_block(&node, vec![]);
+        let size = serde_json::to_vec(&block).unwrap().len();
+        node.max_block_bytes = size - 1;
+
+        assert_eq!(node.try_add_block(block), Err(BlockError::TooLarge(size)));
+        assert_eq!(node.blocks.len(), 1);
+    }
+
+    #[test]
+    fn does_not_accept_replayed_transfer() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        let transfer = sign_transfer(&private_key, 1, 2, 10, 0, 0);
+
+        assert!(node.try_add_transfer(&transfer).is_ok());
+        assert_eq!(
+            node.try_add_transfer(&transfer),
+            Err(TransferError::WrongNonce {
+                expected: 1,
+                got: 0
+            })
+        );
+        assert_eq!(node.accounts[&1].balance, 90);
+        assert_eq!(node.accounts[&1].nonce, 1);
+    }
+
+    #[test]
+    fn applies_transfers_in_nonce_order() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+
+        assert_eq!(
+            node.try_add_transfer(&sign_transfer(&private_key, 1, 2, 10, 0, 1)),
+            Err(TransferError::WrongNonce {
+                expected: 0,
+                got: 1
+            })
+        );
+        assert!(node
+            .try_add_transfer(&sign_transfer(&private_key, 1, 2, 10, 0, 0))
+            .is_ok());
+        assert!(node
+            .try_add_transfer(&sign_transfer(&private_key, 1, 2, 10, 0, 1))
+            .is_ok());
+        assert_eq!(node.accounts[&1].balance, 80);
+        assert_eq!(node.next_nonce(1), Some(2));
+    }
+
+    #[test]
+    fn mines_transfer_after_the_one_with_lower_nonce() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        node.block_reward = 0;
+
+        assert!(node.add_to_mempool(sign_transfer(&private_key, 1, 2, 10, 1, 0)));
+        assert_eq!(node.next_nonce(1), Some(1));
+        assert!(node.add_to_mempool(sign_transfer(&private_key, 1, 2, 10, 5, 1)));
+        let block = node.mine_pending().unwrap();
+
+        assert_eq!(
+            block.data,
+            Data::Batch(vec![
+                Data::Reward(GENESIS_ADDRESS, 6),
+                sign_transfer(&private_key, 1, 2, 10, 1, 0),
+                sign_transfer(&private_key, 1, 2, 10, 5, 1),
+            ])
+        );
+        assert!(node.mempool.is_empty());
+    }
+
+    #[test]
+    fn queries_balances_and_history() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        node.block_reward = 0;
+
+        assert!(node.add_to_mempool(sign_transfer(&private_key, 1, 2, 10, 0, 0)));
+        let first = node.mine_pending().unwrap();
+        let (account, _) = Account::new();
+        let latest_block = node.get_last_block();
+        let account_block = Block::new(
+            latest_block.id + 1,
+            latest_block.hash.clone(),
+            Data::Account(account),
+            node.compute_difficulty(),
+        );
+        assert!(node.try_add_block(account_block).is_ok());
+        assert!(node.add_to_mempool(sign_transfer(&GENESIS_PRIVATE_KEY, 0, 2, 5, 0, 0)));
+        let second = node.mine_pending().unwrap();
+
+        assert_eq!(node.balance_of(1), Some(90));
+        assert_eq!(node.balance_of(2), Some(15));
+        assert_eq!(node.balance_of(3), None);
+        assert_eq!(node.history_of(1), vec![&first]);
+        assert_eq!(node.history_of(2), vec![&first, &second]);
+        assert_eq!(node.history_of(GENESIS_ADDRESS), vec![&second]);
+        assert!(node.history_of(3).is_empty());
+    }
+
+    fn get_node_with_sender_block(pub_key: PublicKey, balance: u64) -> Node {
+        let mut node = Node::new();
+        node.genesis();
+        let latest_block = node.get_last_block();
+        let account_block = Block::new(
+            latest_block.id + 1,
+            latest_block.hash.clone(),
+            Data::Batch(vec![
+                Data::Account(Account::implicit(1, pub_key)),
+                Data::Account(Account::implicit(2, PublicKey([0x22; 32]))),
+                sign_transfer(&GENESIS_PRIVATE_KEY, GENESIS_ADDRESS, 1, balance, 0, 0),
+            ]),
+            node.compute_difficulty(),
+        );
+        assert!(node.try_add_block(account_block).is_ok());
+        node
+    }
+
+    struct MaxAmountPolicy(u64);
+
+    impl BlockPolicy for MaxAmountPolicy {
+        fn accept(&self, block: &Block, _node: &Node) -> Result<(), PolicyError> {
+            let items = match &block.data {
+                Data::Batch(items) => &items[..],
+                data => std::slice::from_ref(data),
+            };
+            match items
+                .iter()
+                .filter_map(Data::amount)
+                .find(|amount| *amount > self.0)
+            {
+                Some(amount) => Err(PolicyError(format!(
+                    "transfer of {} is over {}",
+                    amount, self.0
+                ))),
+                None => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_blocks_not_accepted_by_policy() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let sender = get_node_with_sender_block(pub_key, 50);
+        let mut node = Node::with_policy(Arc::new(MaxAmountPolicy(50)));
+        assert!(node.adopt_chain(sender.blocks.clone()));
+
+        let over_limit = get_batch_block(&node, vec![sign_transfer(&private_key, 1, 2, 60, 0, 0)]);
+        assert_eq!(
+            node.try_add_block(over_limit),
+            Err(BlockError::Rejected(PolicyError(
+                "transfer of 60 is over 50".to_string()
+            )))
+        );
+        assert_eq!(node.height(), Some(1));
+        assert_eq!(node.balance_of(1), Some(50));
+
+        let within_limit =
+            get_batch_block(&node, vec![sign_transfer(&private_key, 1, 2, 50, 0, 0)]);
+        assert!(node.try_add_block(within_limit).is_ok());
+        assert_eq!(node.balance_of(2), Some(50));
+    }
+
+    #[test]
+    fn prunes_keeping_state_and_difficulty() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender_block(pub_key, 100);
+        for nonce in 0..23 {
+            let block =
+                get_batch_block(&node, vec![sign_transfer(&private_key, 1, 2, 1, 0, nonce)]);
+            assert!(node.try_add_block(block).is_ok());
+        }
+        let accounts = node.accounts.clone();
+        let difficulty = node.compute_difficulty();
+        let tip = node.get_last_block().clone();
+
+        assert_eq!(node.prune_below(23), Some(20));
+
+        assert_eq!(node.blocks.len(), 5);
+        assert_eq!(node.checkpoint().unwrap().block.id, 20);
+        assert_eq!(node.accounts, accounts);
+        assert_eq!(node.replay(), Ok(accounts));
+        assert_eq!(node.compute_difficulty(), difficulty);
+        assert!(node.verify().is_valid());
+        assert_eq!(node.get_block_by_id(24), Some(&tip));
+        assert_eq!(node.get_block_by_id(5), None);
+        assert_eq!(node.prune_below(29), None);
+    }
+
+    #[test]
+    fn verifies_pruned_chain_by_index() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender_block(pub_key, 100);
+        for nonce in 0..23 {
+            let block =
+                get_batch_block(&node, vec![sign_transfer(&private_key, 1, 2, 1, 0, nonce)]);
+            assert!(node.try_add_block(block).is_ok());
+        }
+        assert_eq!(node.prune_below(23), Some(20));
+        let overspending =
+            get_batch_block(&node, vec![sign_transfer(&private_key, 1, 2, 100, 0, 23)]);
+        node.blocks.push(overspending);
+
+        assert_eq!(node.replay(), Err(ReplayError::InvalidData(25)));
+        let report = node.verify();
+        assert_eq!(report.invalid_block, Some(5));
+        assert_eq!(node.blocks[5].id, 25);
+    }
+
+    #[test]
+    fn rolls_back_transfer_block() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender_block(pub_key, 100);
+        let accounts = node.accounts.clone();
+        let pub_keys = node.pub_keys.clone();
+        let block = get_batch_block(&node, vec![sign_transfer(&private_key, 1, 2, 30, 0, 0)]);
+        assert!(node.try_add_block(block.clone()).is_ok());
+        assert_eq!(node.balance_of(1), Some(70));
+
+        assert_eq!(node.rollback_last_block(), Some(block.clone()));
+        assert_eq!(node.accounts, accounts);
+        assert_eq!(node.pub_keys, pub_keys);
+        assert_eq!(node.height(), Some(1));
+        // The same transfer, with the same nonce, applies again.
+        assert!(node.try_add_block(block).is_ok());
+    }
+
+    #[test]
+    fn does_not_roll_back_genesis_block() {
+        let mut node = Node::new();
+        assert_eq!(node.rollback_last_block(), None);
+        node.genesis();
+        let account_block = get_first_block();
+        assert!(node.try_add_block(account_block.clone()).is_ok());
+
+        assert_eq!(node.rollback_last_block(), Some(account_block));
+        assert!(!node.accounts.contains_key(&1));
+        assert_eq!(node.accounts.len(), 1);
+        assert_eq!(node.rollback_last_block(), None);
+        assert_eq!(node.blocks, vec![get_genesis_block()]);
+    }
+
+    #[test]
+    fn connects_orphans_once_gap_is_filled() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut miner = get_node_with_sender_block(pub_key, 100);
+        let mut node = Node::new();
+        assert!(node.adopt_chain(miner.blocks.clone()));
+        for nonce in 0..5 {
+            let block =
+                get_batch_block(&miner, vec![sign_transfer(&private_key, 1, 2, 1, 0, nonce)]);
+            assert!(miner.try_add_block(block).is_ok());
+        }
+        let first_new = node.blocks.len();
+
+        for block in miner.blocks[first_new + 1..].iter().rev() {
+            assert!(node.add_orphan(block.clone()));
+        }
+        assert!(!node.add_orphan(miner.blocks[first_new + 2].clone()));
+        assert!(!node.add_orphan(miner.blocks[first_new].clone()));
+        assert_eq!(node.orphan_count(), 4);
+        assert_eq!(node.blocks.len(), first_new);
+
+        assert!(node.try_add_block(miner.blocks[first_new].clone()).is_ok());
+        assert_eq!(node.blocks, miner.blocks);
+        assert_eq!(node.accounts, miner.accounts);
+        assert_eq!(node.orphan_count(), 0);
+    }
+
+    #[test]
+    fn drops_furthest_orphans_when_pool_is_full() {
+        let mut miner = get_node_with_sender_block(crypto::generate_keypair().1, 100);
+        let mut node = Node::new();
+        assert!(node.adopt_chain(miner.blocks.clone()));
+        node.max_orphans = 2;
+        for _ in 0..4 {
+            assert!(miner.try_add_block(get_batch_block(&miner, vec![])).is_ok());
+        }
+        let first_new = node.blocks.len();
+
+        assert!(node.add_orphan(miner.blocks[first_new + 3].clone()));
+        assert!(node.add_orphan(miner.blocks[first_new + 2].clone()));
+        assert!(node.add_orphan(miner.blocks[first_new + 1].clone()));
+        assert_eq!(node.orphan_count(), 2);
+
+        assert!(node.try_add_block(miner.blocks[first_new].clone()).is_ok());
+        assert_eq!(node.blocks[..], miner.blocks[..first_new + 3]);
+        assert_eq!(node.orphan_count(), 0);
+
+        let mut forged = miner.get_last_block().clone();
+        forged.id += 1;
+        assert!(!node.add_orphan(forged));
+    }
+
+    #[test]
+    fn validates_new_blocks_against_checkpoint() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender_block(pub_key, 100);
+        for _ in 0..RETARGET_WINDOW {
+            assert!(node.try_add_block(get_batch_block(&node, vec![])).is_ok());
+        }
+        let mut peer = Node::new();
+        assert!(peer.adopt_chain(node.blocks.clone()));
+        assert_eq!(node.prune_below(10), Some(10));
+
+        let block = get_batch_block(&node, vec![sign_transfer(&private_key, 1, 2, 30, 0, 0)]);
+        let mut unlinked = block.clone();
+        unlinked.previous_hash = node.blocks[0].hash.clone();
+        assert_eq!(
+            node.try_add_block(unlinked),
+            Err(BlockError::WrongPreviousHash)
+        );
+        assert_eq!(node.try_add_block(block), Ok(()));
+        assert_eq!(node.balance_of(1), Some(70));
+
+        // A longer fork above the checkpoint is adopted from the full chain of a peer.
+        for _ in 0..2 {
+            assert!(peer.try_add_block(get_batch_block(&peer, vec![])).is_ok());
+        }
+        assert!(node.adopt_chain(peer.blocks.clone()));
+        assert_eq!(node.blocks[0].id, 10);
+        assert_eq!(node.get_last_block(), peer.get_last_block());
+        assert_eq!(node.balance_of(1), Some(100));
+
+        // A fork below the checkpoint is not, whatever its length.
+        let mut fork = get_node_with_sender_block(PublicKey([0x33; 32]), 100);
+        while fork.blocks.len() <= node.blocks.len() + 10 {
+            assert!(fork.try_add_block(get_batch_block(&fork, vec![])).is_ok());
+        }
+        assert!(!node.adopt_chain(fork.blocks.clone()));
+    }
+
+    #[test]
+    fn replays_accounts_from_blocks() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender_block(pub_key, 100);
+        let block = get_batch_block(
+            &node,
+            vec![
+                sign_transfer(&private_key, 1, 2, 30, 0, 0),
+                sign_transfer(&GENESIS_PRIVATE_KEY, GENESIS_ADDRESS, 1, 5, 0, 1),
+            ],
+        );
+        assert!(node.try_add_block(block).is_ok());
+
+        let accounts = node.replay().unwrap();
+
+        assert_eq!(accounts, node.accounts);
+        assert_eq!(accounts[&1].balance, 75);
+        assert_eq!(accounts[&1].nonce, 1);
+        assert_eq!(Node::new().replay(), Err(ReplayError::EmptyChain));
+    }
+
+    #[test]
+    fn snapshot_does_not_reflect_later_blocks() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender_block(pub_key, 100);
+        let snapshot = node.snapshot();
+        let shared = snapshot.clone();
+        let block = get_batch_block(&node, vec![sign_transfer(&private_key, 1, 2, 30, 0, 0)]);
+        assert!(node.try_add_block(block).is_ok());
+
+        assert_eq!(node.balance_of(1), Some(70));
+        assert_eq!(shared, snapshot);
+        assert_eq!(snapshot.height, Some(1));
+        assert_eq!(snapshot.tip_hash.as_ref(), Some(&node.blocks[1].hash));
+        assert_eq!(snapshot.balance_of(1), Some(100));
+        assert_eq!(snapshot.balance_of(2), Some(0));
+        assert_eq!(snapshot.balance_of(3), None);
+        assert_eq!(snapshot.accounts(), 3);
+        assert_eq!(node.snapshot().height, Some(2));
+        assert_eq!(Node::new().snapshot().height, None);
+    }
+
+    #[test]
+    fn confirmed_balance_lags_tip() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender_block(pub_key, 100);
+        for nonce in 0..3 {
+            let block =
+                get_batch_block(&node, vec![sign_transfer(&private_key, 1, 2, 10, 0, nonce)]);
+            assert!(node.try_add_block(block).is_ok());
+        }
+
+        assert_eq!(node.balance_of(1), Some(70));
+        assert_eq!(node.confirmed_balance_of(1, 0), Some(70));
+        assert_eq!(node.confirmed_balance_of(1, 1), Some(80));
+        assert_eq!(node.confirmed_balance_of(2, 2), Some(10));
+        assert_eq!(node.confirmed_balance_of(1, 3), Some(100));
+        assert_eq!(node.confirmed_balance_of(1, 4), None);
+        assert_eq!(node.confirmed_balance_of(1, 6), None);
+    }
+
+    #[test]
+    fn replays_state_at_height() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender_block(pub_key, 100);
+        let state_after_accounts = node.accounts.clone();
+        for nonce in 0..2 {
+            let block =
+                get_batch_block(&node, vec![sign_transfer(&private_key, 1, 2, 10, 0, nonce)]);
+            assert!(node.try_add_block(block).is_ok());
+        }
+
+        let state = node.state_at(1).unwrap();
+        assert_eq!(state, state_after_accounts);
+        assert_ne!(state, node.accounts);
+        assert_eq!(state[&1].balance, 100);
+        assert_eq!(node.state_at(3), Ok(node.accounts.clone()));
+        assert_eq!(node.state_at(0).unwrap().len(), 1);
+        assert_eq!(node.state_at(4), Err(ReplayError::UnknownHeight(4)));
+
+        // A cached state is returned without replaying the chain.
+        node.blocks[1].data = Data::Batch(vec![]);
+        assert_eq!(node.state_at(1), Ok(state));
+        assert_eq!(node.state_cache.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn verifies_local_chain() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender_block(pub_key, 100);
+        let block = get_batch_block(&node, vec![sign_transfer(&private_key, 1, 2, 30, 0, 0)]);
+        assert!(node.try_add_block(block).is_ok());
+
+        let report = node.verify();
+        assert!(report.is_valid());
+        assert_eq!(report.total_blocks, 3);
+        assert_eq!(report.total_accounts, 3);
+
+        node.accounts.get_mut(&2).unwrap().balance += 1;
+        let report = node.verify();
+        assert_eq!(report.invalid_block, None);
+        assert!(!report.balances_consistent);
+
+        node.blocks[2].timestamp += 1;
+        let report = node.verify();
+        assert!(!report.is_valid());
+        assert_eq!(report.invalid_block, Some(2));
+    }
+
+    #[test]
+    fn replay_rejects_overspending_chain() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender_block(pub_key, 100);
+        // Each transfer is affordable on its own, the second one overspends.
+        let first = get_batch_block(&node, vec![sign_transfer(&private_key, 1, 2, 60, 0, 0)]);
+        node.blocks.push(first);
+        let second = get_batch_block(&node, vec![sign_transfer(&private_key, 1, 2, 60, 0, 1)]);
+        node.blocks.push(second);
+
+        assert_eq!(node.replay(), Err(ReplayError::InvalidData(3)));
+
+        let mut other = Node::new();
+        other.genesis();
+        assert!(other.is_chain_valid(&node.blocks));
+        assert!(!other.adopt_chain(node.blocks.clone()));
+        assert_eq!(other.blocks.len(), 1);
+        assert_eq!(other.accounts.len(), 1);
+    }
+
+    #[test]
+    fn estimated_fee_rises_with_congestion() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 1000);
+        node.max_txs_per_block = 2;
+        node.min_fee_estimate = 1;
+        let mut estimates = vec![node.estimate_fee(1)];
+        for (nonce, fee) in [5, 3, 8, 1, 4, 6].into_iter().enumerate() {
+            let transfer = sign_transfer(&private_key, 1, 2, 10, fee, nonce as u64);
+            assert!(node.add_to_mempool(transfer));
+            estimates.push(node.estimate_fee(1));
+        }
+
+        assert_eq!(estimates, vec![1, 1, 4, 6, 6, 6, 7]);
+        assert_eq!(node.estimate_fee(0), 7);
+        assert_eq!(node.estimate_fee(2), 5);
+        assert_eq!(node.estimate_fee(3), 2);
+        assert_eq!(node.estimate_fee(4), 1);
+    }
+
+    #[test]
+    fn metrics_reflect_mined_chain() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender_block(pub_key, 100);
+        for nonce in 0..3 {
+            assert!(node.add_to_mempool(sign_transfer(&private_key, 1, 2, 10, 1, nonce)));
+            node.mine_pending().unwrap();
+        }
+        assert!(node.add_to_mempool(sign_transfer(&private_key, 1, 2, 10, 1, 3)));
+
+        let metrics = node.metrics();
+
+        assert_eq!(metrics.height, 4);
+        assert_eq!(metrics.accounts, 3);
+        assert_eq!(metrics.mempool, 1);
+        assert_eq!(metrics.difficulty, node.compute_difficulty());
+        assert_eq!(
+            metrics.last_block_timestamp,
+            Some(node.get_last_block().timestamp)
+        );
+        assert_eq!(metrics.total_transactions, 4);
+        assert_eq!(metrics.peers, 0);
+        assert_eq!(Node::new().metrics().last_block_timestamp, None);
+    }
+
+    #[test]
+    fn counts_transactions_per_second() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender_block(pub_key, 100);
+        for transfers in [
+            vec![sign_transfer(&private_key, 1, 2, 10, 0, 0)],
+            vec![
+                sign_transfer(&private_key, 1, 2, 10, 0, 1),
+                sign_transfer(&private_key, 1, 2, 10, 0, 2),
+            ],
+        ] {
+            let block = get_batch_block(&node, transfers);
+            assert!(node.try_add_block(block).is_ok());
+        }
+        let genesis_timestamp = node.blocks[0].timestamp;
+        node.blocks.last_mut().unwrap().timestamp = genesis_timestamp + 40;
+
+        assert_eq!(node.total_transactions(), 4);
+        assert_eq!(node.average_tps(), 0.1);
+
+        node.blocks.last_mut().unwrap().timestamp = genesis_timestamp;
+        assert_eq!(node.average_tps(), 0.0);
+        node.blocks.truncate(1);
+        assert_eq!(node.total_transactions(), 0);
+        assert_eq!(node.average_tps(), 0.0);
+        assert_eq!(Node::new().total_transactions(), 0);
+        assert_eq!(Node::new().average_tps(), 0.0);
+    }
+
+    #[test]
+    fn rejects_transfer_overflowing_receiver_balance() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        node.accounts.get_mut(&2).unwrap().balance = u64::MAX - 5;
+
+        assert_eq!(
+            node.try_add_transfer(&sign_transfer(&private_key, 1, 2, 10, 1, 0)),
+            Err(TransferError::ReceiverOverflow)
+        );
+        assert_eq!(node.accounts[&1].balance, 100);
+        assert_eq!(node.accounts[&1].nonce, 0);
+        assert_eq!(node.accounts[&2].balance, u64::MAX - 5);
+
+        assert!(node
+            .try_add_transfer(&sign_transfer(&private_key, 1, 2, 5, 1, 0))
+            .is_ok());
+        assert_eq!(node.accounts[&2].balance, u64::MAX);
+    }
+
+    #[test]
+    fn rolls_back_accounts_when_switching_forks() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender_block(pub_key, 100);
+        let mut fork = Node::new();
+        assert!(fork.adopt_chain(node.blocks.clone()));
+
+        assert!(node.add_to_mempool(sign_transfer(&private_key, 1, 2, 30, 0, 0)));
+        node.mine_pending().unwrap();
+        assert!(fork.add_to_mempool(sign_transfer(&private_key, 1, 2, 10, 0, 0)));
+        fork.mine_pending().unwrap();
+        assert!(fork.add_to_mempool(sign_transfer(&private_key, 1, 2, 5, 0, 1)));
+        fork.mine_pending().unwrap();
+        assert_eq!(node.accounts[&1].balance, 70);
+
+        assert!(node.adopt_chain(fork.blocks.clone()));
+
+        assert_eq!(node.blocks, fork.blocks);
+        assert_eq!(node.accounts, fork.accounts);
+        assert_eq!(node.accounts[&1].balance, 85);
+        assert_eq!(node.accounts[&1].nonce, 2);
+        assert_eq!(node.accounts[&2].balance, 15);
+        assert_eq!(node.recent_reorgs().back().unwrap().depth, 1);
+    }
+
+    #[test]
+    fn added_account_can_sign_transfers() {
+        let mut node = Node::new();
+        node.genesis();
+        let (account, private_key) = node.add_account();
+        let (receiver, _) = node.add_account();
+
+        assert_eq!(crypto::derive_public_key(&private_key), account.pub_key);
+        assert!(node
+            .try_add_transfer(&sign_transfer(
+                &private_key,
+                account.address,
+                receiver.address,
+                0,
+                0,
+                0
+            ))
+            .is_ok());
+        assert_eq!(node.accounts[&account.address].nonce, 1);
+    }
+
+    #[test]
+    fn publishes_accepted_blocks_to_subscribers() {
+        let mut node = Node::new();
+        node.genesis();
+        let mut events = node.subscribe_blocks();
+        let block = get_batch_block(&node, vec![]);
+        let invalid = get_batch_block(&node, vec![]);
+
+        assert!(node.try_add_block(block.clone()).is_ok());
+        assert!(node.try_add_block(invalid).is_err());
+        assert_eq!(events.try_recv(), Ok(block));
+        assert!(events.try_recv().is_err());
+
+        let mut other = Node::new();
+        other.genesis();
+        let mut adopted = other.subscribe_blocks();
+        assert!(other.adopt_chain(node.blocks.clone()));
+        assert_eq!(adopted.try_recv(), Ok(node.blocks[1].clone()));
+        assert!(adopted.try_recv().is_err());
+    }
+
+    #[test]
+    fn has_no_tip_before_genesis() {
+        let mut node = Node::new();
+        let block = Block {
+            id: 1,
+            ..get_genesis_block()
+        };
+
+        assert_eq!(node.tip(), None);
+        assert_eq!(node.height(), None);
+        assert_eq!(node.mine_pending(), None);
+        assert_eq!(
+            node.try_add_block(block),
+            Err(BlockError::WrongPreviousHash)
+        );
+
+        node.genesis();
+        assert_eq!(node.tip(), Some(&get_genesis_block()));
+        assert_eq!(node.height(), Some(0));
+    }
+
+    #[test]
+    fn does_not_accept_transfer_below_minimum() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        node.min_transfer = 10;
+        let accounts = node.accounts.clone();
+
+        assert_eq!(
+            node.try_add_transfer(&sign_transfer(&private_key, 1, 2, 9, 1, 0)),
+            Err(TransferError::BelowMinimum(10))
+        );
+        assert_eq!(node.accounts, accounts);
+        assert!(node
+            .try_add_transfer(&sign_transfer(&private_key, 1, 2, 10, 1, 0))
+            .is_ok());
+    }
+
+    #[test]
+    fn does_not_accept_transfer_above_maximum() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        node.max_transfer = Some(50);
+        let accounts = node.accounts.clone();
+        let transfer = sign_transfer(&private_key, 1, 2, 51, 0, 0);
+
+        assert_eq!(
+            node.try_add_transfer(&transfer),
+            Err(TransferError::AboveMaximum(50))
+        );
+        assert_eq!(node.accounts, accounts);
+        let block = get_batch_block(&node, vec![transfer]);
+        assert_eq!(
+            node.try_add_block(block),
+            Err(BlockError::InvalidTransfer(TransferError::AboveMaximum(50)))
+        );
+        assert_eq!(node.accounts, accounts);
+        assert!(node
+            .try_add_transfer(&sign_transfer(&private_key, 1, 2, 50, 0, 0))
+            .is_ok());
+    }
+
+    #[test]
+    fn encodes_block_header_canonically() {
+        let bytes = canonical_encode(3, 1, 1665411301, "ab", "cd", 7, 0, None);
+
+        assert_eq!(
+            bytes,
+            vec![
+                0, 0, 0, 3, // version
+                0, 0, 0, 0, 0, 0, 0, 1, // id
+                0, 0, 0, 0, 0x63, 0x44, 0x28, 0xe5, // timestamp
+                0, 0, 0, 0, 0, 0, 0, 2, b'a', b'b', // previous hash
+                0, 0, 0, 0, 0, 0, 0, 2, b'c', b'd', // merkle root
+                0, 0, 0, 0, 0, 0, 0, 7, // nonce
+                0, // no chain id
+                0, // no miner
+            ]
+        );
+        assert_eq!(
+            canonical_encode(3, 1, 1665411301, "ab", "cd", 7, 5, Some("m"))[48..],
+            [
+                1, 0, 0, 0, 0, 0, 0, 0, 5, // chain id
+                1, 0, 0, 0, 0, 0, 0, 0, 1, b'm', // miner
+            ]
+        );
+        assert_eq!(
+            calculate_hash(
+                &Sha256Hasher,
+                0,
+                3,
+                1,
+                1665411301,
+                "ab",
+                &Data::Batch(vec![]),
+                "cd",
+                7,
+                None
+            ),
+            Sha256::digest(&bytes).to_vec()
+        );
+    }
+
+    #[test]
+    fn tags_block_with_miner() {
+        let mut node = Node::new();
+        node.genesis();
+        let genesis = node.get_last_block().clone();
+        let miner = PeerId::random().to_string();
+        let block = Block::try_new(
+            1,
+            genesis.hash.clone(),
+            Data::Batch(vec![]),
+            node.compute_difficulty(),
+            u64::MAX,
+            1,
+            DEFAULT_MINING_LOG_INTERVAL,
+            &AtomicBool::new(false),
+            &Sha256Hasher,
+            0,
+            Some(miner.clone()),
+        )
+        .unwrap();
+        let difficulty = node.compute_difficulty();
+
+        assert_eq!(block.version, BLOCK_VERSION);
+        let json = serde_json::to_string(&block).unwrap();
+        assert_eq!(serde_json::from_str::<Block>(&json).unwrap(), block);
+        let retagged = Block {
+            miner: Some(PeerId::random().to_string()),
+            ..block.clone()
+        };
+        assert_ne!(retagged.compute_hash(&Sha256Hasher, 0), block.hash);
+        assert_eq!(
+            retagged.validate_self(difficulty, &Sha256Hasher, 0, 0),
+            Err(BlockError::HashMismatch)
+        );
+        for miner in [None, Some("not a peer id".to_string())] {
+            let block = Block {
+                miner,
+                ..block.clone()
+            };
+            assert_eq!(
+                block.validate_self(difficulty, &Sha256Hasher, 0, 0),
+                Err(BlockError::MalformedMiner)
+            );
+        }
+        assert!(node.try_add_block(block).is_ok());
+        assert_eq!(node.get_last_block().miner, Some(miner));
+    }
+
+    #[test]
+    fn validates_merkle_block_hashed_as_json() {
+        let mut node = Node::new();
+        node.genesis();
+        let latest_block = node.get_last_block().clone();
+        let data = Data::Batch(vec![]);
+        let (nonce, hash) = Block::mine_block(
+            &Sha256Hasher,
+            0,
+            MERKLE_BLOCK_VERSION,
+            None,
+            1,
+            latest_block.timestamp + 1,
+            &latest_block.hash,
+            &data,
+            node.compute_difficulty(),
+            u64::MAX,
+            DEFAULT_MINING_LOG_INTERVAL,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        let block = Block {
+            version: MERKLE_BLOCK_VERSION,
+            id: 1,
+            hash,
+            previous_hash: latest_block.hash,
+            timestamp: latest_block.timestamp + 1,
+            merkle_root: Block::merkle_root(&data, &Sha256Hasher),
+            data,
+            nonce,
+            miner: None,
+        };
+
+        assert!(node.try_add_block(block).is_ok());
+    }
+
+    #[test]
+    fn validator_accepts_peer_blocks_without_mining() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        node.mining_enabled = false;
+        let transfer = sign_transfer(&private_key, 1, 2, 10, 0, 0);
+        assert!(node.add_to_mempool(transfer.clone()));
+
+        assert_eq!(node.mine_pending(), None);
+        assert_eq!(node.mempool, vec![transfer.clone()]);
+        assert_eq!(node.blocks.len(), 1);
+
+        let block = get_batch_block(&node, vec![transfer]);
+        assert!(node.try_add_block(block.clone()).is_ok());
+        assert_eq!(node.get_last_block(), &block);
+        assert_eq!(node.accounts[&2].balance, 10);
+    }
+
+    #[test]
+    fn does_not_overwrite_existing_account() {
+        let (_, pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        let accounts = node.accounts.clone();
+
+        for (account, error) in [
+            (
+                Account::implicit(1, PublicKey([0x11; 32])),
+                BlockError::PubKeyMismatch(1),
+            ),
+            (
+                Account::implicit(1, pub_key),
+                BlockError::DuplicateAccount(1),
+            ),
+        ] {
+            let block = Block::new(
+                1,
+                node.get_last_block().hash.clone(),
+                Data::Batch(vec![Data::Account(account)]),
+                node.compute_difficulty(),
+            );
+            assert_eq!(node.try_add_block(block), Err(error));
+        }
+        assert_eq!(node.accounts, accounts);
+        assert_eq!(node.accounts[&1].balance, 100);
+    }
+
+    #[test]
+    fn rejects_account_announced_with_another_key() {
+        let (_, pub_key) = crypto::generate_keypair();
+        let (_, other_pub_key) = crypto::generate_keypair();
+        let mut node = get_node_with_sender(pub_key, 100);
+        let announce = |node: &Node, account: Account| {
+            Block::new(
+                node.get_last_block().id + 1,
+                node.get_last_block().hash.clone(),
+                Data::Account(account),
+                node.compute_difficulty(),
+            )
+        };
+
+        let block = announce(&node, Account::implicit(3, other_pub_key));
+        assert_eq!(node.try_add_block(block), Ok(()));
+        let block = announce(&node, Account::implicit(3, other_pub_key));
+        assert_eq!(
+            node.try_add_block(block),
+            Err(BlockError::DuplicateAccount(3))
+        );
+        let block = announce(
+            &node,
+            Account {
+                nonce: 1,
+                ..Account::implicit(5, other_pub_key)
+            },
+        );
+        assert_eq!(node.try_add_block(block), Err(BlockError::FundedAccount(5)));
+        let block = announce(&node, Account::implicit(3, pub_key));
+        assert_eq!(
+            node.try_add_block(block),
+            Err(BlockError::PubKeyMismatch(3))
+        );
+
+        // A key registered without an account still binds the address.
+        node.pub_keys.insert(4, pub_key);
+        let block = announce(&node, Account::implicit(4, other_pub_key));
+        assert_eq!(
+            node.try_add_block(block),
+            Err(BlockError::PubKeyMismatch(4))
+        );
+        assert_eq!(node.pub_keys[&3], other_pub_key);
+        assert!(!node.accounts.contains_key(&4));
+    }
+
+    #[test]
+    fn derives_addresses_from_keys() {
+        let (_, pub_key) = crypto::generate_keypair();
+        let mut node = Node::new();
+        let (account, _) = node.add_account();
+
+        assert_eq!(
+            Account::derive_address(&pub_key),
+            Account::derive_address(&pub_key)
+        );
+        assert_ne!(
+            Account::derive_address(&pub_key),
+            Account::derive_address(&PublicKey([0x11; 32]))
+        );
+        assert_eq!(account.address, Account::derive_address(&account.pub_key));
+    }
+
+    #[test]
+    fn rejects_accounts_not_derived_from_their_keys() {
+        let (private_key, pub_key) = crypto::generate_keypair();
+        let (_, other_pub_key) = crypto::generate_keypair();
+        let derived = Account::derive_address(&other_pub_key);
+        let mut node = get_node_with_sender(pub_key, 100);
+        node.allow_implicit_accounts = true;
+        let announce = |node: &Node, account: Account| {
+            Block::new(
+                node.get_last_block().id + 1,
+                node.get_last_block().hash.clone(),
+                Data::Account(account),
+                node.compute_difficulty(),
+            )
+        };
+
+        let claimed = announce(&node, Account::implicit(3, other_pub_key));
+        node.require_derived_addresses = true;
+        assert_eq!(
+            node.try_add_block(claimed),
+            Err(BlockError::AddressMismatch(3))
+        );
+        assert_eq!(
+            node.try_add_transfer(&sign_transfer_to_new_account(
+                0,
+                &private_key,
+                1,
+                3,
+                other_pub_key,
+                10,
+                0,
+                0
+            )),
+            Err(TransferError::AddressMismatch)
+        );
+        assert!(!node.accounts.contains_key(&3));
+
+        let block = announce(&node, Account::implicit(derived, other_pub_key));
+        assert_eq!(node.try_add_block(block), Ok(()));
+        assert_eq!(node.pub_keys[&derived], other_pub_key);
+
+        node.require_derived_addresses = false;
+        let block = announce(&node, Account::implicit(3, other_pub_key));
+        assert_eq!(node.try_add_block(block), Ok(()));
+    }
+
+    #[test]
+    fn reads_transfer_fields() {
+        let signature = Signature([0x33; 64]);
+        let transfer = Data::transfer(1, 2, 30, 4, 5, signature);
+
+        assert_eq!(transfer.sender(), Some(1));
+        assert_eq!(transfer.receiver(), Some(2));
+        assert_eq!(transfer.amount(), Some(30));
+        assert_eq!(transfer.fee(), Some(4));
+        assert_eq!(transfer.nonce(), Some(5));
+        assert_eq!(transfer.signature(), Some(&signature));
+        assert!(matches!(
+            transfer,
+            Data::Transfer {
+                receiver_pub_key: None,
+                ..
+            }
+        ));
+        for data in [
+            Data::Account(GENESIS_ACCOUNT),
+            Data::Reward(1, 50),
+            Data::Batch(vec![transfer]),
+        ] {
+            assert_eq!(data.sender(), None);
+            assert_eq!(data.receiver(), None);
+            assert_eq!(data.amount(), None);
+            assert_eq!(data.fee(), None);
+            assert_eq!(data.nonce(), None);
+            assert_eq!(data.signature(), None);
+        }
+    }
+}
@@ -0,0 +1,674 @@
+//! Validation of blocks which needs neither the network nor a clock, so a light client built
+//! for `wasm32-unknown-unknown` checks blocks the way the nodes do.
+use crate::crypto::{PublicKey, Signature};
+use crate::hashing::{Hasher, Sha256Hasher};
+use crate::policy::PolicyError;
+use crate::CHAIN_TARGET;
+use log::warn;
+use serde::ser::{SerializeStruct, SerializeStructVariant, SerializeTupleVariant};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::json;
+use std::fmt;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+pub(crate) const LEGACY_BLOCK_VERSION: u32 = 0;
+pub(crate) const MERKLE_BLOCK_VERSION: u32 = 2;
+pub(crate) const CANONICAL_BLOCK_VERSION: u32 = 3;
+/// Blocks since this version name the peer which mined them in `Block::miner`.
+pub(crate) const MINER_BLOCK_VERSION: u32 = 4;
+pub const BLOCK_VERSION: u32 = 4;
+
+/// Default of `Node::block_reward`.
+pub(crate) const DEFAULT_BLOCK_REWARD: u64 = 50;
+/// Default of `Node::max_block_bytes`.
+pub(crate) const DEFAULT_MAX_BLOCK_BYTES: usize = 1024 * 1024;
+/// Difficulty retargeting does not go below.
+const MIN_DIFFICULTY: usize = 1;
+/// Most bytes of a public key inlined into a peer id, longer keys are hashed.
+const MAX_INLINE_KEY_LENGTH: usize = 42;
+/// Multihash codes of the inlined key and of its SHA-256 digest.
+const IDENTITY_CODE: u8 = 0x00;
+const SHA2_256_CODE: u8 = 0x12;
+/// Longest digest of a multihash libp2p reads.
+const MAX_DIGEST_LENGTH: usize = 64;
+
+pub type Address = u64;
+
+#[derive(Deserialize, Hash, Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub address: Address,
+    pub balance: u64,
+    pub pub_key: PublicKey,
+    /// Nonce the next transfer from the account has to be signed with.
+    #[serde(default)]
+    pub nonce: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    #[serde(default)]
+    pub version: u32,
+    pub id: u64,
+    pub hash: String,
+    pub previous_hash: String,
+    pub timestamp: i64,
+    pub data: Data,
+    #[serde(default)]
+    pub merkle_root: String,
+    pub nonce: u64,
+    /// Peer id of the node which mined the block, hashed with the other fields.
+    #[serde(default)]
+    pub miner: Option<String>,
+}
+
+/// Settings a block is checked against, all nodes of a network have to agree on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockRules {
+    pub difficulty: usize,
+    pub chain_id: u64,
+    pub max_block_bytes: usize,
+    /// Reward of the block, see `Node::block_reward_at`. Fees are claimed on top of it.
+    pub block_reward: u64,
+    /// Latest timestamp accepted, not checked if not set. Nodes allow their clock and
+    /// `Node::max_future_drift_secs`.
+    pub max_timestamp: Option<i64>,
+}
+
+/// Why a block was not added, the details are logged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockError {
+    TooLarge(usize),
+    UnsupportedVersion(u32),
+    WrongPreviousHash,
+    BadDifficulty,
+    NonSequentialId,
+    MerkleRootMismatch,
+    HashMismatch,
+    TimestampBeforeParent,
+    TimestampInFuture,
+    RewardTooHigh,
+    NestedBatch,
+    UnknownMiner,
+    /// A reward which would take the balance of the account above `u64::MAX`.
+    BalanceOverflow(Address),
+    /// A block of `MINER_BLOCK_VERSION` without a peer id, or a miner which is not one.
+    MalformedMiner,
+    DuplicateAccount(Address),
+    /// An account announced after the genesis block with a balance or nonce, which would mint coins.
+    FundedAccount(Address),
+    /// An account announced with another key than the one the address is registered with.
+    PubKeyMismatch(Address),
+    /// An account whose address is not derived from its key, see `Node::require_derived_addresses`.
+    AddressMismatch(Address),
+    InvalidTransfer(TransferError),
+    /// Valid, but not accepted by the policy of the node.
+    Rejected(PolicyError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferError {
+    NotATransfer,
+    SelfTransfer,
+    UnknownSender,
+    BadSignature,
+    ImplicitAccountsNotAllowed,
+    AddressMismatch,
+    UnknownReceiver,
+    AmountOverflow,
+    BelowMinimum(u64),
+    AboveMaximum(u64),
+    WrongNonce { expected: u64, got: u64 },
+    InsufficientBalance,
+    ReceiverOverflow,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Data {
+    Account(Account),
+    Transfer {
+        sender: Address,
+        receiver: Address,
+        amount: u64,
+        #[serde(default)]
+        fee: u64,
+        #[serde(default)]
+        nonce: u64,
+        signature: Signature,
+        /// Key of the account created for an unknown receiver, see `Node::allow_implicit_accounts`.
+        #[serde(default)]
+        receiver_pub_key: Option<PublicKey>,
+    },
+    Batch(Vec<Data>),
+    Reward(Address, u64),
+}
+
+impl Default for BlockRules {
+    /// Rules of the default network as far as a block and its parent tell: the retargeted
+    /// difficulty depends on the blocks before them, so only the lowest one is required.
+    fn default() -> Self {
+        Self {
+            difficulty: MIN_DIFFICULTY,
+            chain_id: 0,
+            max_block_bytes: DEFAULT_MAX_BLOCK_BYTES,
+            block_reward: DEFAULT_BLOCK_REWARD,
+            max_timestamp: None,
+        }
+    }
+}
+
+impl Block {
+    /// Hex hash of the fields of the block, which its `hash` has to match.
+    pub(crate) fn compute_hash(&self, hasher: &dyn Hasher, chain_id: u64) -> String {
+        hex::encode(calculate_hash(
+            hasher,
+            chain_id,
+            self.version,
+            self.id,
+            self.timestamp,
+            &self.previous_hash,
+            &self.data,
+            &self.merkle_root,
+            self.nonce,
+            self.miner.as_deref(),
+        ))
+    }
+
+    /// Checks of the block which do not need the chain before it: the proof of work, the hash
+    /// and the timestamp not being after `BlockRules::max_timestamp`.
+    pub fn validate_header(
+        &self,
+        rules: &BlockRules,
+        hasher: &dyn Hasher,
+    ) -> Result<(), BlockError> {
+        let hash = match hex::decode(&self.hash) {
+            Ok(hash) => hash,
+            Err(_) => {
+                warn!(target: CHAIN_TARGET, "Block with id: {} has malformed hash", self.id);
+                return Err(BlockError::HashMismatch);
+            }
+        };
+        if !hash_to_binary_representation(&hash).starts_with(&difficulty_prefix(rules.difficulty)) {
+            warn!(target: CHAIN_TARGET, "Block with id: {} has invalid difficulty.", self.id);
+            return Err(BlockError::BadDifficulty);
+        } else if !self.is_miner_valid() {
+            warn!(target: CHAIN_TARGET, "Block with id: {} has malformed miner", self.id);
+            return Err(BlockError::MalformedMiner);
+        } else if self.compute_hash(hasher, rules.chain_id) != self.hash {
+            warn!(target: CHAIN_TARGET, "Block with id: {} has invalid hash", self.id);
+            return Err(BlockError::HashMismatch);
+        } else if rules
+            .max_timestamp
+            .is_some_and(|max_timestamp| self.timestamp > max_timestamp)
+        {
+            warn!(
+                target: CHAIN_TARGET,
+                "Block with id: {} is timestamped too far in the future: {}",
+                self.id, self.timestamp
+            );
+            return Err(BlockError::TimestampInFuture);
+        }
+        Ok(())
+    }
+
+    /// Any peer may mine, but blocks of `MINER_BLOCK_VERSION` have to name one.
+    fn is_miner_valid(&self) -> bool {
+        match &self.miner {
+            Some(miner) => is_peer_id(miner),
+            None => self.version < MINER_BLOCK_VERSION,
+        }
+    }
+
+    pub(crate) fn expected_merkle_root(&self, hasher: &dyn Hasher) -> String {
+        if self.version >= MERKLE_BLOCK_VERSION {
+            Block::merkle_root(&self.data, hasher)
+        } else {
+            String::new()
+        }
+    }
+
+    /// Root of the Merkle tree over the transactions of a batch, or over the data itself.
+    pub fn merkle_root(data: &Data, hasher: &dyn Hasher) -> String {
+        let items = match data {
+            Data::Batch(items) => items.iter().collect(),
+            data => vec![data],
+        };
+        let mut level: Vec<Vec<u8>> = items
+            .into_iter()
+            .map(|item| {
+                let json = serde_json::to_string(item).expect("Can jsonify data.");
+                hasher.digest(json.as_bytes())
+            })
+            .collect();
+        if level.is_empty() {
+            return hex::encode(hasher.digest(b""));
+        }
+
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| {
+                    // An odd node is paired with itself.
+                    hasher.digest(&[&pair[0][..], pair.get(1).unwrap_or(&pair[0])].concat())
+                })
+                .collect();
+        }
+        hex::encode(&level[0])
+    }
+}
+
+/// Checks of the block against its parent, the block data is not applied. Nodes check the
+/// signatures and the balances of the transfers on top of it.
+pub fn is_block_valid(
+    block: &Block,
+    previous_block: &Block,
+    rules: &BlockRules,
+    hasher: &dyn Hasher,
+) -> Result<(), BlockError> {
+    let size = serde_json::to_vec(block).expect("Can jsonify block.").len();
+    if size > rules.max_block_bytes {
+        warn!(
+            target: CHAIN_TARGET,
+            "Block with id: {} is too large: {} bytes", block.id, size
+        );
+        return Err(BlockError::TooLarge(size));
+    } else if block.version > BLOCK_VERSION || block.version < previous_block.version {
+        warn!(
+            target: CHAIN_TARGET,
+            "Block with id: {} has unsupported version: {}", block.id, block.version
+        );
+        return Err(BlockError::UnsupportedVersion(block.version));
+    } else if block.previous_hash != previous_block.hash {
+        warn!(target: CHAIN_TARGET, "Block with id: {} has wrong previous hash", block.id);
+        return Err(BlockError::WrongPreviousHash);
+    } else if block.id != previous_block.id + 1 {
+        warn!(
+            target: CHAIN_TARGET,
+            "Block with id: {} is not the next block after the latest: {}",
+            block.id, previous_block.id
+        );
+        return Err(BlockError::NonSequentialId);
+    } else if block.merkle_root != block.expected_merkle_root(hasher) {
+        warn!(target: CHAIN_TARGET, "Block with id: {} has invalid merkle root", block.id);
+        return Err(BlockError::MerkleRootMismatch);
+    }
+    block.validate_header(rules, hasher)?;
+    if block.timestamp < previous_block.timestamp {
+        // Blocks mined within the same second share the timestamp of their parent.
+        warn!(
+            target: CHAIN_TARGET,
+            "Block with id: {} is timestamped before its parent", block.id
+        );
+        return Err(BlockError::TimestampBeforeParent);
+    } else if !is_reward_valid(&block.data, rules.block_reward) {
+        warn!(target: CHAIN_TARGET, "Block with id: {} claims too high reward", block.id);
+        return Err(BlockError::RewardTooHigh);
+    }
+    Ok(())
+}
+
+/// Whether the JSON block extends its JSON parent under the `BlockRules::default` rules, for
+/// light clients following the headers of the default network. Signatures are not verified.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn validate_block(block_json: &str, prev_json: &str) -> bool {
+    let (block, previous_block) = match (
+        serde_json::from_str::<Block>(block_json),
+        serde_json::from_str::<Block>(prev_json),
+    ) {
+        (Ok(block), Ok(previous_block)) => (block, previous_block),
+        _ => return false,
+    };
+    is_block_valid(
+        &block,
+        &previous_block,
+        &BlockRules::default(),
+        &Sha256Hasher,
+    )
+    .is_ok()
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockError::TooLarge(size) => write!(f, "block is too large: {} bytes", size),
+            BlockError::UnsupportedVersion(version) => {
+                write!(f, "unsupported block version {}", version)
+            }
+            BlockError::WrongPreviousHash => write!(f, "wrong previous hash"),
+            BlockError::BadDifficulty => write!(f, "hash does not meet the difficulty"),
+            BlockError::NonSequentialId => write!(f, "not the next block after the latest"),
+            BlockError::MerkleRootMismatch => write!(f, "invalid merkle root"),
+            BlockError::HashMismatch => write!(f, "invalid hash"),
+            BlockError::TimestampBeforeParent => write!(f, "timestamp is before the parent block"),
+            BlockError::TimestampInFuture => write!(f, "timestamp is too far in the future"),
+            BlockError::RewardTooHigh => write!(f, "reward is too high"),
+            BlockError::NestedBatch => write!(f, "batch nested in a batch"),
+            BlockError::UnknownMiner => write!(f, "reward for an unknown miner"),
+            BlockError::BalanceOverflow(address) => {
+                write!(f, "balance of account {} overflows", address)
+            }
+            BlockError::MalformedMiner => write!(f, "missing or malformed miner peer id"),
+            BlockError::DuplicateAccount(address) => {
+                write!(f, "account {} already exists", address)
+            }
+            BlockError::FundedAccount(address) => {
+                write!(
+                    f,
+                    "account {} is announced with a balance or nonce",
+                    address
+                )
+            }
+            BlockError::PubKeyMismatch(address) => {
+                write!(f, "account {} is registered with another key", address)
+            }
+            BlockError::AddressMismatch(address) => {
+                write!(f, "address {} is not derived from its key", address)
+            }
+            BlockError::InvalidTransfer(e) => write!(f, "invalid transfer, {}", e),
+            BlockError::Rejected(e) => write!(f, "rejected by policy, {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BlockError {}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransferError::NotATransfer => write!(f, "not a transfer"),
+            TransferError::SelfTransfer => write!(f, "sender and receiver are the same"),
+            TransferError::UnknownSender => write!(f, "unknown sender"),
+            TransferError::BadSignature => write!(f, "signature verification failed"),
+            TransferError::ImplicitAccountsNotAllowed => {
+                write!(f, "implicit accounts are not allowed")
+            }
+            TransferError::AddressMismatch => {
+                write!(f, "receiver address is not derived from its key")
+            }
+            TransferError::UnknownReceiver => write!(f, "unknown receiver"),
+            TransferError::AmountOverflow => write!(f, "amount with fee overflows"),
+            TransferError::WrongNonce { expected, got } => {
+                write!(f, "expected nonce {}, got {}", expected, got)
+            }
+            TransferError::BelowMinimum(min) => write!(f, "amount is below the minimum {}", min),
+            TransferError::AboveMaximum(max) => write!(f, "amount is above the maximum {}", max),
+            TransferError::InsufficientBalance => write!(f, "insufficient balance"),
+            TransferError::ReceiverOverflow => write!(f, "receiver balance overflows"),
+        }
+    }
+}
+
+impl std::error::Error for TransferError {}
+
+impl Serialize for Account {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // A zero nonce is left out of JSON to keep the hashes of blocks from before nonces,
+        // binary formats cannot skip fields.
+        let skip_nonce = serializer.is_human_readable() && self.nonce == 0;
+        let mut state = serializer.serialize_struct("Account", if skip_nonce { 3 } else { 4 })?;
+        state.serialize_field("address", &self.address)?;
+        state.serialize_field("balance", &self.balance)?;
+        state.serialize_field("pub_key", &self.pub_key)?;
+        if skip_nonce {
+            state.skip_field("nonce")?;
+        } else {
+            state.serialize_field("nonce", &self.nonce)?;
+        }
+        state.end()
+    }
+}
+
+impl Serialize for Data {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Data::Account(account) => {
+                serializer.serialize_newtype_variant("Data", 0, "Account", account)
+            }
+            Data::Transfer {
+                sender,
+                receiver,
+                amount,
+                fee,
+                nonce,
+                signature,
+                receiver_pub_key,
+            } => {
+                // Like the nonce of `Account`, a missing key is left out of JSON only.
+                let skip_key = serializer.is_human_readable() && receiver_pub_key.is_none();
+                let len = if skip_key { 6 } else { 7 };
+                let mut state = serializer.serialize_struct_variant("Data", 1, "Transfer", len)?;
+                state.serialize_field("sender", sender)?;
+                state.serialize_field("receiver", receiver)?;
+                state.serialize_field("amount", amount)?;
+                state.serialize_field("fee", fee)?;
+                state.serialize_field("nonce", nonce)?;
+                state.serialize_field("signature", signature)?;
+                if skip_key {
+                    state.skip_field("receiver_pub_key")?;
+                } else {
+                    state.serialize_field("receiver_pub_key", receiver_pub_key)?;
+                }
+                state.end()
+            }
+            Data::Batch(items) => serializer.serialize_newtype_variant("Data", 2, "Batch", items),
+            Data::Reward(miner, reward) => {
+                let mut state = serializer.serialize_tuple_variant("Data", 3, "Reward", 2)?;
+                state.serialize_field(miner)?;
+                state.serialize_field(reward)?;
+                state.end()
+            }
+        }
+    }
+}
+
+/// Sum of the rewards claimed in the data, `None` on overflow.
+pub(crate) fn total_reward(data: &Data) -> Option<u64> {
+    match data {
+        Data::Reward(_, reward) => Some(*reward),
+        Data::Batch(items) => items
+            .iter()
+            .try_fold(0u64, |total, item| total.checked_add(total_reward(item)?)),
+        _ => Some(0),
+    }
+}
+
+/// The claimed rewards may not exceed the block reward and the fees of the block.
+pub(crate) fn is_reward_valid(data: &Data, block_reward: u64) -> bool {
+    let allowed = total_fees(data).and_then(|fees| fees.checked_add(block_reward));
+    matches!((total_reward(data), allowed), (Some(reward), Some(allowed)) if reward <= allowed)
+}
+
+/// Sum of the fees paid by the transfers in the data, `None` on overflow.
+pub(crate) fn total_fees(data: &Data) -> Option<u64> {
+    match data {
+        Data::Transfer { fee, .. } => Some(*fee),
+        Data::Batch(items) => items
+            .iter()
+            .try_fold(0u64, |total, item| total.checked_add(total_fees(item)?)),
+        _ => Some(0),
+    }
+}
+
+/// Whether the string is a libp2p peer id: the base58 multihash of the public key of the peer,
+/// inlined if short enough or its SHA-256 digest.
+fn is_peer_id(s: &str) -> bool {
+    let bytes = match bs58::decode(s).into_vec() {
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+    match bytes[..] {
+        [IDENTITY_CODE, len, ref key @ ..] => {
+            usize::from(len) == key.len() && key.len() <= MAX_INLINE_KEY_LENGTH
+        }
+        [SHA2_256_CODE, len, ref digest @ ..] => {
+            usize::from(len) == digest.len() && digest.len() <= MAX_DIGEST_LENGTH
+        }
+        _ => false,
+    }
+}
+
+pub(crate) fn difficulty_prefix(difficulty: usize) -> String {
+    "0".repeat(difficulty)
+}
+
+pub(crate) fn hash_to_binary_representation(hash: &[u8]) -> String {
+    let mut rep: String = String::new();
+    for c in hash {
+        rep.push_str(&format!("{:08b}", c));
+    }
+    rep
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn calculate_hash(
+    hasher: &dyn Hasher,
+    chain_id: u64,
+    version: u32,
+    id: u64,
+    timestamp: i64,
+    previous_hash: &str,
+    data: &Data,
+    merkle_root: &str,
+    nonce: u64,
+    miner: Option<&str>,
+) -> Vec<u8> {
+    if version >= CANONICAL_BLOCK_VERSION {
+        let bytes = canonical_encode(
+            version,
+            id,
+            timestamp,
+            previous_hash,
+            merkle_root,
+            nonce,
+            chain_id,
+            miner,
+        );
+        return hasher.digest(&bytes);
+    }
+
+    let mut object = json!({
+        "id": id,
+        "previous_hash": previous_hash,
+        "data": data,
+        "timestamp": timestamp,
+        "nonce": nonce
+    });
+    // Legacy blocks were hashed before the version field existed.
+    if version != LEGACY_BLOCK_VERSION {
+        object["version"] = json!(version);
+    }
+    if version >= MERKLE_BLOCK_VERSION {
+        object["merkle_root"] = json!(merkle_root);
+    }
+    if chain_id != 0 {
+        object["chain_id"] = json!(chain_id);
+    }
+    if let Some(miner) = miner {
+        object["miner"] = json!(miner);
+    }
+
+    hasher.digest(object.to_string().as_bytes())
+}
+
+/// Bytes of the block header hashed since `CANONICAL_BLOCK_VERSION`: the version (4 bytes), id,
+/// timestamp, previous hash, merkle root and nonce in this order, integers big-endian and strings
+/// prefixed with their length as 8 bytes. The optional chain id and miner follow, each after a
+/// byte telling if it is present, so no two headers share the bytes. The data is covered by the
+/// merkle root.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn canonical_encode(
+    version: u32,
+    id: u64,
+    timestamp: i64,
+    previous_hash: &str,
+    merkle_root: &str,
+    nonce: u64,
+    chain_id: u64,
+    miner: Option<&str>,
+) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(52 + previous_hash.len() + merkle_root.len());
+    bytes.extend_from_slice(&version.to_be_bytes());
+    bytes.extend_from_slice(&id.to_be_bytes());
+    bytes.extend_from_slice(&timestamp.to_be_bytes());
+    for field in [previous_hash, merkle_root] {
+        bytes.extend_from_slice(&(field.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(field.as_bytes());
+    }
+    bytes.extend_from_slice(&nonce.to_be_bytes());
+    match chain_id {
+        0 => bytes.push(0),
+        chain_id => {
+            bytes.push(1);
+            bytes.extend_from_slice(&chain_id.to_be_bytes());
+        }
+    }
+    match miner {
+        None => bytes.push(0),
+        Some(miner) => {
+            bytes.push(1);
+            bytes.extend_from_slice(&(miner.len() as u64).to_be_bytes());
+            bytes.extend_from_slice(miner.as_bytes());
+        }
+    }
+    bytes
+}
+
+#[cfg(all(test, feature = "native"))]
+mod core_tests {
+    use super::*;
+    use crate::Node;
+    use libp2p::identity::Keypair;
+    use libp2p::PeerId;
+    use std::str::FromStr;
+
+    #[test]
+    fn validates_block_from_json() {
+        let mut node = Node::new();
+        node.genesis();
+        let genesis = node.get_last_block().clone();
+        let data = Data::Account(Account {
+            address: 1,
+            balance: 0,
+            pub_key: PublicKey([0x11; 32]),
+            nonce: 0,
+        });
+        let block = Block::new(1, genesis.hash.clone(), data, 2);
+        let json = |block: &Block| serde_json::to_string(block).unwrap();
+
+        assert!(validate_block(&json(&block), &json(&genesis)));
+        assert!(!validate_block(&json(&genesis), &json(&block)));
+        let tampered = Block {
+            nonce: block.nonce + 1,
+            ..block.clone()
+        };
+        assert!(!validate_block(&json(&tampered), &json(&genesis)));
+        assert!(!validate_block("{}", &json(&genesis)));
+    }
+
+    #[test]
+    fn recognizes_peer_ids_like_libp2p() {
+        let encode = |bytes: &[u8]| bs58::encode(bytes).into_string();
+        let valid = [
+            PeerId::random().to_string(),
+            PeerId::from(Keypair::generate_ed25519().public()).to_string(),
+            encode(&[&[SHA2_256_CODE, 32][..], &[0x77; 32]].concat()),
+        ];
+        let invalid = [
+            String::new(),
+            "not a peer id".to_string(),
+            encode(&[&[SHA2_256_CODE, 33][..], &[0x77; 32]].concat()),
+            encode(&[&[IDENTITY_CODE, 43][..], &[0x77; 43]].concat()),
+            encode(&[0x13, 32]),
+        ];
+
+        for miner in valid.iter().chain(&invalid) {
+            assert_eq!(
+                is_peer_id(miner),
+                PeerId::from_str(miner).is_ok(),
+                "{}",
+                miner
+            );
+        }
+        assert!(valid.iter().all(|miner| is_peer_id(miner)));
+    }
+}
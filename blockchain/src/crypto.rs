@@ -0,0 +1,132 @@
+#[cfg(feature = "native")]
+use libp2p::identity::ed25519;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PrivateKey(pub [u8; 32]);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PublicKey(pub [u8; 32]);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Signature(pub [u8; 64]);
+
+// Keys and signatures are exchanged (JSON, CLI) as hex strings.
+macro_rules! impl_hex_bytes {
+    ($name:ident, $len:expr) => {
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", hex::encode(self.0))
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let bytes = hex::decode(s).map_err(|e| e.to_string())?;
+                let bytes: [u8; $len] = bytes
+                    .try_into()
+                    .map_err(|_| format!("expected {} bytes", $len))?;
+                Ok(Self(bytes))
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&self.to_string())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                String::deserialize(deserializer)?
+                    .parse()
+                    .map_err(D::Error::custom)
+            }
+        }
+    };
+}
+
+impl_hex_bytes!(PrivateKey, 32);
+impl_hex_bytes!(PublicKey, 32);
+impl_hex_bytes!(Signature, 64);
+
+// Wallets and errors holding private keys are debug printed, the key itself is left out of it.
+impl fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PrivateKey(..)")
+    }
+}
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PublicKey({})", self)
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Signature({})", self)
+    }
+}
+
+#[cfg(feature = "native")]
+pub fn generate_keypair() -> (PrivateKey, PublicKey) {
+    let private_key = PrivateKey(
+        ed25519::SecretKey::generate()
+            .as_ref()
+            .try_into()
+            .expect("ed25519 secret keys have 32 bytes."),
+    );
+    (private_key, derive_public_key(&private_key))
+}
+
+#[cfg(feature = "native")]
+pub fn derive_public_key(private_key: &PrivateKey) -> PublicKey {
+    PublicKey(to_keypair(private_key).public().encode())
+}
+
+#[cfg(feature = "native")]
+pub fn sign(private_key: &PrivateKey, message: &[u8]) -> Signature {
+    Signature(
+        to_keypair(private_key)
+            .sign(message)
+            .try_into()
+            .expect("ed25519 signatures have 64 bytes."),
+    )
+}
+
+#[cfg(feature = "native")]
+pub fn verify(public_key: &PublicKey, message: &[u8], signature: &Signature) -> bool {
+    ed25519::PublicKey::decode(&public_key.0)
+        .map(|public_key| public_key.verify(message, &signature.0))
+        .unwrap_or(false)
+}
+
+#[cfg(feature = "native")]
+fn to_keypair(private_key: &PrivateKey) -> ed25519::Keypair {
+    ed25519::SecretKey::from_bytes(private_key.0)
+        .expect("Any 32 bytes are a valid ed25519 secret key.")
+        .into()
+}
+
+#[cfg(all(test, feature = "native"))]
+mod crypto_tests {
+    use super::*;
+
+    #[test]
+    fn does_not_debug_print_private_keys() {
+        let (private_key, public_key) = generate_keypair();
+
+        assert_eq!(format!("{:?}", private_key), "PrivateKey(..)");
+        assert_eq!(
+            format!("{:?}", public_key),
+            format!("PublicKey({})", public_key)
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Hash function of the block headers and of the merkle trees. It is chosen when the node is
+/// created, blocks hashed with another function fail validation, so all nodes of a network have
+/// to use the same one.
+pub trait Hasher: Send + Sync + fmt::Debug {
+    fn digest(&self, bytes: &[u8]) -> Vec<u8>;
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Sha256Hasher;
+
+#[cfg(feature = "sha3")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Sha3Hasher;
+
+impl Hasher for Sha256Hasher {
+    fn digest(&self, bytes: &[u8]) -> Vec<u8> {
+        Sha256::digest(bytes).to_vec()
+    }
+}
+
+#[cfg(feature = "sha3")]
+impl Hasher for Sha3Hasher {
+    fn digest(&self, bytes: &[u8]) -> Vec<u8> {
+        sha3::Sha3_256::digest(bytes).to_vec()
+    }
+}
+
+#[cfg(test)]
+mod hashing_tests {
+    use super::*;
+
+    #[test]
+    fn digests_known_vectors() {
+        assert_eq!(
+            hex::encode(Sha256Hasher.digest(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        #[cfg(feature = "sha3")]
+        assert_eq!(
+            hex::encode(Sha3Hasher.digest(b"abc")),
+            "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532"
+        );
+    }
+}
@@ -1,68 +1,377 @@
-mod lib;
+mod api;
+mod audit;
 mod p2p;
+mod peer_store;
+#[cfg(feature = "json-rpc")]
+mod rpc;
+mod wire;
 
-use crate::p2p::AppBehaviour;
+use clap::Parser;
 use libp2p::{
-    core::upgrade,
     futures::StreamExt,
-    mplex,
-    noise::{Keypair, NoiseConfig, X25519Spec},
-    swarm::{Swarm, SwarmBuilder},
-    tcp::TokioTcpConfig,
-    Transport,
+    swarm::{Swarm, SwarmEvent},
+    Multiaddr,
 };
 use log::{error, info};
+use node::storage::DataDir;
+use peer_store::PeerStore;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::time::Duration;
 use tokio::{
     io::{stdin, AsyncBufReadExt, BufReader},
-    select, spawn,
+    pin, select, signal, spawn,
     sync::mpsc,
     time::sleep,
 };
 
+#[derive(Parser)]
+struct Args {
+    /// Directory of the chain file, wallet, private keys and known peers, created on first run. The
+    /// `--chain-file`, `--wallet` and `--key-dir` flags override its paths.
+    #[arg(long, value_name = "DIR")]
+    datadir: Option<PathBuf>,
+
+    /// File the node state is loaded from on startup and saved to after each accepted block.
+    #[arg(long, value_name = "FILE")]
+    chain_file: Option<PathBuf>,
+
+    /// Chain file to merge into the local chain on startup.
+    #[arg(long, value_name = "FILE")]
+    import_chain: Option<PathBuf>,
+
+    /// JSON file with the genesis timestamp, difficulty and accounts, the default network if not given.
+    #[arg(long, value_name = "FILE")]
+    genesis: Option<PathBuf>,
+
+    /// Validate the chain file, print a report and exit without starting the node.
+    #[arg(long, value_name = "FILE")]
+    validate_chain: Option<PathBuf>,
+
+    /// Directory the private keys of accounts created with `create account` are saved to.
+    #[arg(long, value_name = "DIR")]
+    key_dir: Option<PathBuf>,
+
+    /// File every rejected block and transfer is appended to as a JSON line, with the reason and
+    /// the peer it came from.
+    #[arg(long, value_name = "FILE")]
+    audit_log: Option<PathBuf>,
+
+    /// File the wallet with the private keys of the local accounts is loaded from and saved to.
+    #[arg(long, value_name = "FILE")]
+    wallet: Option<PathBuf>,
+
+    /// Only validate and relay the blocks of peers, never mine.
+    #[arg(long)]
+    no_mine: bool,
+
+    /// Most transfers mined into one block, the ones with the highest fees go first.
+    #[arg(
+        long,
+        value_name = "COUNT",
+        default_value_t = node::MAX_BLOCK_TRANSFERS,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    max_txs_per_block: usize,
+
+    /// Nonces tried between the logs of mining progress.
+    #[arg(long, value_name = "NONCES", default_value_t = node::DEFAULT_MINING_LOG_INTERVAL)]
+    mining_log_interval: u64,
+
+    /// Blocks after which the block reward halves, never halved if not given, all nodes of a
+    /// network have to use the same setting.
+    #[arg(
+        long,
+        value_name = "BLOCKS",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    halving_interval: Option<u64>,
+
+    /// Most blocks a chain of a peer may be ahead of the local one to replace it, unlimited if not
+    /// given.
+    #[arg(long, value_name = "BLOCKS")]
+    max_reorg_depth: Option<u64>,
+
+    /// Id of the network, signed into transfers and hashed into blocks so they cannot be replayed
+    /// on a network with another id, all nodes of a network have to use the same id.
+    #[arg(long, value_name = "ID", default_value_t = 0)]
+    chain_id: u64,
+
+    /// Address of the account credited with the reward of the blocks mined by this node.
+    #[arg(long, value_name = "ADDRESS")]
+    miner_address: Option<node::Address>,
+
+    /// Create the receiver account of a transfer to an unknown address with the key in the transfer,
+    /// all nodes of a network have to use the same setting.
+    #[arg(long)]
+    allow_implicit_accounts: bool,
+
+    /// Only accept accounts whose address is derived from their key, all nodes of a network have
+    /// to use the same setting.
+    #[arg(long)]
+    require_derived_addresses: bool,
+
+    /// Smallest amount of a transfer, all nodes of a network have to use the same setting.
+    #[arg(long, value_name = "AMOUNT", default_value_t = 0)]
+    min_transfer: u64,
+
+    /// Largest amount of a transfer, unlimited if not given, all nodes of a network have to use
+    /// the same setting.
+    #[arg(long, value_name = "AMOUNT")]
+    max_transfer: Option<u64>,
+
+    /// Hash blocks with SHA3-256 instead of SHA-256, all nodes of a network have to use the same
+    /// hash function.
+    #[cfg(feature = "sha3")]
+    #[arg(long)]
+    sha3: bool,
+
+    /// Multiaddr to listen on for peers.
+    #[arg(long, value_name = "MULTIADDR", default_value = p2p::DEFAULT_LISTEN_ADDRESS)]
+    listen: String,
+
+    /// TCP port to listen on, replacing the port of `--listen`.
+    #[arg(long, value_name = "PORT")]
+    port: Option<u16>,
+
+    /// Multiaddr of a peer to join the network through, ending with its `/p2p/<peer id>`.
+    #[arg(long, value_name = "MULTIADDR")]
+    bootstrap: Vec<Multiaddr>,
+
+    /// Seconds after which a peer that is not connected and was not seen is dropped.
+    #[arg(long, value_name = "SECS", default_value_t = p2p::PEER_TIMEOUT.as_secs())]
+    peer_timeout: u64,
+
+    /// Messages a second accepted from a peer, the rest are dropped.
+    #[arg(
+        long,
+        value_name = "MSGS",
+        default_value_t = p2p::PEER_MESSAGE_RATE,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    peer_message_rate: u32,
+
+    /// Port of the HTTP API, it is not served if not given.
+    #[cfg(feature = "http-api")]
+    #[arg(long, value_name = "PORT")]
+    http_port: Option<u16>,
+}
+
+/// Resolves on SIGINT, or SIGTERM on Unix.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Can listen for SIGTERM.");
+        select! {
+            result = signal::ctrl_c() => result.expect("Can listen for SIGINT."),
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    signal::ctrl_c().await.expect("Can listen for SIGINT.");
+}
+
 #[tokio::main]
 async fn main() {
+    let args = Args::parse();
     pretty_env_logger::init();
 
-    info!("Peer Id: {}", p2p::PEER_ID.clone());
+    let genesis_config = match &args.genesis {
+        Some(path) => match node::storage::read_genesis_config(path) {
+            Ok(config) => config,
+            Err(e) => {
+                error!(
+                    target: node::SYNC_TARGET,
+                    "Error reading genesis config from {}, {}",
+                    path.display(),
+                    e
+                );
+                process::exit(1);
+            }
+        },
+        None => node::GenesisConfig::default(),
+    };
+
+    #[cfg(feature = "sha3")]
+    let mut node = if args.sha3 {
+        node::Node::with_hasher(&node::hashing::Sha3Hasher)
+    } else {
+        node::Node::new()
+    };
+    #[cfg(not(feature = "sha3"))]
+    let mut node = node::Node::new();
+    if let Err(e) = node.set_genesis(&genesis_config) {
+        error!(target: node::SYNC_TARGET, "Invalid genesis config, {}", e);
+        process::exit(1);
+    }
+    node.allow_implicit_accounts = args.allow_implicit_accounts;
+    node.require_derived_addresses = args.require_derived_addresses;
+    node.mining_enabled = !args.no_mine;
+    node.max_txs_per_block = args.max_txs_per_block;
+    node.mining_log_interval = args.mining_log_interval;
+    node.halving_interval = args.halving_interval;
+    node.max_reorg_depth = args.max_reorg_depth;
+    node.chain_id = args.chain_id;
+    node.min_transfer = args.min_transfer;
+    node.max_transfer = args.max_transfer;
+
+    if let Some(path) = &args.validate_chain {
+        match node.validate_chain_file(path) {
+            Ok(report) => {
+                println!("{}", report);
+                process::exit(report.exit_code());
+            }
+            Err(e) => {
+                eprintln!("Error reading chain file: {}", e);
+                process::exit(2);
+            }
+        }
+    }
+
+    let listen_address = match p2p::listen_address(&args.listen, args.port) {
+        Ok(address) => address,
+        Err(e) => {
+            error!(target: p2p::P2P_TARGET, "Error parsing listen address, {}", e);
+            process::exit(1);
+        }
+    };
+
+    let data_dir = match args.datadir.as_deref().map(DataDir::create).transpose() {
+        Ok(data_dir) => data_dir,
+        Err(e) => {
+            error!(target: p2p::CLI_TARGET, "Error creating data directory, {}", e);
+            process::exit(1);
+        }
+    };
+    let chain_file = args
+        .chain_file
+        .clone()
+        .or_else(|| data_dir.as_ref().map(DataDir::chain_file));
+    let wallet_file = args
+        .wallet
+        .clone()
+        .or_else(|| data_dir.as_ref().map(DataDir::wallet_file));
+    let key_dir = args
+        .key_dir
+        .clone()
+        .or_else(|| data_dir.as_ref().map(DataDir::key_dir));
+    let peers_file = data_dir.as_ref().map(DataDir::peers_file);
+
+    info!(target: p2p::P2P_TARGET, "Peer Id: {}", p2p::PEER_ID.clone());
 
     let (response_sender, mut response_receiver) = mpsc::unbounded_channel();
     let (init_sender, mut init_receiver) = mpsc::unbounded_channel();
+    let (mined_sender, mut mined_receiver) = mpsc::unbounded_channel();
+    // The sender is kept alive for the whole run, so the receiver never reports a closed channel.
+    #[cfg_attr(not(feature = "http-api"), allow(unused_variables))]
+    let (api_sender, mut api_receiver) = mpsc::unbounded_channel();
 
-    let auth_keys = Keypair::<X25519Spec>::new()
-        .into_authentic(&p2p::KEYS)
-        .expect("Can create auth keys.");
-
-    let transport = TokioTcpConfig::new()
-        .upgrade(upgrade::Version::V1)
-        .authenticate(NoiseConfig::xx(auth_keys).into_authenticated())
-        .multiplex(mplex::MplexConfig::new())
-        .boxed();
-
-    let behaviour =
-        AppBehaviour::new(lib::Node::new(), response_sender, init_sender.clone()).await;
-
-    let mut swarm = SwarmBuilder::new(transport, behaviour, *p2p::PEER_ID)
-        .executor(Box::new(|fut| {
-            spawn(fut);
-        }))
-        .build();
-
-    Swarm::listen_on(
-        &mut swarm,
-        "/ip4/0.0.0.0/tcp/0"
-            .parse()
-            .expect("Can get a local socket."),
-    )
-    .expect("Swarm can be started.");
+    if let Some(path) = chain_file.as_ref().filter(|path| path.exists()) {
+        node = match node.load_chain_file(path) {
+            Ok(node) => {
+                info!(
+                    target: node::SYNC_TARGET,
+                    "Loaded chain with {} blocks from {}",
+                    node.blocks.len(),
+                    path.display()
+                );
+                node
+            }
+            Err(e) => {
+                error!(
+                    target: node::SYNC_TARGET,
+                    "Error loading chain from {}, {}",
+                    path.display(),
+                    e
+                );
+                process::exit(1);
+            }
+        };
+    }
+    let wallet = match wallet_file.as_ref().filter(|path| path.exists()) {
+        Some(path) => match node::wallet::Wallet::load(path) {
+            Ok(wallet) => wallet,
+            Err(e) => {
+                error!(
+                    target: p2p::CLI_TARGET,
+                    "Error loading wallet from {}, {}",
+                    path.display(),
+                    e
+                );
+                process::exit(1);
+            }
+        },
+        None => node::wallet::Wallet::new(),
+    };
+    if let Some(miner_address) = args.miner_address {
+        node.miner_address = miner_address;
+    }
+
+    let mut swarm =
+        p2p::build_swarm(&p2p::KEYS, node, response_sender, mined_sender, chain_file).await;
+
+    if let Err(e) = Swarm::listen_on(&mut swarm, listen_address.clone()) {
+        error!(
+            target: p2p::P2P_TARGET,
+            "Error listening on {}, {}", listen_address, e
+        );
+        process::exit(1);
+    }
+
+    swarm.behaviour_mut().peer_timeout = Duration::from_secs(args.peer_timeout);
+    swarm.behaviour_mut().peer_message_rate = args.peer_message_rate;
+    swarm.behaviour_mut().key_dir = key_dir;
+    swarm.behaviour_mut().wallet = wallet;
+    swarm.behaviour_mut().wallet_file = wallet_file;
+    swarm.behaviour_mut().genesis_config = genesis_config;
+    let peer_store = match peers_file.as_ref().filter(|path| path.exists()) {
+        Some(path) => match PeerStore::load(path, peer_store::MAX_STORED_PEERS) {
+            Ok(peer_store) => peer_store,
+            Err(e) => {
+                error!(
+                    target: p2p::P2P_TARGET,
+                    "Error loading peers from {}, {}",
+                    path.display(),
+                    e
+                );
+                PeerStore::default()
+            }
+        },
+        None => PeerStore::default(),
+    };
+    // Peers of the last run are dialed like the bootstrap ones, before mDNS finds any.
+    let mut bootstrap = args.bootstrap.clone();
+    for address in peer_store.addresses() {
+        if !bootstrap.contains(&address) {
+            bootstrap.push(address);
+        }
+    }
+    swarm.behaviour_mut().peer_store = peer_store;
+    swarm.behaviour_mut().peers_file = peers_file;
+    swarm.behaviour_mut().audit_log = args.audit_log.clone().map(audit::AuditLog::new);
+    let network_config = p2p::NetworkConfig { bootstrap };
+    p2p::join_network(&mut swarm, &network_config);
+
+    #[cfg(feature = "http-api")]
+    if let Some(port) = args.http_port {
+        let api_sender = api_sender.clone();
+        spawn(async move {
+            if let Err(e) = api::serve(port, api_sender).await {
+                error!(target: api::API_TARGET, "Error serving HTTP API, {}", e);
+            }
+        });
+    }
 
     spawn(async move {
         sleep(Duration::from_secs(1)).await;
-        info!("Sending init event.");
+        info!(target: node::SYNC_TARGET, "Sending init event.");
         init_sender.send(true).expect("Can send init event.");
     });
 
     let mut stdin = BufReader::new(stdin()).lines();
+    let shutdown = shutdown_signal();
+    pin!(shutdown);
 
     loop {
         let event = {
@@ -71,62 +380,118 @@ async fn main() {
                 response = response_receiver.recv() => {
                     Some(p2p::EventType::LocalChainResponse(response.expect("Response exists.")))
                 },
-                _init = init_receiver.recv() => {
+                Some(_init) = init_receiver.recv() => {
                     Some(p2p::EventType::Init)
                 }
-                event = swarm.select_next_some() => {
-                    info!("Unhandled Swarm Event: {:?}", event);
-                    None
+                request = api_receiver.recv() => request.map(p2p::EventType::Api),
+                Some(mined) = mined_receiver.recv() => Some(p2p::EventType::Mined(mined)),
+                _ = &mut shutdown => Some(p2p::EventType::Shutdown),
+                event = swarm.select_next_some() => match event {
+                    SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                        Some(p2p::EventType::ConnectionEstablished(peer_id, endpoint))
+                    }
+                    SwarmEvent::ConnectionClosed { peer_id, endpoint, .. } => {
+                        Some(p2p::EventType::ConnectionClosed(peer_id, endpoint))
+                    }
+                    event => {
+                        info!(target: p2p::P2P_TARGET, "Unhandled Swarm Event: {:?}", event);
+                        None
+                    }
                 },
             }
         };
 
+        for peer_id in swarm.behaviour_mut().take_pending_bans() {
+            swarm.ban_peer_id(peer_id);
+        }
+
         if let Some(event) = event {
             match event {
                 p2p::EventType::Init => {
-                    let peers = p2p::get_list_peers(&swarm);
-                    swarm.behaviour_mut().node.genesis();
-
-                    info!("Connected nodes: {}", peers.len());
-                    if !peers.is_empty() {
-                        let req = p2p::LocalChainRequest {
-                            from_peer_id: peers
-                                .iter()
-                                .last()
-                                .expect("At least one peer.")
-                                .to_string(),
-                        };
-
-                        let json = serde_json::to_string(&req).expect("Can jsonify request.");
-                        swarm
-                            .behaviour_mut()
-                            .floodsub
-                            .publish(p2p::CHAIN_TOPIC.clone(), json.as_bytes());
+                    let peers = p2p::get_list_peers(&mut swarm);
+                    if let Some(path) = &args.import_chain {
+                        p2p::handle_import_chain(path, &mut swarm);
                     }
+
+                    info!(target: node::SYNC_TARGET, "Connected nodes: {}", peers.len());
+                    let peer = peers.last().map(|(peer, _)| *peer);
+                    swarm.behaviour_mut().init(peer);
                 }
                 p2p::EventType::LocalChainResponse(res) => {
-                    let json = serde_json::to_string(&res).expect("Can jsonify response.");
-                    swarm
-                        .behaviour_mut()
-                        .floodsub
-                        .publish(p2p::CHAIN_TOPIC.clone(), json.as_bytes());
+                    let behaviour = swarm.behaviour_mut();
+                    behaviour.publish(
+                        behaviour.chain_topic.clone(),
+                        &wire::Message::ChainResponse(res),
+                    );
+                }
+                p2p::EventType::ConnectionEstablished(peer_id, endpoint) => {
+                    swarm.behaviour_mut().record_connection(peer_id, &endpoint);
+                }
+                p2p::EventType::ConnectionClosed(peer_id, endpoint) => {
+                    swarm.behaviour_mut().remove_connection(&peer_id, &endpoint);
+                }
+                p2p::EventType::Api(request) => p2p::handle_api_request(request, &mut swarm),
+                p2p::EventType::Mined(result) => p2p::handle_mined_block(result, &mut swarm),
+                p2p::EventType::Shutdown => {
+                    p2p::handle_shutdown(&swarm);
+                    break;
                 }
                 p2p::EventType::Input(line) => match line.as_str() {
-                    "ls p" => p2p::handle_print_peers(&swarm),
+                    "ls p" | "peers" => p2p::handle_print_peers(&mut swarm),
+                    "reorgs" => p2p::handle_print_reorgs(&swarm),
+                    "ls mempool" => p2p::handle_print_mempool(&swarm),
+                    "metrics" => p2p::handle_print_metrics(&mut swarm),
+                    "ls wallet" => p2p::handle_print_wallet(&swarm),
+                    "mine" => p2p::handle_mine(&mut swarm),
+                    "verify" => p2p::handle_verify_chain(&swarm),
+                    cmd if cmd.starts_with("prune ") => p2p::handle_prune(
+                        cmd.strip_prefix("prune ").expect("Can strip"),
+                        &mut swarm,
+                    ),
+                    cmd if cmd.starts_with("peer ") => p2p::handle_print_peer(
+                        cmd.strip_prefix("peer ").expect("Can strip"),
+                        &swarm,
+                    ),
                     cmd if cmd.starts_with("ls accounts") => p2p::handle_print_accounts(&swarm),
                     cmd if cmd.starts_with("ls account") => p2p::handle_print_account(
                         cmd.strip_prefix("ls account").expect("Can strip"),
                         &swarm,
                     ),
+                    cmd if cmd.split_whitespace().next() == Some("balance") => {
+                        p2p::handle_print_balance(
+                            cmd.trim_start().strip_prefix("balance").expect("Can strip"),
+                            &swarm,
+                        )
+                    }
+                    cmd if cmd.starts_with("ls block ") => p2p::handle_print_block(
+                        cmd.strip_prefix("ls block ").expect("Can strip"),
+                        &swarm,
+                    ),
                     cmd if cmd.starts_with("ls b") => p2p::handle_print_chain(&swarm),
                     cmd if cmd.starts_with("create account") => {
                         p2p::handle_create_account(&mut swarm)
                     }
+                    cmd if cmd.starts_with("export ") => p2p::handle_export_chain(
+                        Path::new(cmd.strip_prefix("export ").expect("Can strip").trim()),
+                        &swarm,
+                    ),
+                    cmd if cmd.starts_with("dump ") => p2p::handle_dump_state(
+                        Path::new(cmd.strip_prefix("dump ").expect("Can strip").trim()),
+                        &swarm,
+                    ),
+                    cmd if cmd.starts_with("import key ") => p2p::handle_import_key(
+                        cmd.strip_prefix("import key ").expect("Can strip"),
+                        &mut swarm,
+                    ),
+                    cmd if cmd.starts_with("import ") => p2p::handle_import_chain(
+                        Path::new(cmd.strip_prefix("import ").expect("Can strip").trim()),
+                        &mut swarm,
+                    ),
                     cmd if cmd.starts_with("transfer ") => p2p::handle_transfer(
                         cmd.strip_prefix("transfer ").expect("Can strip"),
                         &mut swarm,
                     ),
-                    _ => error!("Unknown command"),
+                    _ => error!(target: p2p::CLI_TARGET, "Unknown command"),
                 },
             }
         }
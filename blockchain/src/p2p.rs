@@ -1,98 +1,945 @@
-use crate::lib::{Address, Node, Block, Data};
+use crate::api::ApiRequest;
+use crate::audit::AuditLog;
+use crate::peer_store::PeerStore;
+use crate::wire::{self, Message, WireError};
+use chrono::Utc;
 use libp2p::{
-    floodsub::{Floodsub, FloodsubEvent, Topic},
+    core::{muxing::StreamMuxerBox, transport::Boxed, upgrade, ConnectedPoint},
+    gossipsub::{
+        Gossipsub, GossipsubConfigBuilder, GossipsubEvent, GossipsubMessage, IdentTopic as Topic,
+        MessageAcceptance, MessageAuthenticity, MessageId, ValidationMode,
+    },
     identity,
+    kad::{record::store::MemoryStore, Kademlia, KademliaEvent},
     mdns::{Mdns, MdnsEvent},
-    swarm::{NetworkBehaviourEventProcess, Swarm},
-    NetworkBehaviour, PeerId,
+    mplex,
+    multiaddr::Protocol,
+    noise::{Keypair, NoiseConfig, X25519Spec},
+    ping::{Ping, PingConfig, PingEvent, PingSuccess},
+    swarm::{NetworkBehaviour as _, NetworkBehaviourEventProcess, Swarm, SwarmBuilder},
+    tcp::TokioTcpConfig,
+    Multiaddr, NetworkBehaviour, PeerId, Transport,
+};
+use log::{debug, error, info, warn};
+use node::crypto::derive_public_key;
+use node::storage::{read_chain, write_chain, write_private_key};
+use node::wallet::Wallet;
+use node::{
+    Address, Block, BlockError, Data, GenesisConfig, MiningError, Node, PrivateKey, SYNC_TARGET,
 };
-use log::{error, info};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use tokio::sync::mpsc;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::{sync::mpsc, task};
+
+pub const P2P_TARGET: &str = "p2p";
+pub const CLI_TARGET: &str = "cli";
+
+const MINING_NONCE_BUDGET: u64 = 10_000_000;
+/// Larger messages are dropped without being deserialized.
+pub const MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+/// Most blocks sent in one chain response, larger requests are clamped.
+pub const MAX_CHAIN_CHUNK: u64 = 32;
+/// Most blocks a fork is fetched past the local height when `Node::max_reorg_depth` is not set.
+pub const MAX_FORK_LEAD: u64 = 1024;
+/// Longer than the mDNS query interval, so peers on the LAN are seen again before they expire.
+pub const PEER_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Messages a second accepted from a peer, also the size of the burst it may send at once.
+pub const PEER_MESSAGE_RATE: u32 = 50;
+/// Peers whose reputation drops to this score are banned.
+pub const BAN_THRESHOLD: i32 = -100;
+pub const INVALID_BLOCK_PENALTY: i32 = 25;
+pub const INVALID_CHAIN_PENALTY: i32 = 50;
+pub const MALFORMED_MESSAGE_PENALTY: i32 = 10;
+/// Bytes of an undecodable message logged, enough to tell what the peer sent.
+const LOGGED_PAYLOAD_BYTES: usize = 32;
 
 pub static KEYS: Lazy<identity::Keypair> = Lazy::new(identity::Keypair::generate_ed25519);
 pub static PEER_ID: Lazy<PeerId> = Lazy::new(|| PeerId::from(KEYS.public()));
-pub static CHAIN_TOPIC: Lazy<Topic> = Lazy::new(|| Topic::new("chains"));
-pub static BLOCK_TOPIC: Lazy<Topic> = Lazy::new(|| Topic::new("blocks"));
+/// Topic of the chain requests and responses of the network with the chain id.
+pub fn chain_topic(chain_id: u64) -> Topic {
+    network_topic("chains", chain_id)
+}
+
+/// Topic of the blocks of the network with the chain id.
+pub fn block_topic(chain_id: u64) -> Topic {
+    network_topic("blocks", chain_id)
+}
+
+/// Networks with different chain ids do not share topics, the default one keeps the bare names.
+fn network_topic(name: &str, chain_id: u64) -> Topic {
+    match chain_id {
+        0 => Topic::new(name),
+        chain_id => Topic::new(format!("{}-{}", name, chain_id)),
+    }
+}
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Blocks of the responder chain starting at `from_height`, out of `chain_len` blocks.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ChainResponse {
     pub blocks: Vec<Block>,
     pub receiver: String,
+    pub from_height: u64,
+    pub chain_len: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Asks the peer `from_peer_id` for at most `max_blocks` blocks starting at `from_height`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct LocalChainRequest {
     pub from_peer_id: String,
+    pub from_height: u64,
+    pub max_blocks: u64,
+}
+
+/// Catching up with the chain of a peer in chunks, from the height of the local chain. Blocks
+/// extending the local chain are added one by one, so progress is kept if the peer goes away,
+/// while a fork is collected from after the genesis block and adopted as a whole.
+pub struct ChainSync {
+    pub peer: String,
+    pub next_height: u64,
+    pub fork: Option<Vec<Block>>,
+    /// Whether the peer sent an invalid block or chain.
+    pub invalid: bool,
+}
+
+/// Token bucket of the messages received from a peer, refilled at the rate limit up to one
+/// second worth of messages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateState {
+    tokens: f64,
+    refilled_at: Instant,
+    throttled: bool,
 }
 
+/// Score of a peer, lowered for each invalid block or chain it sends.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Reputation(pub i32);
+
 pub enum EventType {
     LocalChainResponse(ChainResponse),
     Input(String),
     Init,
+    ConnectionEstablished(PeerId, ConnectedPoint),
+    ConnectionClosed(PeerId, ConnectedPoint),
+    Api(ApiRequest),
+    Mined(Result<Block, MiningError>),
+    Shutdown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ConnectionDirection {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionRecord {
+    pub address: Multiaddr,
+    pub direction: ConnectionDirection,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub connected: bool,
+    pub connections: Vec<ConnectionInfo>,
+    pub latency_ms: Option<u128>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct ConnectionInfo {
+    pub address: String,
+    pub protocols: Vec<String>,
+    pub direction: ConnectionDirection,
+}
+
+/// Block being mined in the background, its data goes back to the mempool if it is not added.
+pub struct MiningTask {
+    pub id: u64,
+    pub data: Data,
+    cancel: Arc<AtomicBool>,
+}
+
+pub const DEFAULT_LISTEN_ADDRESS: &str = "/ip4/0.0.0.0/tcp/0";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ListenAddressError {
+    Invalid(String),
+    NoTcpPort(Multiaddr),
+}
+
+/// Peers to join the network through, in addition to the ones found with mDNS.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkConfig {
+    pub bootstrap: Vec<Multiaddr>,
 }
 
 #[derive(NetworkBehaviour)]
 pub struct AppBehaviour {
-    pub floodsub: Floodsub,
+    pub gossipsub: Gossipsub,
     pub mdns: Mdns,
+    pub kademlia: Kademlia<MemoryStore>,
+    pub ping: Ping,
+    #[behaviour(ignore)]
+    pub peer_id: PeerId,
+    #[behaviour(ignore)]
+    pub chain_topic: Topic,
+    #[behaviour(ignore)]
+    pub block_topic: Topic,
     #[behaviour(ignore)]
     pub response_sender: mpsc::UnboundedSender<ChainResponse>,
     #[behaviour(ignore)]
-    pub init_sender: mpsc::UnboundedSender<bool>,
+    pub mined_sender: mpsc::UnboundedSender<Result<Block, MiningError>>,
+    #[behaviour(ignore)]
+    pub mining: Option<MiningTask>,
+    #[behaviour(ignore)]
+    pub sync: Option<ChainSync>,
     #[behaviour(ignore)]
     pub node: Node,
+    #[behaviour(ignore)]
+    pub chain_file: Option<PathBuf>,
+    #[behaviour(ignore)]
+    pub connections: HashMap<PeerId, Vec<ConnectionRecord>>,
+    #[behaviour(ignore)]
+    pub latencies: HashMap<PeerId, Duration>,
+    #[behaviour(ignore)]
+    pub last_seen: HashMap<PeerId, Instant>,
+    /// Peers neither connected nor seen for this long are dropped.
+    #[behaviour(ignore)]
+    pub peer_timeout: Duration,
+    /// Messages a second accepted from a peer, the rest are dropped.
+    #[behaviour(ignore)]
+    pub peer_message_rate: u32,
+    #[behaviour(ignore)]
+    pub rate_limits: HashMap<PeerId, RateState>,
+    #[behaviour(ignore)]
+    pub reputations: HashMap<PeerId, Reputation>,
+    /// Messages of each peer which could not be decoded.
+    #[behaviour(ignore)]
+    pub malformed_messages: HashMap<PeerId, u64>,
+    #[behaviour(ignore)]
+    pub banned: HashSet<PeerId>,
+    /// Peers banned since the swarm last disconnected them.
+    #[behaviour(ignore)]
+    pending_bans: Vec<PeerId>,
+    /// Directory the private keys of created accounts are saved to.
+    #[behaviour(ignore)]
+    pub key_dir: Option<PathBuf>,
+    #[behaviour(ignore)]
+    pub wallet: Wallet,
+    /// File the wallet is saved to after each change, kept in memory only if not set.
+    #[behaviour(ignore)]
+    pub wallet_file: Option<PathBuf>,
+    /// Genesis the node starts a chain with when there is no peer to sync one from.
+    #[behaviour(ignore)]
+    pub genesis_config: GenesisConfig,
+    /// Peers dialed successfully, dialed again on the next start.
+    #[behaviour(ignore)]
+    pub peer_store: PeerStore,
+    /// File the peer store is saved to after each change, kept in memory only if not set.
+    #[behaviour(ignore)]
+    pub peers_file: Option<PathBuf>,
+    /// Where rejected blocks and transfers are recorded, only logged if not set.
+    #[behaviour(ignore)]
+    pub audit_log: Option<AuditLog>,
 }
 
 impl AppBehaviour {
     pub async fn new(
-        node: Node,
+        keys: &identity::Keypair,
+        mut node: Node,
         response_sender: mpsc::UnboundedSender<ChainResponse>,
-        init_sender: mpsc::UnboundedSender<bool>,
+        mined_sender: mpsc::UnboundedSender<Result<Block, MiningError>>,
+        chain_file: Option<PathBuf>,
     ) -> Self {
+        let config = GossipsubConfigBuilder::default()
+            .validation_mode(ValidationMode::Strict)
+            // Received messages are forwarded only once reported as accepted.
+            .validate_messages()
+            .message_id_fn(message_id)
+            .build()
+            .expect("Can create gossipsub config.");
+        let peer_id = PeerId::from(keys.public());
+        node.miner = Some(peer_id.to_string());
         let mut behaviour = Self {
+            chain_topic: chain_topic(node.chain_id),
+            block_topic: block_topic(node.chain_id),
             node,
-            floodsub: Floodsub::new(*PEER_ID),
+            chain_file,
+            gossipsub: Gossipsub::new(MessageAuthenticity::Signed(keys.clone()), config)
+                .expect("Can create gossipsub."),
             mdns: Mdns::new(Default::default())
                 .await
                 .expect("Can created mdns."),
+            kademlia: Kademlia::new(peer_id, MemoryStore::new(peer_id)),
+            ping: Ping::new(PingConfig::new()),
+            peer_id,
             response_sender,
-            init_sender,
+            mined_sender,
+            mining: None,
+            sync: None,
+            connections: HashMap::new(),
+            latencies: HashMap::new(),
+            last_seen: HashMap::new(),
+            peer_timeout: PEER_TIMEOUT,
+            peer_message_rate: PEER_MESSAGE_RATE,
+            rate_limits: HashMap::new(),
+            reputations: HashMap::new(),
+            malformed_messages: HashMap::new(),
+            banned: HashSet::new(),
+            pending_bans: vec![],
+            key_dir: None,
+            wallet: Wallet::new(),
+            wallet_file: None,
+            genesis_config: GenesisConfig::default(),
+            peer_store: PeerStore::default(),
+            peers_file: None,
+            audit_log: None,
         };
-        behaviour.floodsub.subscribe(CHAIN_TOPIC.clone());
-        behaviour.floodsub.subscribe(BLOCK_TOPIC.clone());
+        for topic in [&behaviour.chain_topic, &behaviour.block_topic] {
+            behaviour
+                .gossipsub
+                .subscribe(topic)
+                .expect("Can subscribe to topic.");
+        }
 
         behaviour
     }
-}
 
-impl NetworkBehaviourEventProcess<FloodsubEvent> for AppBehaviour {
-    fn inject_event(&mut self, event: FloodsubEvent) {
-        if let FloodsubEvent::Message(msg) = event {
-            if let Ok(res) = serde_json::from_slice::<ChainResponse>(&msg.data) {
-                if res.receiver == PEER_ID.to_string() {
-                    info!("Response from {}:", msg.source);
-                    res.blocks.iter().for_each(|r| info!("{:?}", r));
+    pub fn publish(&mut self, topic: Topic, message: &Message) {
+        if let Err(e) = self.gossipsub.publish(topic.clone(), wire::encode(message)) {
+            warn!(target: P2P_TARGET, "Could not publish to {}, {:?}", topic, e);
+        }
+    }
+
+    pub fn persist(&self) {
+        if let Some(path) = &self.chain_file {
+            match self.node.save_to_file(path) {
+                Ok(()) => debug!(target: SYNC_TARGET, "Saved chain to {}", path.display()),
+                Err(e) => {
+                    error!(target: SYNC_TARGET, "Error saving chain to {}, {}", path.display(), e)
+                }
+            }
+        }
+    }
+
+    pub fn save_peers(&self) {
+        if let Some(path) = &self.peers_file {
+            match self.peer_store.save(path) {
+                Ok(()) => debug!(target: P2P_TARGET, "Saved peers to {}", path.display()),
+                Err(e) => {
+                    error!(target: P2P_TARGET, "Error saving peers to {}, {}", path.display(), e)
+                }
+            }
+        }
+    }
+
+    pub fn save_wallet(&self) {
+        if let Some(path) = &self.wallet_file {
+            match self.wallet.save(path) {
+                Ok(()) => debug!(target: CLI_TARGET, "Saved wallet to {}", path.display()),
+                Err(e) => {
+                    error!(target: CLI_TARGET, "Error saving wallet to {}, {}", path.display(), e)
+                }
+            }
+        }
+    }
+
+    /// Mines a block with the data on a blocking thread, the result is sent to `mined_sender`.
+    pub fn start_mining(&mut self, data: Data) {
+        if !self.node.mining_enabled {
+            error!(target: CLI_TARGET, "Mining is disabled on this node.");
+            self.node.requeue(data);
+            return;
+        }
+        let latest_block = match self.node.tip() {
+            Some(block) => block,
+            None => {
+                error!(target: CLI_TARGET, "No genesis block yet, try again later.");
+                self.node.requeue(data);
+                return;
+            }
+        };
+        let id = latest_block.id + 1;
+        let previous_hash = latest_block.hash.clone();
+        let difficulty = self.node.compute_difficulty();
+        let (hasher, chain_id, miner, log_interval) = (
+            self.node.hasher(),
+            self.node.chain_id,
+            self.node.miner.clone(),
+            self.node.mining_log_interval,
+        );
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let mined_sender = self.mined_sender.clone();
+        let (block_data, block_cancel) = (data.clone(), cancel.clone());
+        task::spawn_blocking(move || {
+            let result = Block::try_new(
+                id,
+                previous_hash,
+                block_data,
+                difficulty,
+                MINING_NONCE_BUDGET,
+                mining_threads(),
+                log_interval,
+                &block_cancel,
+                hasher,
+                chain_id,
+                miner,
+            );
+            // The receiver is gone only when the node is shutting down.
+            let _ = mined_sender.send(result);
+        });
+        self.mining = Some(MiningTask { id, data, cancel });
+    }
+
+    pub fn cancel_mining(&self) {
+        if let Some(task) = &self.mining {
+            task.cancel.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Stops mining once a competing block for the same height is in the chain.
+    fn cancel_stale_mining(&self) {
+        if matches!(&self.mining, Some(task) if self.node.height() >= Some(task.id)) {
+            info!(target: P2P_TARGET, "Chain moved past the mined block, cancelling mining");
+            self.cancel_mining();
+        }
+    }
+
+    /// Queues the transfer from the local API or console, recording it in the audit log if it is
+    /// rejected.
+    pub fn queue_transfer(&mut self, transfer: Data) -> bool {
+        match self.node.try_add_to_mempool(transfer.clone()) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!(target: P2P_TARGET, "Rejecting transfer, {}", e);
+                if let Some(audit_log) = &self.audit_log {
+                    audit_log.transfer(&transfer, None, &e);
+                }
+                false
+            }
+        }
+    }
+
+    /// Syncs the chain from the peer if there is one. Only a node without peers starts a chain
+    /// of its own, a joining node does it only if the sync leaves it without one.
+    pub fn init(&mut self, peer: Option<PeerId>) {
+        match peer {
+            Some(peer) => self.request_chain(peer.to_string()),
+            None => self.genesis_if_empty(),
+        }
+    }
+
+    fn genesis_if_empty(&mut self) {
+        if self.node.blocks.is_empty() {
+            info!(target: SYNC_TARGET, "No chain to sync, starting a new one");
+            if let Err(e) = self.node.genesis_from_config(&self.genesis_config) {
+                error!(target: SYNC_TARGET, "Error creating genesis block, {}", e);
+                return;
+            }
+            self.persist();
+        }
+    }
+
+    /// Starts fetching the chain of the peer, from the height of the local chain.
+    pub fn request_chain(&mut self, peer: String) {
+        let sync = ChainSync::new(peer, &self.node);
+        let req = sync.next_request();
+        self.sync = Some(sync);
+        self.publish(self.chain_topic.clone(), &Message::ChainRequest(req));
+    }
+
+    /// Only the chunks requested by this node are accepted, so that gossipsub does not forward
+    /// the chunks meant for other peers to the whole mesh.
+    fn handle_chain_response(&mut self, res: ChainResponse, source: PeerId) -> MessageAcceptance {
+        if res.receiver != self.peer_id.to_string() {
+            return MessageAcceptance::Ignore;
+        }
+        let mut sync = match self.sync.take() {
+            Some(sync) if sync.peer == source.to_string() => sync,
+            sync => {
+                self.sync = sync;
+                debug!(target: SYNC_TARGET, "Ignoring unrequested chain from {}", source);
+                return MessageAcceptance::Ignore;
+            }
+        };
+        info!(
+            target: SYNC_TARGET,
+            "Response from {}: {} blocks from height {} of {}",
+            source,
+            res.blocks.len(),
+            res.from_height,
+            res.chain_len
+        );
+        let tip = self.node.tip().map(|block| block.hash.clone());
+        let done = sync.add_chunk(&mut self.node, res);
+        if self.node.tip().map(|block| &block.hash) != tip.as_ref() {
+            self.persist();
+        }
+        if sync.invalid {
+            self.penalize(source, INVALID_CHAIN_PENALTY);
+        }
+        if !done {
+            let req = sync.next_request();
+            self.sync = Some(sync);
+            self.publish(self.chain_topic.clone(), &Message::ChainRequest(req));
+        } else {
+            self.genesis_if_empty();
+        }
+        MessageAcceptance::Accept
+    }
+
+    /// Adds the block if it extends the chain, only such blocks are forwarded to other peers.
+    fn handle_block(&mut self, block: Block, source: PeerId) -> MessageAcceptance {
+        if self.node.has_block(&block) {
+            debug!(target: P2P_TARGET, "Ignoring known block {} from {}", block.id, source);
+            return MessageAcceptance::Ignore;
+        }
+        let expected_id = match self.node.height() {
+            Some(height) => height + 1,
+            None => {
+                debug!(target: P2P_TARGET, "Ignoring block {} before genesis", block.id);
+                return MessageAcceptance::Ignore;
+            }
+        };
+        if block.id > expected_id {
+            // A block right after the missing one most likely overtook its parent in gossip, it
+            // is added once the parent arrives. A block further ahead means the node fell behind.
+            let id = block.id;
+            self.node.add_orphan(block);
+            if id > expected_id + 1 && self.sync.is_none() {
+                info!(
+                    target: SYNC_TARGET,
+                    "Block {} from {} is ahead of the local chain, syncing", id, source
+                );
+                self.request_chain(source.to_string());
+            }
+            return MessageAcceptance::Ignore;
+        }
+        if block.id != expected_id {
+            info!(
+                target: P2P_TARGET,
+                "Ignoring block {} from {}, expected block {}", block.id, source, expected_id
+            );
+            return MessageAcceptance::Ignore;
+        }
+
+        info!(target: P2P_TARGET, "Received new block from {}", source);
+        match self.node.try_add_block(block.clone()) {
+            Ok(()) => {
+                self.persist();
+                MessageAcceptance::Accept
+            }
+            Err(e) => {
+                if let Some(audit_log) = &self.audit_log {
+                    audit_log.block(&block, Some(source.to_string()), &e);
+                }
+                if is_forged(&e) {
+                    warn!(target: P2P_TARGET, "Rejecting block from {}, {}", source, e);
+                    self.penalize(source, INVALID_BLOCK_PENALTY);
+                    MessageAcceptance::Reject
+                } else {
+                    info!(target: P2P_TARGET, "Ignoring block from {}, {}", source, e);
+                    MessageAcceptance::Ignore
+                }
+            }
+        }
+    }
+
+    fn handle_message(&mut self, data: &[u8], source: PeerId) -> MessageAcceptance {
+        match wire::decode(data) {
+            Ok(Message::ChainResponse(res)) => self.handle_chain_response(res, source),
+            Ok(Message::ChainRequest(req)) => self.handle_chain_request(req, source),
+            Ok(Message::Block(block)) => self.handle_block(*block, source),
+            Err(e) => {
+                warn!(target: P2P_TARGET, "Dropping message from {}, {}", source, e);
+                debug!(
+                    target: P2P_TARGET,
+                    "Undecodable message from {}: {}{}",
+                    source,
+                    hex::encode(&data[..data.len().min(LOGGED_PAYLOAD_BYTES)]),
+                    if data.len() > LOGGED_PAYLOAD_BYTES { "..." } else { "" }
+                );
+                *self.malformed_messages.entry(source).or_default() += 1;
+                // A peer on another wire version or codec is not misbehaving.
+                if matches!(e, WireError::MissingHeader | WireError::Malformed(_)) {
+                    self.penalize(source, MALFORMED_MESSAGE_PENALTY);
+                }
+                MessageAcceptance::Reject
+            }
+        }
+    }
+
+    fn handle_chain_request(
+        &mut self,
+        req: LocalChainRequest,
+        source: PeerId,
+    ) -> MessageAcceptance {
+        if self.peer_id.to_string() != req.from_peer_id {
+            return MessageAcceptance::Ignore;
+        }
+        let blocks = chain_slice(&self.node.blocks, req.from_height, req.max_blocks);
+        info!(
+            target: SYNC_TARGET,
+            "Sending {} blocks from height {} to {}",
+            blocks.len(),
+            req.from_height,
+            source
+        );
+        if let Err(e) = self.response_sender.send(ChainResponse {
+            blocks: blocks.to_vec(),
+            receiver: source.to_string(),
+            from_height: req.from_height,
+            chain_len: self.node.height().map_or(0, |height| height + 1),
+        }) {
+            error!(target: SYNC_TARGET, "Error sending response via channel, {}", e);
+        }
+        MessageAcceptance::Accept
+    }
+
+    pub fn record_connection(&mut self, peer_id: PeerId, endpoint: &ConnectedPoint) {
+        self.see_peer(peer_id);
+        // The address of an inbound connection is an ephemeral port of the peer, not worth dialing.
+        if let ConnectedPoint::Dialer { address } = endpoint {
+            self.peer_store
+                .record(peer_id, address.clone(), Utc::now().timestamp());
+            self.save_peers();
+        }
+        self.connections
+            .entry(peer_id)
+            .or_default()
+            .push(ConnectionRecord::from(endpoint));
+    }
 
-                    self.node.blocks = self.node.choose_chain(self.node.blocks.clone(), res.blocks);
+    pub fn remove_connection(&mut self, peer_id: &PeerId, endpoint: &ConnectedPoint) {
+        self.see_peer(*peer_id);
+        let record = ConnectionRecord::from(endpoint);
+        if let Some(records) = self.connections.get_mut(peer_id) {
+            records.retain(|r| r != &record);
+            if records.is_empty() {
+                self.connections.remove(peer_id);
+                self.latencies.remove(peer_id);
+                if self
+                    .sync
+                    .as_ref()
+                    .is_some_and(|sync| sync.peer == peer_id.to_string())
+                {
+                    info!(target: SYNC_TARGET, "Peer {} disconnected, stopping sync", peer_id);
+                    self.sync = None;
+                    self.genesis_if_empty();
                 }
-            } else if let Ok(res) = serde_json::from_slice::<LocalChainRequest>(&msg.data) {
-                info!("Sending local chain to {}", msg.source.to_string());
-                let peer_id = res.from_peer_id;
-                if PEER_ID.to_string() == peer_id {
-                    if let Err(e) = self.response_sender.send(ChainResponse {
-                        blocks: self.node.blocks.clone(),
-                        receiver: msg.source.to_string(),
-                    }) {
-                        error!("Error sending response via channel, {}", e);
+            }
+        }
+    }
+
+    fn see_peer(&mut self, peer_id: PeerId) {
+        self.last_seen.insert(peer_id, Instant::now());
+    }
+
+    /// Takes a token from the bucket of the peer, logging when it starts or stops being throttled.
+    fn allow_message(&mut self, peer_id: PeerId) -> bool {
+        let rate = self.peer_message_rate;
+        let now = Instant::now();
+        let state = self
+            .rate_limits
+            .entry(peer_id)
+            .or_insert_with(|| RateState::new(rate, now));
+        let allowed = state.try_consume(rate, now);
+        if !allowed && !state.throttled {
+            warn!(
+                target: P2P_TARGET,
+                "Throttling peer {}, more than {} messages a second", peer_id, rate
+            );
+        } else if allowed && state.throttled {
+            info!(target: P2P_TARGET, "Peer {} is no longer throttled", peer_id);
+        }
+        state.throttled = !allowed;
+        allowed
+    }
+
+    /// Lowers the reputation of the peer, banning it once it drops to `BAN_THRESHOLD`.
+    fn penalize(&mut self, peer_id: PeerId, penalty: i32) {
+        if self.banned.contains(&peer_id) {
+            return;
+        }
+        let reputation = self.reputations.entry(peer_id).or_default();
+        let banned = reputation.penalize(penalty);
+        debug!(target: P2P_TARGET, "Reputation of {} is {}", peer_id, reputation.0);
+        if banned {
+            self.ban(peer_id);
+        }
+    }
+
+    fn ban(&mut self, peer_id: PeerId) {
+        warn!(target: P2P_TARGET, "Banning peer {} for sending invalid data", peer_id);
+        self.banned.insert(peer_id);
+        self.pending_bans.push(peer_id);
+        self.gossipsub.remove_explicit_peer(&peer_id);
+        self.gossipsub.blacklist_peer(&peer_id);
+        self.kademlia.remove_peer(&peer_id);
+        if self
+            .sync
+            .as_ref()
+            .is_some_and(|sync| sync.peer == peer_id.to_string())
+        {
+            self.sync = None;
+            self.genesis_if_empty();
+        }
+    }
+
+    /// Peers to be disconnected and refused by the swarm, which the behaviour cannot reach.
+    pub fn take_pending_bans(&mut self) -> Vec<PeerId> {
+        std::mem::take(&mut self.pending_bans)
+    }
+
+    fn is_peer_alive(&self, peer_id: &PeerId) -> bool {
+        self.connections.contains_key(peer_id) || self.last_seen.contains_key(peer_id)
+    }
+
+    /// Drops the peers which are not connected and were not seen within `peer_timeout`.
+    pub fn prune_stale_peers(&mut self) {
+        let stale: Vec<PeerId> = self
+            .last_seen
+            .iter()
+            .filter(|(peer_id, seen)| {
+                !self.connections.contains_key(peer_id) && seen.elapsed() > self.peer_timeout
+            })
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+        for peer_id in stale {
+            info!(target: P2P_TARGET, "Dropping stale peer {}", peer_id);
+            self.last_seen.remove(&peer_id);
+            self.rate_limits.remove(&peer_id);
+            self.gossipsub.remove_explicit_peer(&peer_id);
+            self.kademlia.remove_peer(&peer_id);
+        }
+    }
+}
+
+impl Reputation {
+    /// Lowers the score, returns whether the peer is to be banned.
+    pub fn penalize(&mut self, penalty: i32) -> bool {
+        self.0 = self.0.saturating_sub(penalty);
+        self.0 <= BAN_THRESHOLD
+    }
+}
+
+impl RateState {
+    pub fn new(rate: u32, now: Instant) -> Self {
+        Self {
+            tokens: rate as f64,
+            refilled_at: now,
+            throttled: false,
+        }
+    }
+
+    /// Refills the bucket for the time elapsed since the last message, then takes a token if
+    /// there is one.
+    pub fn try_consume(&mut self, rate: u32, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * rate as f64).min(rate as f64);
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl ChainSync {
+    pub fn new(peer: String, node: &Node) -> Self {
+        Self {
+            peer,
+            next_height: node.height().map_or(0, |height| height + 1),
+            fork: None,
+            invalid: false,
+        }
+    }
+
+    pub fn next_request(&self) -> LocalChainRequest {
+        LocalChainRequest {
+            from_peer_id: self.peer.clone(),
+            from_height: self.next_height,
+            max_blocks: MAX_CHAIN_CHUNK,
+        }
+    }
+
+    /// Adds the blocks of the chunk to the node, returns whether the sync is over.
+    pub fn add_chunk(&mut self, node: &mut Node, res: ChainResponse) -> bool {
+        if res.from_height != self.next_height || res.blocks.is_empty() {
+            return true;
+        }
+        self.next_height += res.blocks.len() as u64;
+        if let Some(fork) = &mut self.fork {
+            let validated = fork.len();
+            fork.extend(res.blocks);
+            let invalid = node.first_invalid_block_from(fork, validated).is_some();
+            // The peer may claim any length, the fork is fetched only as far as it could be adopted.
+            let max_height = node
+                .height()
+                .unwrap_or(0)
+                .saturating_add(node.max_reorg_depth.unwrap_or(MAX_FORK_LEAD));
+            let too_long = self.next_height > max_height;
+            if !invalid && !too_long && self.next_height < res.chain_len {
+                return false;
+            }
+            let mut fork = self.fork.take().expect("Fork is being fetched.");
+            if invalid {
+                warn!(
+                    target: SYNC_TARGET,
+                    "Chain of {} has invalid blocks, only its valid prefix is kept", self.peer
+                );
+                self.invalid = true;
+            } else if too_long {
+                info!(
+                    target: SYNC_TARGET,
+                    "Chain of {} is too far ahead, keeping it up to height {}", self.peer, max_height
+                );
+                let len = fork
+                    .iter()
+                    .take_while(|block| block.id <= max_height)
+                    .count();
+                fork.truncate(len);
+            }
+            match node.choose_chain(node.blocks.clone(), fork) {
+                Ok(chosen) if chosen != node.blocks => {
+                    if !node.adopt_chain(chosen) {
+                        error!(target: SYNC_TARGET, "Could not adopt received chain.");
                     }
                 }
-            } else if let Ok(block) = serde_json::from_slice::<Block>(&msg.data) {
-                info!("Received new block from {}", msg.source.to_string());
-                self.node.try_add_block(block);
+                Ok(_) => {}
+                Err(e) => error!(target: SYNC_TARGET, "Dropping received chain, {}", e),
             }
+            return true;
+        }
+
+        for block in res.blocks {
+            if node.tip().map(|tip| &tip.hash) != Some(&block.previous_hash) {
+                // The peer is on another fork, it is fetched again from after the genesis block,
+                // or after the checkpoint if the local chain is pruned.
+                info!(
+                    target: SYNC_TARGET,
+                    "Block {} of {} is on another fork, fetching its chain", block.id, self.peer
+                );
+                self.fork = Some(node.blocks.iter().take(1).cloned().collect());
+                self.next_height = node.blocks.first().map_or(0, |first| first.id + 1);
+                return false;
+            }
+            let id = block.id;
+            if let Err(e) = node.try_add_block(block) {
+                warn!(target: SYNC_TARGET, "Stopping sync, block {} is invalid, {}", id, e);
+                self.invalid = is_forged(&e);
+                return true;
+            }
+        }
+        self.next_height >= res.chain_len
+    }
+}
+
+/// Forged blocks are invalid for every peer, the others may be valid on another fork.
+fn is_forged(e: &BlockError) -> bool {
+    matches!(
+        e,
+        BlockError::TooLarge(_)
+            | BlockError::MerkleRootMismatch
+            | BlockError::HashMismatch
+            | BlockError::NestedBatch
+    )
+}
+
+/// At most `MAX_CHAIN_CHUNK` blocks of the chain, starting at `from_height`. None are returned
+/// from below the first block of a pruned chain.
+fn chain_slice(blocks: &[Block], from_height: u64, max_blocks: u64) -> &[Block] {
+    let first = blocks.first().map_or(0, |block| block.id);
+    let start = from_height
+        .checked_sub(first)
+        .map_or(blocks.len(), |start| {
+            usize::try_from(start).unwrap_or(usize::MAX)
+        })
+        .min(blocks.len());
+    let len = max_blocks.min(MAX_CHAIN_CHUNK) as usize;
+    &blocks[start..blocks.len().min(start + len)]
+}
+
+impl From<&ConnectedPoint> for ConnectionRecord {
+    fn from(endpoint: &ConnectedPoint) -> Self {
+        match endpoint {
+            ConnectedPoint::Dialer { address } => Self {
+                address: address.clone(),
+                direction: ConnectionDirection::Outbound,
+            },
+            ConnectedPoint::Listener { send_back_addr, .. } => Self {
+                address: send_back_addr.clone(),
+                direction: ConnectionDirection::Inbound,
+            },
+        }
+    }
+}
+
+impl PeerInfo {
+    pub fn new(
+        peer_id: &PeerId,
+        connected: bool,
+        records: &[ConnectionRecord],
+        latency: Option<Duration>,
+    ) -> Self {
+        Self {
+            peer_id: peer_id.to_string(),
+            connected,
+            connections: records
+                .iter()
+                .map(|record| ConnectionInfo {
+                    address: record.address.to_string(),
+                    protocols: record.address.iter().map(protocol_name).collect(),
+                    direction: record.direction,
+                })
+                .collect(),
+            latency_ms: latency.map(|latency| latency.as_millis()),
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<GossipsubEvent> for AppBehaviour {
+    fn inject_event(&mut self, event: GossipsubEvent) {
+        if let GossipsubEvent::Message {
+            propagation_source: source,
+            message_id,
+            message: msg,
+        } = event
+        {
+            self.see_peer(source);
+            // Dropped before decoding, without penalizing the peer, as a burst may be relayed.
+            let acceptance = if !self.allow_message(source) {
+                MessageAcceptance::Ignore
+            } else if msg.data.len() > MAX_MESSAGE_BYTES {
+                warn!(
+                    target: P2P_TARGET,
+                    "Dropping message of {} bytes from {}",
+                    msg.data.len(),
+                    source
+                );
+                MessageAcceptance::Reject
+            } else {
+                self.handle_message(&msg.data, source)
+            };
+            if let Err(e) =
+                self.gossipsub
+                    .report_message_validation_result(&message_id, &source, acceptance)
+            {
+                warn!(target: P2P_TARGET, "Error forwarding message, {:?}", e);
+            }
+            self.cancel_stale_mining();
         }
     }
 }
@@ -102,101 +949,1418 @@ impl NetworkBehaviourEventProcess<MdnsEvent> for AppBehaviour {
         match event {
             MdnsEvent::Discovered(discovered_list) => {
                 for (peer, _addr) in discovered_list {
-                    self.floodsub.add_node_to_partial_view(peer);
+                    self.see_peer(peer);
+                    self.gossipsub.add_explicit_peer(&peer);
                 }
             }
+            // Each expired record is one address, the peer is kept while mDNS has another one.
             MdnsEvent::Expired(expired_list) => {
                 for (peer, _addr) in expired_list {
-                    if !self.mdns.has_node(&peer) {
-                        self.floodsub.remove_node_from_partial_view(&peer);
+                    let has_other_address = self.mdns.has_node(&peer);
+                    if !has_other_address {
+                        self.gossipsub.remove_explicit_peer(&peer);
                     }
                 }
             }
         }
+        self.prune_stale_peers();
+    }
+}
+
+impl NetworkBehaviourEventProcess<KademliaEvent> for AppBehaviour {
+    fn inject_event(&mut self, event: KademliaEvent) {
+        if let KademliaEvent::RoutingUpdated { peer, .. } = event {
+            self.see_peer(peer);
+            info!(target: P2P_TARGET, "Added {} to the routing table", peer);
+        }
+    }
+}
+
+fn mining_threads() -> usize {
+    std::thread::available_parallelism()
+        .map(usize::from)
+        .unwrap_or(1)
+}
+
+/// Identifies messages by content, so a re-broadcast block is recognized as a duplicate.
+fn message_id(message: &GossipsubMessage) -> MessageId {
+    let mut hasher = DefaultHasher::new();
+    message.data.hash(&mut hasher);
+    MessageId::from(hasher.finish().to_string())
+}
+
+pub fn build_transport(keys: &identity::Keypair) -> Boxed<(PeerId, StreamMuxerBox)> {
+    let auth_keys = Keypair::<X25519Spec>::new()
+        .into_authentic(keys)
+        .expect("Can create auth keys.");
+
+    TokioTcpConfig::new()
+        .upgrade(upgrade::Version::V1)
+        .authenticate(NoiseConfig::xx(auth_keys).into_authenticated())
+        .multiplex(mplex::MplexConfig::new())
+        .boxed()
+}
+
+/// Swarm of the node with the given identity, its connections are driven by tokio tasks.
+pub async fn build_swarm(
+    keys: &identity::Keypair,
+    node: Node,
+    response_sender: mpsc::UnboundedSender<ChainResponse>,
+    mined_sender: mpsc::UnboundedSender<Result<Block, MiningError>>,
+    chain_file: Option<PathBuf>,
+) -> Swarm<AppBehaviour> {
+    let behaviour = AppBehaviour::new(keys, node, response_sender, mined_sender, chain_file).await;
+    SwarmBuilder::new(
+        build_transport(keys),
+        behaviour,
+        PeerId::from(keys.public()),
+    )
+    .executor(Box::new(|fut| {
+        task::spawn(fut);
+    }))
+    .build()
+}
+
+fn protocol_name(protocol: Protocol) -> String {
+    // Protocols display as `/<name>/<value>`, e.g. `/tcp/4001`.
+    protocol
+        .to_string()
+        .split('/')
+        .nth(1)
+        .unwrap_or_default()
+        .to_string()
+}
+
+impl NetworkBehaviourEventProcess<PingEvent> for AppBehaviour {
+    fn inject_event(&mut self, event: PingEvent) {
+        if let Ok(PingSuccess::Ping { rtt }) = event.result {
+            self.latencies.insert(event.peer, rtt);
+        }
+    }
+}
+
+/// Parses the address to listen on, its TCP port is replaced with `port` if given.
+pub fn listen_address(listen: &str, port: Option<u16>) -> Result<Multiaddr, ListenAddressError> {
+    let address = Multiaddr::from_str(listen)
+        .map_err(|e| ListenAddressError::Invalid(format!("{}: {}", listen, e)))?;
+    if !address
+        .iter()
+        .any(|protocol| matches!(protocol, Protocol::Tcp(_)))
+    {
+        return Err(ListenAddressError::NoTcpPort(address));
+    }
+    Ok(match port {
+        Some(port) => address
+            .iter()
+            .map(|protocol| match protocol {
+                Protocol::Tcp(_) => Protocol::Tcp(port),
+                protocol => protocol,
+            })
+            .collect(),
+        None => address,
+    })
+}
+
+impl fmt::Display for ListenAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ListenAddressError::Invalid(e) => write!(f, "invalid multiaddr {}", e),
+            ListenAddressError::NoTcpPort(address) => {
+                write!(f, "{} has no TCP port to listen on", address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ListenAddressError {}
+
+/// Dials the bootstrap peers and looks up the closest peers to join the DHT.
+pub fn join_network(swarm: &mut Swarm<AppBehaviour>, config: &NetworkConfig) {
+    for address in &config.bootstrap {
+        match address.iter().last() {
+            Some(Protocol::P2p(hash)) => match PeerId::from_multihash(hash) {
+                Ok(peer_id) => {
+                    let behaviour = swarm.behaviour_mut();
+                    behaviour.kademlia.add_address(&peer_id, address.clone());
+                    behaviour.see_peer(peer_id);
+                }
+                Err(_) => warn!(target: P2P_TARGET, "Invalid peer id in {}", address),
+            },
+            _ => warn!(target: P2P_TARGET, "No peer id in {}, not added to the DHT", address),
+        }
+        info!(target: P2P_TARGET, "Dialing bootstrap peer {}", address);
+        if let Err(e) = swarm.dial_addr(address.clone()) {
+            error!(target: P2P_TARGET, "Error dialing {}, {}", address, e);
+        }
+    }
+    if !config.bootstrap.is_empty() {
+        if let Err(e) = swarm.behaviour_mut().kademlia.bootstrap() {
+            warn!(target: P2P_TARGET, "Could not bootstrap the DHT, {:?}", e);
+        }
+    }
+}
+
+/// Peers found by mDNS or in the DHT, with the addresses they were found at.
+pub fn get_list_peers(swarm: &mut Swarm<AppBehaviour>) -> Vec<(PeerId, Vec<Multiaddr>)> {
+    info!(target: P2P_TARGET, "Discovered Peers:");
+    let behaviour = swarm.behaviour_mut();
+    behaviour.prune_stale_peers();
+    let mdns_peers: HashSet<PeerId> = behaviour.mdns.discovered_nodes().copied().collect();
+    let mut records = vec![];
+    for peer_id in mdns_peers {
+        for address in behaviour.mdns.addresses_of_peer(&peer_id) {
+            records.push((peer_id, address));
+        }
+    }
+    for bucket in behaviour.kademlia.kbuckets() {
+        for entry in bucket.iter() {
+            let peer_id = *entry.node.key.preimage();
+            records.extend(
+                entry
+                    .node
+                    .value
+                    .iter()
+                    .map(|address| (peer_id, address.clone())),
+            );
+        }
+    }
+    merge_peer_addresses(records)
+        .into_iter()
+        .filter(|(peer_id, _)| behaviour.is_peer_alive(peer_id))
+        .collect()
+}
+
+/// Groups the `(peer, address)` records by peer, without duplicate addresses.
+fn merge_peer_addresses(
+    records: impl IntoIterator<Item = (PeerId, Multiaddr)>,
+) -> Vec<(PeerId, Vec<Multiaddr>)> {
+    let mut peers: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+    for (peer_id, address) in records {
+        let addresses = peers.entry(peer_id).or_default();
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+    let mut peers: Vec<_> = peers.into_iter().collect();
+    peers.sort_by_cached_key(|(peer_id, _)| peer_id.to_string());
+    peers
+}
+
+pub fn handle_api_request(request: ApiRequest, swarm: &mut Swarm<AppBehaviour>) {
+    let peers = get_list_peers(swarm)
+        .iter()
+        .map(|(peer_id, _)| peer_id.to_string())
+        .collect();
+    let behaviour = swarm.behaviour_mut();
+    match request {
+        // Queued by the behaviour, so rejected transfers reach the audit log.
+        ApiRequest::Transfer(data, sender) => {
+            let _ = sender.send(behaviour.queue_transfer(data));
+        }
+        request => request.respond(&mut behaviour.node, peers),
     }
 }
 
-pub fn get_list_peers(swarm: &Swarm<AppBehaviour>) -> Vec<String> {
-    info!("Discovered Peers:");
-    let nodes = swarm.behaviour().mdns.discovered_nodes();
-    let mut unique_peers = HashSet::new();
-    for peer in nodes {
-        unique_peers.insert(peer);
+pub fn handle_print_peers(swarm: &mut Swarm<AppBehaviour>) {
+    for (peer_id, addresses) in get_list_peers(swarm) {
+        let state = if swarm.is_connected(&peer_id) {
+            "connected"
+        } else {
+            "not connected"
+        };
+        let addresses: Vec<String> = addresses.iter().map(Multiaddr::to_string).collect();
+        info!(target: CLI_TARGET, "{} ({}): {}", peer_id, state, addresses.join(", "));
     }
-    unique_peers.iter().map(|p| p.to_string()).collect()
 }
 
-pub fn handle_print_peers(swarm: &Swarm<AppBehaviour>) {
-    let peers = get_list_peers(swarm);
-    peers.iter().for_each(|p| info!("{}", p));
+pub fn handle_print_peer(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    let peer_id = match PeerId::from_str(cmd.trim()) {
+        Ok(peer_id) => peer_id,
+        Err(_) => {
+            error!(target: CLI_TARGET, "peer: error parsing peer id");
+            return;
+        }
+    };
+
+    let behaviour = swarm.behaviour();
+    let records = behaviour.connections.get(&peer_id);
+    let is_discovered = behaviour.mdns.discovered_nodes().any(|p| p == &peer_id);
+    if records.is_none() && !is_discovered {
+        info!(target: CLI_TARGET, "No known peer with id: <{}>", peer_id);
+        return;
+    }
+
+    let peer_info = PeerInfo::new(
+        &peer_id,
+        swarm.is_connected(&peer_id),
+        records.map(Vec::as_slice).unwrap_or_default(),
+        behaviour.latencies.get(&peer_id).copied(),
+    );
+    let pretty_json = serde_json::to_string_pretty(&peer_info).expect("Can jsonify peer info.");
+    info!(target: CLI_TARGET, "Peer:");
+    info!(target: CLI_TARGET, "{}", pretty_json);
 }
 
 pub fn handle_print_accounts(swarm: &Swarm<AppBehaviour>) {
-    info!("Accounts:");
+    info!(target: CLI_TARGET, "Accounts:");
     let pretty_json = serde_json::to_string_pretty(&swarm.behaviour().node.accounts)
         .expect("Can jsonify accounts");
-    info!("{}", pretty_json);
+    info!(target: CLI_TARGET, "{}", pretty_json);
 }
 
 pub fn handle_print_account(cmd: &str, swarm: &Swarm<AppBehaviour>) {
     if let Ok(address) = serde_json::from_str::<Address>(cmd) {
-        if let Some(account) = swarm.behaviour().node.accounts.get(&address) {
-            let pretty_json = serde_json::to_string_pretty(account).expect("Can jsonify account.");
-            info!("Account:");
-            info!("{}", pretty_json);
+        let node = &swarm.behaviour().node;
+        if let Some(balance) = node.balance_of(address) {
+            info!(target: CLI_TARGET, "Account <{}> balance: {}", address, balance);
+            let pretty_json = serde_json::to_string_pretty(&node.history_of(address))
+                .expect("Can jsonify blocks.");
+            info!(target: CLI_TARGET, "History:");
+            info!(target: CLI_TARGET, "{}", pretty_json);
         } else {
-            info!("No account with address: <{:?}>", address);
+            info!(target: CLI_TARGET, "No account with address: <{:?}>", address);
         }
     } else {
-        error!("ls account: error parsing");
+        error!(target: CLI_TARGET, "ls account: error parsing");
+    }
+}
+
+pub fn handle_print_balance(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    match parse_address(cmd) {
+        Ok(address) => info!(
+            target: CLI_TARGET,
+            "{}",
+            format_balance(&swarm.behaviour().node, address)
+        ),
+        Err(e) => error!(target: CLI_TARGET, "balance: {}", e),
+    }
+}
+
+/// Just the number, so it can be read by scripts.
+fn format_balance(node: &Node, address: Address) -> String {
+    node.balance_of(address).map_or_else(
+        || "unknown address".to_string(),
+        |balance| balance.to_string(),
+    )
+}
+
+pub fn handle_print_block(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    match parse_block_id(cmd) {
+        Some(id) => match swarm.behaviour().node.get_block_by_id(id) {
+            Some(block) => {
+                let pretty_json = serde_json::to_string_pretty(block).expect("Can jsonify block.");
+                info!(target: CLI_TARGET, "{}", pretty_json);
+            }
+            None => info!(target: CLI_TARGET, "No such block: <{}>", id),
+        },
+        None => error!(target: CLI_TARGET, "ls block: error parsing"),
+    }
+}
+
+pub fn handle_verify_chain(swarm: &Swarm<AppBehaviour>) {
+    let report = swarm.behaviour().node.verify();
+    for line in report.to_string().lines() {
+        if report.is_valid() {
+            info!(target: CLI_TARGET, "{}", line);
+        } else {
+            warn!(target: CLI_TARGET, "{}", line);
+        }
+    }
+}
+
+pub fn handle_prune(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    let height = match parse_block_id(cmd) {
+        Some(height) => height,
+        None => {
+            error!(target: CLI_TARGET, "prune: error parsing");
+            return;
+        }
+    };
+    let behaviour = swarm.behaviour_mut();
+    match behaviour.node.prune_below(height) {
+        Some(checkpoint) => {
+            info!(target: CLI_TARGET, "Pruned the chain below height {}", checkpoint);
+            behaviour.persist();
+        }
+        None => info!(target: CLI_TARGET, "No blocks to prune below height {}", height),
     }
 }
 
+pub fn handle_print_reorgs(swarm: &Swarm<AppBehaviour>) {
+    info!(target: CLI_TARGET, "Recent reorgs:");
+    let pretty_json = serde_json::to_string_pretty(swarm.behaviour().node.recent_reorgs())
+        .expect("Can jsonify reorgs.");
+    info!(target: CLI_TARGET, "{}", pretty_json);
+}
+
+pub fn handle_print_mempool(swarm: &Swarm<AppBehaviour>) {
+    info!(target: CLI_TARGET, "Pending transfers:");
+    let pretty_json = serde_json::to_string_pretty(&swarm.behaviour().node.mempool)
+        .expect("Can jsonify mempool.");
+    info!(target: CLI_TARGET, "{}", pretty_json);
+}
+
+pub fn handle_print_metrics(swarm: &mut Swarm<AppBehaviour>) {
+    let mut metrics = swarm.behaviour().node.metrics();
+    metrics.peers = get_list_peers(swarm).len();
+    info!(target: CLI_TARGET, "Metrics:");
+    let pretty_json = serde_json::to_string_pretty(&metrics).expect("Can jsonify metrics.");
+    info!(target: CLI_TARGET, "{}", pretty_json);
+}
+
 pub fn handle_print_chain(swarm: &Swarm<AppBehaviour>) {
-    info!("Local Blockchain:");
+    info!(target: CLI_TARGET, "Local Blockchain:");
     let pretty_json =
         serde_json::to_string_pretty(&swarm.behaviour().node.blocks).expect("Can jsonify blocks.");
-    info!("{}", pretty_json);
+    info!(target: CLI_TARGET, "{}", pretty_json);
 }
 
 pub fn handle_create_account(swarm: &mut Swarm<AppBehaviour>) {
     let behaviour = swarm.behaviour_mut();
-    let new_account = behaviour.node.add_account();
+    if !behaviour.node.mining_enabled {
+        error!(
+            target: CLI_TARGET,
+            "Mining is disabled on this node, accounts are created by mining a block."
+        );
+        return;
+    }
+    // Checked before the key is generated, so no key is handed out for an account never mined.
+    if behaviour.mining.is_some() {
+        error!(target: CLI_TARGET, "Already mining a block, try again later.");
+        return;
+    }
+    let (new_account, private_key) = behaviour.wallet.new_account();
+    behaviour.save_wallet();
 
-    info!("Creating new account with address: {}", new_account.address);
+    info!(target: CLI_TARGET, "Creating new account with address: {}", new_account.address);
+    // Printed rather than logged, so the key does not end up in collected logs.
+    println!(
+        "Private key of account {}: {}",
+        new_account.address, private_key
+    );
+    if let Some(dir) = &behaviour.key_dir {
+        match write_private_key(dir, new_account.address, &private_key) {
+            Ok(path) => info!(target: CLI_TARGET, "Saved private key to {}", path.display()),
+            Err(e) => error!(target: CLI_TARGET, "Error saving private key, {}", e),
+        }
+    }
 
     let data = Data::Account(new_account);
     handle_create_block(data, swarm);
 }
 
 pub fn handle_transfer(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
-    info!("Sending transfer");
+    info!(target: CLI_TARGET, "Sending transfer");
 
-    if let Ok(data) = serde_json::from_str::<Data>(cmd) {
-        if let Data::Transfer(..) = &data {
-            handle_create_block(data, swarm);
-        } else {
-            error!("Transfer: invalid data!");
+    let (sender, receiver, amount, fee) = match parse_transfer(cmd) {
+        Some(args) => args,
+        None => {
+            error!(
+                target: CLI_TARGET,
+                "Transfer: error parsing! Usage: transfer <sender> <receiver> <amount> [fee]",
+            );
+            return;
         }
-    } else {
-        error!("Transfer: error parsing!");
+    };
+
+    let behaviour = swarm.behaviour_mut();
+    let data = match behaviour
+        .wallet
+        .sign_transfer(&behaviour.node, sender, receiver, amount, fee)
+    {
+        Ok(data) => data,
+        Err(e) => {
+            error!(target: CLI_TARGET, "Transfer: {}", e);
+            return;
+        }
+    };
+    if behaviour.queue_transfer(data) {
+        info!(target: CLI_TARGET, "Transfer queued, use `mine` to include it in a block");
     }
 }
 
-fn handle_create_block(data: Data, swarm: &mut Swarm<AppBehaviour>) {
+pub fn handle_mine(swarm: &mut Swarm<AppBehaviour>) {
     let behaviour = swarm.behaviour_mut();
-    let latest_block = behaviour.node.get_last_block();
-    let new_block = Block::new(latest_block.id + 1, latest_block.hash.clone(), data);
+    if behaviour.mining.is_some() {
+        error!(target: CLI_TARGET, "Already mining a block, try again later.");
+        return;
+    }
+    match behaviour.node.take_pending() {
+        Some(data) => behaviour.start_mining(data),
+        None => info!(target: CLI_TARGET, "No pending transfers to mine"),
+    }
+}
+
+pub fn handle_import_key(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    let (address, private_key) = match parse_import_key(cmd) {
+        Some(args) => args,
+        None => {
+            error!(
+                target: CLI_TARGET,
+                "Import key: error parsing! Usage: import key <address> <private_key>",
+            );
+            return;
+        }
+    };
 
-    if !behaviour.node.try_add_block(new_block.clone()) {
-        error!("Error adding block!");
+    let behaviour = swarm.behaviour_mut();
+    if behaviour.node.pub_keys.get(&address) != Some(&derive_public_key(&private_key)) {
+        error!(target: CLI_TARGET, "Import key: private key does not match account!");
         return;
     }
+    behaviour.wallet.import_key(address, private_key);
+    behaviour.save_wallet();
+    info!(target: CLI_TARGET, "Imported key of account {}", address);
+}
+
+pub fn handle_print_wallet(swarm: &Swarm<AppBehaviour>) {
+    let addresses = swarm.behaviour().wallet.addresses();
+    info!(target: CLI_TARGET, "Wallet accounts ({}):", addresses.len());
+    addresses
+        .iter()
+        .for_each(|address| info!(target: CLI_TARGET, "{}", address));
+}
+
+fn parse_block_id(cmd: &str) -> Option<u64> {
+    cmd.trim().parse().ok()
+}
+
+fn parse_address(cmd: &str) -> Result<Address, String> {
+    match cmd.trim() {
+        "" => Err("missing address".to_string()),
+        arg => arg
+            .parse()
+            .map_err(|e| format!("invalid address <{}>, {}", arg, e)),
+    }
+}
+
+fn parse_import_key(cmd: &str) -> Option<(Address, PrivateKey)> {
+    let mut args = cmd.split_whitespace();
+    let key = (args.next()?.parse().ok()?, args.next()?.parse().ok()?);
+    args.next().is_none().then_some(key)
+}
+
+fn parse_transfer(cmd: &str) -> Option<(Address, Address, u64, u64)> {
+    let mut args = cmd.split_whitespace();
+    let transfer = (
+        args.next()?.parse().ok()?,
+        args.next()?.parse().ok()?,
+        args.next()?.parse().ok()?,
+        match args.next() {
+            Some(fee) => fee.parse().ok()?,
+            None => 0,
+        },
+    );
+    args.next().is_none().then_some(transfer)
+}
+
+fn handle_create_block(data: Data, swarm: &mut Swarm<AppBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    if behaviour.mining.is_some() {
+        error!(target: CLI_TARGET, "Already mining a block, try again later.");
+        return;
+    }
+    let data = behaviour.node.with_reward(data);
+    behaviour.start_mining(data);
+}
+
+pub fn handle_mined_block(result: Result<Block, MiningError>, swarm: &mut Swarm<AppBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    let task = match behaviour.mining.take() {
+        Some(task) => task,
+        None => return,
+    };
+
+    match result {
+        Ok(block) if behaviour.node.try_add_block(block.clone()).is_ok() => {
+            behaviour.persist();
+            publish_block(behaviour, &block);
+            return;
+        }
+        Ok(_) => error!(target: P2P_TARGET, "Error adding block!"),
+        Err(MiningError::Cancelled) => info!(target: P2P_TARGET, "Mining cancelled"),
+        Err(e) => error!(target: P2P_TARGET, "Error mining block: {}, try again.", e),
+    }
+    behaviour.node.requeue(task.data);
+}
+
+fn publish_block(behaviour: &mut AppBehaviour, block: &Block) {
+    info!(target: P2P_TARGET, "Broadcasting new block");
+
+    behaviour.publish(
+        behaviour.block_topic.clone(),
+        &Message::Block(Box::new(block.clone())),
+    );
+}
+
+pub fn handle_shutdown(swarm: &Swarm<AppBehaviour>) {
+    let behaviour = swarm.behaviour();
+    behaviour.cancel_mining();
+    behaviour.persist();
+    info!(
+        target: P2P_TARGET,
+        "Shutting down with {} blocks in the chain",
+        behaviour.node.blocks.len()
+    );
+}
+
+pub fn handle_export_chain(path: &Path, swarm: &Swarm<AppBehaviour>) {
+    let blocks = &swarm.behaviour().node.blocks;
+    match write_chain(path, blocks) {
+        Ok(()) => info!(
+            target: CLI_TARGET,
+            "Exported {} blocks to {}",
+            blocks.len(),
+            path.display()
+        ),
+        Err(e) => error!(target: CLI_TARGET, "export: error writing chain file, {}", e),
+    }
+}
+
+pub fn handle_dump_state(path: &Path, swarm: &Swarm<AppBehaviour>) {
+    let node = &swarm.behaviour().node;
+    match node.dump_state(path) {
+        Ok(()) => info!(
+            target: CLI_TARGET,
+            "Dumped {} accounts to {}",
+            node.accounts.len(),
+            path.display()
+        ),
+        Err(e) => error!(target: CLI_TARGET, "dump: error writing state file, {}", e),
+    }
+}
+
+pub fn handle_import_chain(path: &Path, swarm: &mut Swarm<AppBehaviour>) {
+    info!(target: SYNC_TARGET, "Importing chain from {}", path.display());
+
+    match read_chain(path) {
+        Ok(chain) => {
+            let behaviour = swarm.behaviour_mut();
+            if behaviour.node.import_chain(chain) {
+                behaviour.persist();
+                behaviour.cancel_stale_mining();
+            }
+        }
+        Err(e) => error!(target: SYNC_TARGET, "Import: error reading chain file, {}", e),
+    }
+}
+
+#[cfg(test)]
+mod p2p_tests {
+    use super::*;
+    use libp2p::futures::{future::select_all, StreamExt};
+    use libp2p::gossipsub::error::PublishError;
+    use libp2p::swarm::SwarmEvent;
+    use node::Account;
+    use tokio::{
+        select,
+        time::{sleep, timeout},
+    };
+
+    fn get_linked_chain(len: u64) -> Vec<Block> {
+        let mut node = Node::new();
+        node.genesis();
+        let mut chain = vec![node.get_last_block().clone()];
+        for id in 1..len {
+            let previous = chain.last().unwrap();
+            let block = Block {
+                id,
+                previous_hash: previous.hash.clone(),
+                hash: format!("{:064x}", id),
+                ..previous.clone()
+            };
+            chain.push(block);
+        }
+        chain
+    }
+
+    fn get_chunk(chain: &[Block], from_height: u64, max_blocks: u64) -> ChainResponse {
+        ChainResponse {
+            blocks: chain_slice(chain, from_height, max_blocks).to_vec(),
+            receiver: "peer".to_string(),
+            from_height,
+            chain_len: chain.len() as u64,
+        }
+    }
+
+    #[test]
+    fn slices_requested_chain_range() {
+        let chain = get_linked_chain(10);
+
+        assert_eq!(chain_slice(&chain, 3, 4), &chain[3..7]);
+        assert_eq!(chain_slice(&chain, 8, 4), &chain[8..]);
+        assert!(chain_slice(&chain, 10, 4).is_empty());
+        assert!(chain_slice(&chain, u64::MAX, u64::MAX).is_empty());
+        // A pruned chain starting at block 4.
+        assert_eq!(chain_slice(&chain[4..], 5, 2), &chain[5..7]);
+        assert!(chain_slice(&chain[4..], 3, 4).is_empty());
+    }
+
+    #[test]
+    fn clamps_requested_chunk_size() {
+        let chain = get_linked_chain(2 * MAX_CHAIN_CHUNK + 5);
+
+        assert_eq!(
+            chain_slice(&chain, 1, u64::MAX),
+            &chain[1..1 + MAX_CHAIN_CHUNK as usize]
+        );
+        assert_eq!(chain_slice(&chain, 0, 0).len(), 0);
+    }
+
+    #[test]
+    fn bans_peer_crossing_threshold() {
+        let mut reputation = Reputation::default();
+
+        assert!(!reputation.penalize(INVALID_CHAIN_PENALTY));
+        assert!(!reputation.penalize(INVALID_BLOCK_PENALTY));
+        assert!(!reputation.penalize(INVALID_BLOCK_PENALTY - 1));
+        assert_eq!(reputation, Reputation(BAN_THRESHOLD + 1));
+        assert!(reputation.penalize(1));
+        assert!(reputation.penalize(i32::MAX));
+        assert_eq!(reputation, Reputation(i32::MIN));
+    }
+
+    #[test]
+    fn refills_rate_limit_bucket() {
+        let start = Instant::now();
+        let mut state = RateState::new(4, start);
+
+        assert!((0..4).all(|_| state.try_consume(4, start)));
+        assert!(!state.try_consume(4, start));
+        assert!(state.try_consume(4, start + Duration::from_millis(250)));
+        assert!(!state.try_consume(4, start + Duration::from_millis(250)));
+        // The bucket holds at most one second worth of messages.
+        let later = start + Duration::from_secs(10);
+        assert!((0..4).all(|_| state.try_consume(4, later)));
+        assert!(!state.try_consume(4, later));
+    }
+
+    fn mine_next(node: &mut Node, data: Data) {
+        let latest_block = node.get_last_block();
+        let block = Block::new(
+            latest_block.id + 1,
+            latest_block.hash.clone(),
+            data,
+            node.compute_difficulty(),
+        );
+        assert!(node.try_add_block(block).is_ok());
+    }
+
+    fn get_synced_pair(common: usize) -> (Node, Node) {
+        let mut remote = Node::new();
+        remote.genesis();
+        for _ in 1..common {
+            mine_next(&mut remote, Data::Batch(vec![]));
+        }
+        let mut local = Node::new();
+        local.genesis();
+        assert!(local.adopt_chain(remote.blocks.clone()));
+        (local, remote)
+    }
+
+    #[test]
+    fn catches_up_block_by_block() {
+        let (mut local, mut remote) = get_synced_pair(2);
+        for _ in 0..3 {
+            mine_next(&mut remote, Data::Batch(vec![]));
+        }
+        let mut sync = ChainSync::new("peer".to_string(), &local);
+
+        let mut heights = vec![];
+        loop {
+            let req = sync.next_request();
+            assert_eq!(req.from_height, local.blocks.len() as u64);
+            // Chunks of a single block, as if the peer clamped the requests to one.
+            let done = sync.add_chunk(&mut local, get_chunk(&remote.blocks, req.from_height, 1));
+            heights.push(local.blocks.len());
+            if done {
+                break;
+            }
+        }
+
+        assert_eq!(heights, vec![3, 4, 5]);
+        assert_eq!(local.blocks, remote.blocks);
+        assert!(local.recent_reorgs().is_empty());
+    }
+
+    #[test]
+    fn fetches_forked_chain_from_genesis() {
+        let (mut local, mut remote) = get_synced_pair(1);
+        let (account, _) = Account::new();
+        mine_next(&mut local, Data::Account(account));
+        for _ in 0..2 {
+            mine_next(&mut remote, Data::Batch(vec![]));
+        }
+        let mut sync = ChainSync::new("peer".to_string(), &local);
+
+        let req = sync.next_request();
+        assert!(!sync.add_chunk(&mut local, get_chunk(&remote.blocks, req.from_height, 1)));
+        assert_eq!(sync.next_request().from_height, 1);
+        let req = sync.next_request();
+        assert!(sync.add_chunk(
+            &mut local,
+            get_chunk(&remote.blocks, req.from_height, req.max_blocks)
+        ));
+        assert_eq!(local.blocks, remote.blocks);
+    }
+
+    fn get_forked_pair(remote_blocks: usize) -> (Node, Node, ChainSync) {
+        let (mut local, mut remote) = get_synced_pair(1);
+        mine_next(&mut local, Data::Account(Account::new().0));
+        for _ in 0..remote_blocks {
+            mine_next(&mut remote, Data::Batch(vec![]));
+        }
+        let mut sync = ChainSync::new("peer".to_string(), &local);
+        let req = sync.next_request();
+        assert!(!sync.add_chunk(&mut local, get_chunk(&remote.blocks, req.from_height, 1)));
+        (local, remote, sync)
+    }
+
+    #[test]
+    fn stops_fetching_fork_past_reorg_depth() {
+        let (mut local, remote, mut sync) = get_forked_pair(6);
+        local.max_reorg_depth = Some(2);
+        // The peer claims an endless chain.
+        let next_chunk = |sync: &ChainSync| ChainResponse {
+            chain_len: u64::MAX,
+            ..get_chunk(&remote.blocks, sync.next_request().from_height, 2)
+        };
+
+        let chunk = next_chunk(&sync);
+        assert!(!sync.add_chunk(&mut local, chunk));
+        let chunk = next_chunk(&sync);
+        assert!(sync.add_chunk(&mut local, chunk));
+        assert!(!sync.invalid);
+        assert_eq!(local.blocks, remote.blocks[..4]);
+    }
+
+    #[test]
+    fn stops_fetching_fork_at_invalid_block() {
+        let (mut local, mut remote, mut sync) = get_forked_pair(6);
+        let tip = local.get_last_block().clone();
+        remote.blocks[2].timestamp += 1;
+
+        let req = sync.next_request();
+        let chunk = ChainResponse {
+            chain_len: u64::MAX,
+            ..get_chunk(&remote.blocks, req.from_height, 2)
+        };
+        assert!(sync.add_chunk(&mut local, chunk));
+        assert!(sync.invalid);
+        assert_eq!(local.get_last_block(), &tip);
+    }
+
+    #[test]
+    fn parses_transfer_command() {
+        let private_key = PrivateKey([0x42; 32]);
+
+        assert_eq!(parse_transfer("1 2 30"), Some((1, 2, 30, 0)));
+        assert_eq!(parse_transfer("1 2 30 5"), Some((1, 2, 30, 5)));
+        assert_eq!(parse_transfer("1 2"), None);
+        assert_eq!(parse_transfer("1 2 -30"), None);
+        assert_eq!(parse_transfer(&format!("1 2 30 {}", private_key)), None);
+        assert_eq!(
+            parse_import_key(&format!("0 {}", private_key)),
+            Some((0, private_key))
+        );
+        assert_eq!(parse_import_key("0"), None);
+    }
+
+    #[test]
+    fn parses_block_id() {
+        assert_eq!(parse_block_id("7"), Some(7));
+        assert_eq!(parse_block_id(" 7 "), Some(7));
+        assert_eq!(parse_block_id("seven"), None);
+        assert_eq!(parse_block_id("-1"), None);
+        assert_eq!(parse_block_id(""), None);
+    }
+
+    #[test]
+    fn parses_address() {
+        assert_eq!(parse_address(" 7 "), Ok(7));
+        assert_eq!(parse_address(""), Err("missing address".to_string()));
+        assert_eq!(
+            parse_address("seven"),
+            Err("invalid address <seven>, invalid digit found in string".to_string())
+        );
+        assert!(parse_address("1 2").is_err());
+        assert!(parse_address("-1").is_err());
+    }
+
+    #[test]
+    fn formats_balance_of_address() {
+        let mut node = Node::new();
+        node.genesis();
+
+        assert_eq!(format_balance(&node, 0), node::GENESIS_SUPPLY.to_string());
+        assert_eq!(format_balance(&node, 7), "unknown address");
+    }
+
+    #[test]
+    fn assembles_peer_info_from_connection_records() {
+        let peer_id = PeerId::random();
+        let dialed: Multiaddr = "/ip4/10.0.0.1/tcp/4001".parse().unwrap();
+        let send_back: Multiaddr = "/ip4/10.0.0.2/tcp/5001".parse().unwrap();
+        let records = vec![
+            ConnectionRecord::from(&ConnectedPoint::Dialer {
+                address: dialed.clone(),
+            }),
+            ConnectionRecord::from(&ConnectedPoint::Listener {
+                local_addr: "/ip4/0.0.0.0/tcp/6001".parse().unwrap(),
+                send_back_addr: send_back.clone(),
+            }),
+        ];
+
+        let peer_info = PeerInfo::new(&peer_id, true, &records, Some(Duration::from_millis(42)));
+
+        assert_eq!(
+            peer_info,
+            PeerInfo {
+                peer_id: peer_id.to_string(),
+                connected: true,
+                connections: vec![
+                    ConnectionInfo {
+                        address: dialed.to_string(),
+                        protocols: vec!["ip4".to_string(), "tcp".to_string()],
+                        direction: ConnectionDirection::Outbound,
+                    },
+                    ConnectionInfo {
+                        address: send_back.to_string(),
+                        protocols: vec!["ip4".to_string(), "tcp".to_string()],
+                        direction: ConnectionDirection::Inbound,
+                    },
+                ],
+                latency_ms: Some(42),
+            }
+        );
+    }
+
+    type MinedReceiver = mpsc::UnboundedReceiver<Result<Block, MiningError>>;
+
+    async fn spawn_swarm() -> (Swarm<AppBehaviour>, Multiaddr, MinedReceiver) {
+        spawn_swarm_with_chain_file(None).await
+    }
+
+    async fn spawn_swarm_with_chain_file(
+        chain_file: Option<PathBuf>,
+    ) -> (Swarm<AppBehaviour>, Multiaddr, MinedReceiver) {
+        spawn_swarm_on_chain(0, chain_file).await
+    }
+
+    async fn spawn_swarm_on_chain(
+        chain_id: u64,
+        chain_file: Option<PathBuf>,
+    ) -> (Swarm<AppBehaviour>, Multiaddr, MinedReceiver) {
+        let keys = identity::Keypair::generate_ed25519();
+        let mut node = Node::new();
+        node.chain_id = chain_id;
+        node.genesis();
+        let (response_sender, _) = mpsc::unbounded_channel();
+        let (mined_sender, mined_receiver) = mpsc::unbounded_channel();
+        let mut swarm = build_swarm(&keys, node, response_sender, mined_sender, chain_file).await;
+
+        swarm
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .unwrap();
+        loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = swarm.select_next_some().await {
+                return (swarm, address, mined_receiver);
+            }
+        }
+    }
+
+    async fn drive_until<const N: usize>(
+        swarms: &mut [Swarm<AppBehaviour>; N],
+        done: impl Fn(&mut [Swarm<AppBehaviour>; N]) -> bool,
+    ) {
+        timeout(Duration::from_secs(30), async {
+            while !done(swarms) {
+                select! {
+                    _ = select_all(swarms.iter_mut().map(|swarm| swarm.select_next_some())) => {}
+                    // Events handled by the behaviour itself do not wake up the loop.
+                    _ = sleep(Duration::from_millis(50)) => {}
+                }
+            }
+        })
+        .await
+        .expect("Swarms get there in time.");
+    }
+
+    async fn create_account(swarm: &mut Swarm<AppBehaviour>, mined_receiver: &mut MinedReceiver) {
+        handle_create_account(swarm);
+        let mined = mined_receiver.recv().await.expect("Mining result is sent.");
+        handle_mined_block(mined, swarm);
+    }
+
+    fn heights(swarms: &[Swarm<AppBehaviour>; 3]) -> Vec<usize> {
+        swarms
+            .iter()
+            .map(|swarm| swarm.behaviour().node.blocks.len())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn saves_chain_once_on_shutdown() {
+        let path = std::env::temp_dir().join(format!("ch-rust-{}-shutdown", std::process::id()));
+        let (swarm, _, _) = spawn_swarm_with_chain_file(Some(path.clone())).await;
+        testing_logger::setup();
+
+        handle_shutdown(&swarm);
+
+        testing_logger::validate(|captured_logs| {
+            let saves = captured_logs
+                .iter()
+                .filter(|log| log.body.starts_with("Saved chain to"))
+                .count();
+            assert_eq!(saves, 1);
+        });
+        let saved = Node::load_from_file(&path).unwrap();
+        assert_eq!(saved.blocks, swarm.behaviour().node.blocks);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn parses_listen_address() {
+        assert_eq!(
+            listen_address(DEFAULT_LISTEN_ADDRESS, None),
+            Ok("/ip4/0.0.0.0/tcp/0".parse().unwrap())
+        );
+        assert_eq!(
+            listen_address("/ip4/127.0.0.1/tcp/0", Some(4001)),
+            Ok("/ip4/127.0.0.1/tcp/4001".parse().unwrap())
+        );
+        assert_eq!(
+            listen_address("/ip6/::1/tcp/5000", None),
+            Ok("/ip6/::1/tcp/5000".parse().unwrap())
+        );
+        assert!(matches!(
+            listen_address("0.0.0.0:4001", None),
+            Err(ListenAddressError::Invalid(_))
+        ));
+        assert!(matches!(
+            listen_address("/ip4/0.0.0.0/tcp/99999", None),
+            Err(ListenAddressError::Invalid(_))
+        ));
+        assert!(matches!(
+            listen_address("/ip4/0.0.0.0/udp/4001", Some(4001)),
+            Err(ListenAddressError::NoTcpPort(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn ignores_known_and_stale_blocks() {
+        let (mut swarm, _, _) = spawn_swarm().await;
+        let mut other = Node::new();
+        other.genesis();
+        let genesis_hash = other.get_last_block().hash.clone();
+        let difficulty = other.compute_difficulty();
+        let block = Block::new(
+            1,
+            genesis_hash.clone(),
+            Data::Account(other.add_account().0),
+            difficulty,
+        );
+        let competing = Block::new(
+            1,
+            genesis_hash,
+            Data::Account(other.add_account().0),
+            difficulty,
+        );
+        let source = PeerId::random();
+        let behaviour = swarm.behaviour_mut();
+
+        assert!(matches!(
+            behaviour.handle_block(block.clone(), source),
+            MessageAcceptance::Accept
+        ));
+        assert!(matches!(
+            behaviour.handle_block(block.clone(), source),
+            MessageAcceptance::Ignore
+        ));
+        assert!(matches!(
+            behaviour.handle_block(competing, source),
+            MessageAcceptance::Ignore
+        ));
+        assert_eq!(behaviour.node.blocks.len(), 2);
+        assert_eq!(behaviour.node.get_last_block(), &block);
+    }
 
-    info!("Broadcasting new block");
+    #[tokio::test]
+    async fn records_rejected_blocks_in_audit_log() {
+        let path = std::env::temp_dir().join(format!("ch-rust-audit-{}", std::process::id()));
+        let (mut swarm, _, _) = spawn_swarm().await;
+        let mut other = Node::new();
+        other.genesis();
+        let mut block = Block::new(
+            1,
+            other.get_last_block().hash.clone(),
+            Data::Account(other.add_account().0),
+            other.compute_difficulty(),
+        );
+        block.nonce += 1;
+        let source = PeerId::random();
+        let behaviour = swarm.behaviour_mut();
+        behaviour.audit_log = Some(AuditLog::new(path.clone()));
 
-    let json = serde_json::to_string(&new_block).expect("Can jsonify request.");
-    behaviour
-        .floodsub
-        .publish(BLOCK_TOPIC.clone(), json.as_bytes());
+        assert!(matches!(
+            behaviour.handle_block(block.clone(), source),
+            MessageAcceptance::Reject
+        ));
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let records: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["kind"], "block");
+        assert_eq!(records[0]["hash"], block.hash);
+        assert_eq!(records[0]["peer"], source.to_string());
+        assert_eq!(records[0]["reason"], "invalid hash");
+    }
+
+    #[tokio::test]
+    async fn counts_malformed_messages() {
+        let (mut swarm, _, _) = spawn_swarm().await;
+        let source = PeerId::random();
+        let behaviour = swarm.behaviour_mut();
+        let mut malformed = wire::encode(&Message::Block(Box::new(
+            behaviour.node.get_last_block().clone(),
+        )));
+        malformed.truncate(malformed.len() / 2);
+
+        for data in [&malformed[..], &[1]] {
+            assert!(matches!(
+                behaviour.handle_message(data, source),
+                MessageAcceptance::Reject
+            ));
+        }
+
+        assert_eq!(behaviour.malformed_messages.get(&source), Some(&2));
+        assert_eq!(
+            behaviour.reputations.get(&source),
+            Some(&Reputation(-2 * MALFORMED_MESSAGE_PENALTY))
+        );
+    }
+
+    #[tokio::test]
+    async fn accepts_only_chain_messages_for_local_peer() {
+        let (mut swarm, _, _) = spawn_swarm().await;
+        let source = PeerId::random();
+        let behaviour = swarm.behaviour_mut();
+        let local = behaviour.peer_id.to_string();
+        let request = |from_peer_id: String| {
+            wire::encode(&Message::ChainRequest(LocalChainRequest {
+                from_peer_id,
+                from_height: 0,
+                max_blocks: MAX_CHAIN_CHUNK,
+            }))
+        };
+
+        assert!(matches!(
+            behaviour.handle_message(&request(PeerId::random().to_string()), source),
+            MessageAcceptance::Ignore
+        ));
+        assert!(matches!(
+            behaviour.handle_message(&request(local.clone()), source),
+            MessageAcceptance::Accept
+        ));
+        // Neither a chunk for another peer nor an unrequested one is consumed.
+        for receiver in [PeerId::random().to_string(), local] {
+            let mut res = get_chunk(&behaviour.node.blocks, 0, MAX_CHAIN_CHUNK);
+            res.receiver = receiver;
+            let data = wire::encode(&Message::ChainResponse(res));
+            assert!(matches!(
+                behaviour.handle_message(&data, source),
+                MessageAcceptance::Ignore
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn adds_block_received_before_its_parent() {
+        let (mut swarm, _, _) = spawn_swarm().await;
+        let mut other = Node::new();
+        other.genesis();
+        for _ in 0..2 {
+            let tip = other.get_last_block().clone();
+            let data = Data::Account(Account::new().0);
+            let block = Block::new(tip.id + 1, tip.hash, data, other.compute_difficulty());
+            assert!(other.try_add_block(block).is_ok());
+        }
+        let source = PeerId::random();
+        let behaviour = swarm.behaviour_mut();
+
+        assert!(matches!(
+            behaviour.handle_block(other.blocks[2].clone(), source),
+            MessageAcceptance::Ignore
+        ));
+        assert!(behaviour.sync.is_none());
+        assert!(matches!(
+            behaviour.handle_block(other.blocks[1].clone(), source),
+            MessageAcceptance::Accept
+        ));
+        assert_eq!(behaviour.node.blocks, other.blocks);
+    }
+
+    #[tokio::test]
+    async fn joining_node_syncs_genesis_instead_of_creating_it() {
+        let build = || {
+            let (response_sender, _) = mpsc::unbounded_channel();
+            let (mined_sender, _) = mpsc::unbounded_channel();
+            let keys = identity::Keypair::generate_ed25519();
+            async move { build_swarm(&keys, Node::new(), response_sender, mined_sender, None).await }
+        };
+        let mut other = Node::new();
+        other.genesis();
+        let (account, _) = Account::new();
+        mine_next(&mut other, Data::Account(account));
+        let peer = PeerId::random();
+
+        let mut swarm = build().await;
+        let behaviour = swarm.behaviour_mut();
+        behaviour.init(Some(peer));
+        assert!(behaviour.node.blocks.is_empty());
+        while let Some(sync) = &behaviour.sync {
+            let req = sync.next_request();
+            let mut res = get_chunk(&other.blocks, req.from_height, req.max_blocks);
+            res.receiver = behaviour.peer_id.to_string();
+            behaviour.handle_chain_response(res, peer);
+        }
+        assert_eq!(behaviour.node.blocks, other.blocks);
+        assert_eq!(behaviour.node.accounts, other.accounts);
+
+        // A peer without a chain leaves the node to start its own.
+        let mut swarm = build().await;
+        let behaviour = swarm.behaviour_mut();
+        behaviour.init(Some(peer));
+        let mut res = get_chunk(&[], 0, MAX_CHAIN_CHUNK);
+        res.receiver = behaviour.peer_id.to_string();
+        behaviour.handle_chain_response(res, peer);
+        assert!(behaviour.sync.is_none());
+        assert_eq!(behaviour.node.blocks, other.blocks[..1]);
+
+        let mut swarm = build().await;
+        swarm.behaviour_mut().init(None);
+        assert_eq!(swarm.behaviour().node.blocks, other.blocks[..1]);
+    }
+
+    #[tokio::test]
+    async fn requeues_transfers_when_mining_is_cancelled() {
+        let (mut swarm, _, mut mined_receiver) = spawn_swarm().await;
+        let node = &mut swarm.behaviour_mut().node;
+        let receiver = node.add_account().0.address;
+        let transfer = node::sign_transfer(&node::GENESIS_PRIVATE_KEY, 0, receiver, 10, 0, 0);
+        assert!(node.add_to_mempool(transfer.clone()));
+        // No hash has that many leading zero bits, mining runs until cancelled.
+        node.difficulty = 256;
+
+        handle_mine(&mut swarm);
+        assert!(swarm.behaviour().node.mempool.is_empty());
+        swarm.behaviour().cancel_mining();
+        let mined = mined_receiver.recv().await.unwrap();
+        assert_eq!(mined, Err(MiningError::Cancelled));
+        handle_mined_block(mined, &mut swarm);
+
+        assert!(swarm.behaviour().mining.is_none());
+        assert_eq!(swarm.behaviour().node.mempool, vec![transfer]);
+        assert_eq!(swarm.behaviour().node.blocks.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn creates_account_only_once_mined() {
+        let (mut swarm, _, mut mined_receiver) = spawn_swarm().await;
+        let accounts = swarm.behaviour().node.accounts.clone();
+        swarm.behaviour_mut().node.difficulty = 256;
+
+        handle_create_account(&mut swarm);
+        handle_create_account(&mut swarm);
+        assert_eq!(swarm.behaviour().wallet.addresses().len(), 1);
+        assert_eq!(swarm.behaviour().node.accounts, accounts);
+        swarm.behaviour().cancel_mining();
+        let mined = mined_receiver.recv().await.unwrap();
+        handle_mined_block(mined, &mut swarm);
+
+        assert_eq!(swarm.behaviour().node.accounts, accounts);
+        swarm.behaviour_mut().node.difficulty = 1;
+        create_account(&mut swarm, &mut mined_receiver).await;
+        assert_eq!(swarm.behaviour().node.accounts.len(), accounts.len() + 1);
+    }
+
+    #[tokio::test]
+    async fn dials_bootstrap_peer_on_startup() {
+        let (bootstrap, address, _) = spawn_swarm().await;
+        let (node, _, _) = spawn_swarm().await;
+        let bootstrap_id = *bootstrap.local_peer_id();
+        let mut swarms = [bootstrap, node];
+        let address = address.with(Protocol::P2p(bootstrap_id.into()));
+        let config = NetworkConfig {
+            bootstrap: vec![address.clone()],
+        };
+
+        join_network(&mut swarms[1], &config);
+
+        assert!(get_list_peers(&mut swarms[1]).contains(&(bootstrap_id, vec![address])));
+        drive_until(&mut swarms, |swarms| swarms[1].is_connected(&bootstrap_id)).await;
+    }
+
+    #[test]
+    fn merges_addresses_per_peer() {
+        let (first, second) = (PeerId::random(), PeerId::random());
+        let address = |port: u16| {
+            format!("/ip4/127.0.0.1/tcp/{}", port)
+                .parse::<Multiaddr>()
+                .unwrap()
+        };
+
+        let peers = merge_peer_addresses([
+            (first, address(1)),
+            (second, address(2)),
+            (first, address(3)),
+            (first, address(1)),
+        ]);
+
+        let mut expected = vec![
+            (first, vec![address(1), address(3)]),
+            (second, vec![address(2)]),
+        ];
+        expected.sort_by_key(|(peer_id, _)| peer_id.to_string());
+        assert_eq!(peers, expected);
+        assert!(merge_peer_addresses([]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn drops_peer_unseen_for_timeout() {
+        let (mut swarm, _, _) = spawn_swarm().await;
+        swarm.behaviour_mut().peer_timeout = Duration::from_millis(20);
+        let unreachable = PeerId::random();
+        let config = NetworkConfig {
+            bootstrap: vec!["/ip4/127.0.0.1/tcp/1"
+                .parse::<Multiaddr>()
+                .unwrap()
+                .with(Protocol::P2p(unreachable.into()))],
+        };
+
+        join_network(&mut swarm, &config);
+        assert!(get_list_peers(&mut swarm)
+            .iter()
+            .any(|(peer_id, _)| peer_id == &unreachable));
+
+        sleep(Duration::from_millis(50)).await;
+        assert!(!get_list_peers(&mut swarm)
+            .iter()
+            .any(|(peer_id, _)| peer_id == &unreachable));
+        assert!(!swarm.behaviour().last_seen.contains_key(&unreachable));
+    }
+
+    #[tokio::test]
+    async fn propagates_mined_block_to_bootstrapped_peer() {
+        let (a, a_address, mut a_mined) = spawn_swarm().await;
+        let (b, _, _) = spawn_swarm().await;
+        let a_id = *a.local_peer_id();
+        let mut swarms = [a, b];
+        let config = NetworkConfig {
+            bootstrap: vec![a_address.with(Protocol::P2p(a_id.into()))],
+        };
+        join_network(&mut swarms[1], &config);
+        drive_until(&mut swarms, |swarms| {
+            swarms[0]
+                .behaviour()
+                .gossipsub
+                .all_peers()
+                .any(|(_, topics)| topics.contains(&&block_topic(0).hash()))
+        })
+        .await;
+
+        create_account(&mut swarms[0], &mut a_mined).await;
+        drive_until(&mut swarms, |swarms| {
+            swarms[1].behaviour().node.blocks == swarms[0].behaviour().node.blocks
+        })
+        .await;
+
+        assert_eq!(swarms[1].behaviour().node.blocks.len(), 2);
+        assert_eq!(
+            swarms[1].behaviour().node.accounts,
+            swarms[0].behaviour().node.accounts
+        );
+        assert_eq!(
+            swarms[1].behaviour().node.blocks[1].miner,
+            Some(swarms[0].local_peer_id().to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn nodes_of_different_chains_do_not_exchange_blocks() {
+        let (a, a_address, mut a_mined) = spawn_swarm_on_chain(1, None).await;
+        let (b, _, _) = spawn_swarm_on_chain(2, None).await;
+        let (c, _, _) = spawn_swarm_on_chain(1, None).await;
+        let (a_topic, b_topic) = (block_topic(1).hash(), block_topic(2).hash());
+        let mut swarms = [a, b, c];
+        swarms[1].dial_addr(a_address.clone()).unwrap();
+        swarms[2].dial_addr(a_address).unwrap();
+        let (b_id, c_id) = (*swarms[1].local_peer_id(), *swarms[2].local_peer_id());
+        drive_until(&mut swarms, |swarms| {
+            swarms[0].is_connected(&b_id)
+                && swarms[0]
+                    .behaviour()
+                    .gossipsub
+                    .all_peers()
+                    .any(|(peer_id, topics)| *peer_id == c_id && topics.contains(&&a_topic))
+        })
+        .await;
+
+        create_account(&mut swarms[0], &mut a_mined).await;
+        drive_until(&mut swarms, |swarms| heights(swarms)[2] == 2).await;
+
+        assert_eq!(heights(&swarms), vec![2, 1, 2]);
+        assert!(!swarms[1]
+            .behaviour()
+            .gossipsub
+            .all_peers()
+            .any(|(_, topics)| topics.contains(&&b_topic)));
+    }
+
+    #[tokio::test]
+    async fn three_nodes_converge_on_same_chain() {
+        let (a, a_address, mut a_mined) = spawn_swarm().await;
+        let (b, b_address, _) = spawn_swarm().await;
+        let (c, _, mut c_mined) = spawn_swarm().await;
+        let mut swarms = [a, b, c];
+        swarms[1].dial_addr(a_address.clone()).unwrap();
+        swarms[2].dial_addr(a_address).unwrap();
+        swarms[2].dial_addr(b_address).unwrap();
+        drive_until(&mut swarms, |swarms| {
+            swarms.iter().all(|swarm| {
+                swarm
+                    .behaviour()
+                    .gossipsub
+                    .all_peers()
+                    .filter(|(_, topics)| topics.contains(&&block_topic(0).hash()))
+                    .count()
+                    == 2
+            })
+        })
+        .await;
+
+        create_account(&mut swarms[0], &mut a_mined).await;
+        drive_until(&mut swarms, |swarms| heights(swarms) == vec![2, 2, 2]).await;
+        create_account(&mut swarms[2], &mut c_mined).await;
+        drive_until(&mut swarms, |swarms| heights(swarms) == vec![3, 3, 3]).await;
+
+        let tip = swarms[0].behaviour().node.get_last_block().clone();
+        for swarm in &swarms {
+            assert_eq!(swarm.behaviour().node.get_last_block(), &tip);
+        }
+
+        // A received block is not broadcast again.
+        let bytes = wire::encode(&Message::Block(Box::new(tip)));
+        assert!(matches!(
+            swarms[0]
+                .behaviour_mut()
+                .gossipsub
+                .publish(block_topic(0), bytes),
+            Err(PublishError::Duplicate)
+        ));
+    }
 }
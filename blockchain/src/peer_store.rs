@@ -0,0 +1,175 @@
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use node::storage::write_atomically;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Default of `PeerStore::capacity`.
+pub const MAX_STORED_PEERS: usize = 64;
+
+/// Addresses the node dialed peers at, saved so the peers are dialed again after a restart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerStore {
+    peers: HashMap<PeerId, StoredPeer>,
+    /// Most peers kept, the least recently seen ones are dropped first.
+    pub capacity: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct StoredPeer {
+    address: Multiaddr,
+    last_seen: i64,
+}
+
+/// Saved form of a peer, entries which do not parse are skipped on load.
+#[derive(Serialize, Deserialize)]
+struct PeerEntry {
+    peer_id: String,
+    address: String,
+    last_seen: i64,
+}
+
+impl Default for PeerStore {
+    fn default() -> Self {
+        Self::new(MAX_STORED_PEERS)
+    }
+}
+
+impl PeerStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            peers: HashMap::new(),
+            capacity,
+        }
+    }
+
+    pub fn load(path: &Path, capacity: usize) -> io::Result<PeerStore> {
+        let json = fs::read_to_string(path)?;
+        let entries = serde_json::from_str::<Vec<PeerEntry>>(&json)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut store = PeerStore::new(capacity);
+        for entry in entries {
+            if let (Ok(peer_id), Ok(address)) = (
+                PeerId::from_str(&entry.peer_id),
+                Multiaddr::from_str(&entry.address),
+            ) {
+                store.record(peer_id, address, entry.last_seen);
+            }
+        }
+        Ok(store)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut entries: Vec<PeerEntry> = self
+            .peers
+            .iter()
+            .map(|(peer_id, peer)| PeerEntry {
+                peer_id: peer_id.to_string(),
+                address: peer.address.to_string(),
+                last_seen: peer.last_seen,
+            })
+            .collect();
+        entries.sort_by_key(|entry| (Reverse(entry.last_seen), entry.peer_id.clone()));
+        let json = serde_json::to_string_pretty(&entries)?;
+        write_atomically(path, json.as_bytes())
+    }
+
+    /// Keeps the address the peer was reached at, evicting the least recently seen peer if the
+    /// store is full.
+    pub fn record(&mut self, peer_id: PeerId, mut address: Multiaddr, seen_at: i64) {
+        if let Some(Protocol::P2p(_)) = address.iter().last() {
+            address.pop();
+        }
+        self.peers.insert(
+            peer_id,
+            StoredPeer {
+                address,
+                last_seen: seen_at,
+            },
+        );
+        while self.peers.len() > self.capacity {
+            let oldest = self
+                .peers
+                .iter()
+                .min_by_key(|(_, peer)| peer.last_seen)
+                .map(|(peer_id, _)| *peer_id);
+            match oldest {
+                Some(peer_id) => self.peers.remove(&peer_id),
+                None => break,
+            };
+        }
+    }
+
+    /// Addresses to dial with the peer id appended, the most recently seen peers first.
+    pub fn addresses(&self) -> Vec<Multiaddr> {
+        let mut peers: Vec<(&PeerId, &StoredPeer)> = self.peers.iter().collect();
+        peers.sort_by_key(|(_, peer)| Reverse(peer.last_seen));
+        peers
+            .into_iter()
+            .map(|(peer_id, peer)| peer.address.clone().with(Protocol::P2p((*peer_id).into())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod peer_store_tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn get_test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ch-rust-peers-{}-{}", std::process::id(), name))
+    }
+
+    fn address(port: u16) -> Multiaddr {
+        format!("/ip4/10.0.0.1/tcp/{}", port).parse().unwrap()
+    }
+
+    #[test]
+    fn saves_and_loads_peers() {
+        let path = get_test_path("persist.json");
+        let (first, second) = (PeerId::random(), PeerId::random());
+        let mut store = PeerStore::default();
+        store.record(first, address(4001), 10);
+        store.record(second, address(4002).with(Protocol::P2p(second.into())), 20);
+        store.save(&path).unwrap();
+
+        let loaded = PeerStore::load(&path, MAX_STORED_PEERS).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, store);
+        assert_eq!(
+            loaded.addresses(),
+            vec![
+                address(4002).with(Protocol::P2p(second.into())),
+                address(4001).with(Protocol::P2p(first.into())),
+            ]
+        );
+        assert!(PeerStore::load(&path, MAX_STORED_PEERS).is_err());
+    }
+
+    #[test]
+    fn evicts_least_recently_seen_peers() {
+        let peers: Vec<PeerId> = (0..4).map(|_| PeerId::random()).collect();
+        let mut store = PeerStore::new(3);
+        for (seen_at, peer_id) in peers.iter().enumerate().take(3) {
+            store.record(*peer_id, address(4000 + seen_at as u16), seen_at as i64);
+        }
+        // Seen again, so the second peer becomes the least recently seen one.
+        store.record(peers[0], address(5000), 5);
+        store.record(peers[3], address(4003), 6);
+
+        assert_eq!(
+            store.addresses(),
+            vec![
+                address(4003).with(Protocol::P2p(peers[3].into())),
+                address(5000).with(Protocol::P2p(peers[0].into())),
+                address(4002).with(Protocol::P2p(peers[2].into())),
+            ]
+        );
+    }
+}
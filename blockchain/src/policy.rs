@@ -0,0 +1,34 @@
+#[cfg(feature = "native")]
+use crate::{Block, Node};
+use std::fmt;
+
+/// Deployment specific rule a block has to pass, on top of the validation every node does. It is
+/// consulted once the block is known to be well formed, before its data is applied. Nodes with
+/// different policies may disagree on the chain, so a network should use one policy.
+#[cfg(feature = "native")]
+pub trait BlockPolicy: Send + Sync {
+    fn accept(&self, block: &Block, node: &Node) -> Result<(), PolicyError>;
+}
+
+/// Policy of a node created without one, it accepts every valid block.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AcceptAll;
+
+/// Why the policy rejected the block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyError(pub String);
+
+#[cfg(feature = "native")]
+impl BlockPolicy for AcceptAll {
+    fn accept(&self, _block: &Block, _node: &Node) -> Result<(), PolicyError> {
+        Ok(())
+    }
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for PolicyError {}
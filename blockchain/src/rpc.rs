@@ -0,0 +1,241 @@
+use crate::api::{ask, ApiRequest, ApiSender, TransferRequest};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use node::Address;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+/// Error object of a JSON-RPC 2.0 response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RpcError {
+    code: i64,
+    message: &'static str,
+}
+
+impl RpcError {
+    const PARSE_ERROR: Self = Self::new(-32700, "Parse error");
+    const INVALID_REQUEST: Self = Self::new(-32600, "Invalid Request");
+    const METHOD_NOT_FOUND: Self = Self::new(-32601, "Method not found");
+    const INVALID_PARAMS: Self = Self::new(-32602, "Invalid params");
+    const INTERNAL_ERROR: Self = Self::new(-32603, "Internal error");
+    const TRANSFER_REJECTED: Self = Self::new(-32000, "Transfer rejected");
+
+    const fn new(code: i64, message: &'static str) -> Self {
+        Self { code, message }
+    }
+
+    fn response(self, id: Value) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "error": {"code": self.code, "message": self.message},
+            "id": id,
+        })
+    }
+}
+
+impl From<StatusCode> for RpcError {
+    fn from(_: StatusCode) -> Self {
+        RpcError::INTERNAL_ERROR
+    }
+}
+
+/// Answers a single call or a batch of calls, with no content if all of them were notifications.
+pub async fn handle_rpc(State(sender): State<ApiSender>, body: Bytes) -> Response {
+    let response = match serde_json::from_slice(&body) {
+        Ok(Value::Array(calls)) if calls.is_empty() => {
+            Some(RpcError::INVALID_REQUEST.response(Value::Null))
+        }
+        Ok(Value::Array(calls)) => {
+            let mut responses = vec![];
+            for call in calls {
+                responses.extend(handle_call(&sender, call).await);
+            }
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        Ok(call) => handle_call(&sender, call).await,
+        Err(_) => Some(RpcError::PARSE_ERROR.response(Value::Null)),
+    };
+    match response {
+        Some(response) => Json(response).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+/// Answers the call, or returns `None` for a notification, which has no id.
+async fn handle_call(sender: &ApiSender, call: Value) -> Option<Value> {
+    let mut call = match call {
+        Value::Object(call) => call,
+        _ => return Some(RpcError::INVALID_REQUEST.response(Value::Null)),
+    };
+    let id = call.remove("id");
+    let method = match (call.remove("jsonrpc"), call.remove("method")) {
+        (Some(version), Some(Value::String(method))) if version == "2.0" => method,
+        _ => return Some(RpcError::INVALID_REQUEST.response(id.unwrap_or(Value::Null))),
+    };
+    let params = call.remove("params").unwrap_or(Value::Array(vec![]));
+
+    let result = call_method(sender, &method, params).await;
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "result": result, "id": id}),
+        Err(error) => error.response(id),
+    })
+}
+
+async fn call_method(sender: &ApiSender, method: &str, params: Value) -> Result<Value, RpcError> {
+    match method {
+        "getBlockByNumber" => {
+            let (id,) = parse_params::<(u64,)>(params)?;
+            let block = ask(sender, |response| ApiRequest::Block(id, response)).await?;
+            Ok(json!(block))
+        }
+        "getBalance" => {
+            let (address,) = parse_params::<(Address,)>(params)?;
+            let account = ask(sender, |response| ApiRequest::Account(address, response)).await?;
+            Ok(json!(account.map(|account| account.balance)))
+        }
+        "sendTransfer" => {
+            let (transfer,) = parse_params::<(TransferRequest,)>(params)?;
+            match ask(sender, |response| {
+                ApiRequest::Transfer(transfer.into(), response)
+            })
+            .await?
+            {
+                true => Ok(Value::Bool(true)),
+                false => Err(RpcError::TRANSFER_REJECTED),
+            }
+        }
+        "getPeerCount" => Ok(json!(ask(sender, ApiRequest::Peers).await?.len())),
+        "getChainHeight" => Ok(json!(ask(sender, ApiRequest::Height).await?)),
+        _ => Err(RpcError::METHOD_NOT_FOUND),
+    }
+}
+
+/// Params are positional, given as an array.
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|_| RpcError::INVALID_PARAMS)
+}
+
+#[cfg(test)]
+mod rpc_tests {
+    use crate::api::{router, spawn_node, ApiSender};
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use http_body_util::BodyExt;
+    use node::{sign_transfer, Node, GENESIS_PRIVATE_KEY};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    async fn rpc(sender: &ApiSender, body: String) -> (StatusCode, Value) {
+        let request = Request::post("/rpc")
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        let response = router(sender.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    fn call(id: u64, method: &str, params: Value) -> Value {
+        json!({"jsonrpc": "2.0", "method": method, "params": params, "id": id})
+    }
+
+    #[tokio::test]
+    async fn answers_single_calls() {
+        let mut node = Node::new();
+        node.genesis();
+        let genesis_block = node.blocks[0].clone();
+        let receiver = node.add_account().0.address;
+        let sender = spawn_node(node);
+
+        let (status, response) =
+            rpc(&sender, call(1, "getChainHeight", json!([])).to_string()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response, json!({"jsonrpc": "2.0", "result": 0, "id": 1}));
+
+        let (_, response) = rpc(&sender, call(2, "getBlockByNumber", json!([0])).to_string()).await;
+        assert_eq!(response["result"], json!(genesis_block));
+
+        let (_, response) = rpc(&sender, call(3, "getBalance", json!([0])).to_string()).await;
+        assert_eq!(response["result"], json!(node::GENESIS_SUPPLY));
+
+        let (_, response) = rpc(&sender, call(4, "getPeerCount", json!([])).to_string()).await;
+        assert_eq!(response["result"], json!(1));
+
+        let transfer = sign_transfer(&GENESIS_PRIVATE_KEY, 0, receiver, 10, 0, 0);
+        let params = json!([{
+            "sender": transfer.sender(),
+            "receiver": transfer.receiver(),
+            "amount": transfer.amount(),
+            "nonce": transfer.nonce(),
+            "signature": transfer.signature(),
+        }]);
+        let (_, response) = rpc(&sender, call(5, "sendTransfer", params.clone()).to_string()).await;
+        assert_eq!(response, json!({"jsonrpc": "2.0", "result": true, "id": 5}));
+
+        let (_, response) = rpc(&sender, call(6, "sendTransfer", params).to_string()).await;
+        assert_eq!(response["error"]["code"], json!(-32000));
+        assert_eq!(response["id"], json!(6));
+
+        let (status, response) = rpc(&sender, "{".to_string()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response["error"]["code"], json!(-32700));
+        assert_eq!(response["id"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn answers_batched_calls() {
+        let mut node = Node::new();
+        node.genesis();
+        let sender = spawn_node(node);
+
+        let batch = json!([
+            call(1, "getChainHeight", json!([])),
+            call(2, "getBlockByNumber", json!(["zero"])),
+            call(3, "getBalance", json!([])),
+            call(4, "mineBlock", json!([])),
+            {"jsonrpc": "2.0", "method": "getPeerCount"},
+            {"method": "getChainHeight", "id": 5},
+            call(6, "getBlockByNumber", json!([7])),
+        ]);
+        let (status, response) = rpc(&sender, batch.to_string()).await;
+
+        assert_eq!(status, StatusCode::OK);
+        let error_code = |index: usize| response[index]["error"]["code"].clone();
+        assert_eq!(response.as_array().unwrap().len(), 6);
+        assert_eq!(response[0], json!({"jsonrpc": "2.0", "result": 0, "id": 1}));
+        assert_eq!(
+            (error_code(1), &response[1]["id"]),
+            (json!(-32602), &json!(2))
+        );
+        assert_eq!(
+            (error_code(2), &response[2]["id"]),
+            (json!(-32602), &json!(3))
+        );
+        assert_eq!(
+            (error_code(3), &response[3]["id"]),
+            (json!(-32601), &json!(4))
+        );
+        assert_eq!(
+            (error_code(4), &response[4]["id"]),
+            (json!(-32600), &json!(5))
+        );
+        assert_eq!(
+            response[5],
+            json!({"jsonrpc": "2.0", "result": null, "id": 6})
+        );
+
+        let (_, response) = rpc(&sender, "[]".to_string()).await;
+        assert_eq!(response["error"]["code"], json!(-32600));
+
+        let notifications = json!([{"jsonrpc": "2.0", "method": "getPeerCount"}]);
+        let (status, _) = rpc(&sender, notifications.to_string()).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+}
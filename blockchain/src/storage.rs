@@ -0,0 +1,557 @@
+use crate::{
+    Account, Address, Block, ChainReport, Checkpoint, GenesisConfig, Node, PrivateKey, PublicKey,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize)]
+struct NodeState {
+    blocks: Vec<Block>,
+    accounts: HashMap<Address, Account>,
+    pub_keys: HashMap<Address, PublicKey>,
+    /// Set once the chain is pruned, `blocks` then start at the checkpoint block.
+    #[serde(default)]
+    checkpoint: Option<Checkpoint>,
+}
+
+/// Directory holding the files of a node, so nodes given different directories on one machine
+/// keep independent state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataDir {
+    path: PathBuf,
+}
+
+impl DataDir {
+    /// Opens the directory, creating it on first run accessible only by the owner on Unix.
+    pub fn create(path: &Path) -> io::Result<DataDir> {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(path)?;
+        Ok(DataDir {
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn chain_file(&self) -> PathBuf {
+        self.path.join("chain.json")
+    }
+
+    pub fn wallet_file(&self) -> PathBuf {
+        self.path.join("wallet.json")
+    }
+
+    pub fn key_dir(&self) -> PathBuf {
+        self.path.join("keys")
+    }
+
+    pub fn peers_file(&self) -> PathBuf {
+        self.path.join("peers.json")
+    }
+}
+
+pub fn read_chain(path: &Path) -> io::Result<Vec<Block>> {
+    let json = fs::read_to_string(path)?;
+    serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes the blocks in the format read by `read_chain`.
+pub fn write_chain(path: &Path, blocks: &[Block]) -> io::Result<()> {
+    let json = serde_json::to_string(blocks)?;
+    write_atomically(path, json.as_bytes())
+}
+
+/// Header of the CSV written by `Node::dump_state`.
+const STATE_CSV_HEADER: &str = "address,balance,pub_key,nonce";
+
+/// Accounts as CSV rows sorted by address, so dumps of the same state are identical.
+fn accounts_csv(accounts: &HashMap<Address, Account>) -> String {
+    let mut accounts: Vec<&Account> = accounts.values().collect();
+    accounts.sort_by_key(|account| account.address);
+    let mut csv = format!("{}\n", STATE_CSV_HEADER);
+    for account in accounts {
+        let fields = [
+            account.address.to_string(),
+            account.balance.to_string(),
+            account.pub_key.to_string(),
+            account.nonce.to_string(),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Quotes a field holding a separator, quote or line break, doubling its quotes.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub fn read_genesis_config(path: &Path) -> io::Result<GenesisConfig> {
+    let json = fs::read_to_string(path)?;
+    let config = serde_json::from_str::<GenesisConfig>(&json)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    config
+        .validate()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(config)
+}
+
+/// Writes `contents` to a temporary file next to `path`, syncs it and renames it over `path`,
+/// so a crash mid-write never leaves a truncated file behind.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_atomically_with(path, contents, false)
+}
+
+/// Like `write_atomically`, but the file is readable only by the owner on Unix.
+pub fn write_private_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_atomically_with(path, contents, true)
+}
+
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn write_atomically_with(path: &Path, contents: &[u8], owner_only: bool) -> io::Result<()> {
+    let temp_path = get_temp_path(path);
+
+    let mut file = File::create(&temp_path)?;
+    #[cfg(unix)]
+    if owner_only {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)?;
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Writes the hex private key to `<dir>/<address>.key`, readable only by the owner on Unix.
+pub fn write_private_key(
+    dir: &Path,
+    address: Address,
+    private_key: &PrivateKey,
+) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{}.key", address));
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(&path)?;
+    file.write_all(private_key.to_string().as_bytes())?;
+    file.sync_all()?;
+    Ok(path)
+}
+
+fn get_temp_path(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.tmp", file_name))
+}
+
+impl Node {
+    pub fn save_to_file(&self, path: &Path) -> io::Result<()> {
+        let state = NodeState {
+            blocks: self.blocks.clone(),
+            accounts: self.accounts.clone(),
+            pub_keys: self.pub_keys.clone(),
+            checkpoint: self.checkpoint.clone(),
+        };
+        let json = serde_json::to_string(&state)?;
+        write_atomically(path, json.as_bytes())
+    }
+
+    /// Writes the accounts as CSV with the columns `address,balance,pub_key,nonce`.
+    pub fn dump_state(&self, path: &Path) -> io::Result<()> {
+        write_atomically(path, accounts_csv(&self.accounts).as_bytes())
+    }
+
+    pub fn load_from_file(path: &Path) -> io::Result<Node> {
+        Node::new().load_chain_file(path)
+    }
+
+    /// Validates the chain file with the genesis block and settings of this node.
+    pub fn validate_chain_file(&self, path: &Path) -> io::Result<ChainReport> {
+        let chain = read_chain(path)?;
+        Ok(self.validate_chain(&chain))
+    }
+
+    /// Loads the saved state, validated with the genesis block and settings of this node.
+    pub fn load_chain_file(&self, path: &Path) -> io::Result<Node> {
+        let json = fs::read_to_string(path)?;
+        let state = serde_json::from_str::<NodeState>(&json)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        // The saved checkpoint is trusted, the blocks after it are validated on top of it.
+        let mut node = self.with_same_settings();
+        node.checkpoint = state.checkpoint;
+        if !node.is_chain_valid(&state.blocks) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Saved chain is invalid.",
+            ));
+        }
+        match node.replay_chain(state.blocks) {
+            Some(node) if node.accounts == state.accounts && node.pub_keys == state.pub_keys => {
+                Ok(node)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Saved accounts do not match the chain.",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod storage_tests {
+    use super::*;
+    use crate::wallet::Wallet;
+    use crate::{Account, Data, PublicKey};
+
+    fn get_test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ch-rust-{}-{}", std::process::id(), name))
+    }
+
+    fn get_test_chain() -> Vec<Block> {
+        let mut node = Node::new();
+        node.genesis();
+        let genesis_hash = node.get_last_block().hash.clone();
+        let block = Block::new(
+            1,
+            genesis_hash,
+            Data::Account(Account {
+                address: 7,
+                balance: 0,
+                pub_key: PublicKey([0x77; 32]),
+                nonce: 0,
+            }),
+            node.difficulty,
+        );
+        assert!(node.try_add_block(block).is_ok());
+        node.blocks
+    }
+
+    #[test]
+    fn validates_good_chain_file() {
+        let path = get_test_path("good.json");
+        fs::write(&path, serde_json::to_string(&get_test_chain()).unwrap()).unwrap();
+
+        let report = Node::new().validate_chain_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.exit_code(), 0);
+        assert_eq!(report.total_blocks, 2);
+        assert_eq!(report.total_accounts, 2);
+    }
+
+    #[test]
+    fn reports_tampered_block_in_chain_file() {
+        let path = get_test_path("tampered.json");
+        let mut chain = get_test_chain();
+        chain[1].timestamp += 1;
+        fs::write(&path, serde_json::to_string(&chain).unwrap()).unwrap();
+
+        let report = Node::new().validate_chain_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_ne!(report.exit_code(), 0);
+        assert_eq!(report.invalid_block, Some(1));
+    }
+
+    #[test]
+    fn saves_and_loads_node() {
+        let path = get_test_path("round-trip.json");
+        let mut node = Node::new();
+        node.adopt_chain(get_test_chain());
+
+        node.save_to_file(&path).unwrap();
+        let loaded = Node::load_from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.blocks, node.blocks);
+        assert_eq!(loaded.accounts, node.accounts);
+        assert_eq!(loaded.pub_keys, node.pub_keys);
+    }
+
+    #[test]
+    fn saves_and_loads_pruned_node() {
+        let path = get_test_path("pruned.json");
+        let mut node = Node::new();
+        node.adopt_chain(get_test_chain());
+        while node.blocks.len() < 12 {
+            let tip = node.get_last_block();
+            let block = Block::new(
+                tip.id + 1,
+                tip.hash.clone(),
+                Data::Batch(vec![]),
+                node.compute_difficulty(),
+            );
+            assert!(node.try_add_block(block).is_ok());
+        }
+        assert_eq!(node.prune_below(10), Some(10));
+
+        node.save_to_file(&path).unwrap();
+        let loaded = Node::load_from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.blocks, node.blocks);
+        assert_eq!(loaded.accounts, node.accounts);
+        assert_eq!(loaded.checkpoint(), node.checkpoint());
+    }
+
+    #[test]
+    fn keeps_independent_state_per_data_dir() {
+        let root = get_test_path("datadirs");
+        let first = DataDir::create(&root.join("first")).unwrap();
+        let second = DataDir::create(&root.join("second")).unwrap();
+        let mut first_node = Node::new();
+        first_node.adopt_chain(get_test_chain());
+        let mut second_node = Node::new();
+        second_node.genesis();
+        let mut first_wallet = Wallet::new();
+        first_wallet.new_account();
+
+        first_node.save_to_file(&first.chain_file()).unwrap();
+        second_node.save_to_file(&second.chain_file()).unwrap();
+        first_wallet.save(&first.wallet_file()).unwrap();
+        Wallet::new().save(&second.wallet_file()).unwrap();
+        let loaded_first = Node::load_from_file(&first.chain_file()).unwrap();
+        let loaded_second = Node::load_from_file(&second.chain_file()).unwrap();
+        let first_addresses = Wallet::load(&first.wallet_file()).unwrap().addresses();
+        let second_addresses = Wallet::load(&second.wallet_file()).unwrap().addresses();
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            fs::metadata(first.path()).unwrap().permissions().mode()
+        };
+        // Opening an existing directory again keeps its files.
+        let reopened = DataDir::create(first.path()).unwrap();
+        let is_chain_kept = reopened.chain_file().exists();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(loaded_first.blocks, first_node.blocks);
+        assert_eq!(loaded_second.blocks, second_node.blocks);
+        assert_ne!(loaded_first.blocks, loaded_second.blocks);
+        assert_eq!(first_addresses, first_wallet.addresses());
+        assert!(second_addresses.is_empty());
+        assert_ne!(first.key_dir(), second.key_dir());
+        assert!(is_chain_kept);
+        #[cfg(unix)]
+        assert_eq!(mode & 0o777, 0o700);
+    }
+
+    #[test]
+    fn writes_private_key_for_owner_only() {
+        let dir = get_test_path("keys");
+        let (private_key, _) = crate::crypto::generate_keypair();
+
+        let path = write_private_key(&dir, 7, &private_key).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        let overwritten = write_private_key(&dir, 7, &private_key);
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            fs::metadata(&path).unwrap().permissions().mode()
+        };
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(path, dir.join("7.key"));
+        assert_eq!(saved.parse::<PrivateKey>().unwrap(), private_key);
+        assert!(overwritten.is_err());
+        #[cfg(unix)]
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn exports_and_imports_chain() {
+        let path = get_test_path("export.json");
+        let mut node = Node::new();
+        node.adopt_chain(get_test_chain());
+
+        write_chain(&path, &node.blocks).unwrap();
+        let mut other = Node::new();
+        other.genesis();
+        let is_imported = other.import_chain(read_chain(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert!(is_imported);
+        assert_eq!(other.blocks, node.blocks);
+        assert_eq!(other.accounts, node.accounts);
+    }
+
+    #[test]
+    fn dumps_state_as_csv() {
+        let path = get_test_path("state.csv");
+        let mut node = Node::new();
+        node.adopt_chain(get_test_chain());
+        node.add_account();
+
+        node.dump_state(&path).unwrap();
+        let csv = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("address,balance,pub_key,nonce"));
+        let rows: Vec<Account> = lines
+            .map(|line| match line.split(',').collect::<Vec<_>>()[..] {
+                [address, balance, pub_key, nonce] => Account {
+                    address: address.parse().unwrap(),
+                    balance: balance.parse().unwrap(),
+                    pub_key: pub_key.parse().unwrap(),
+                    nonce: nonce.parse().unwrap(),
+                },
+                _ => panic!("Unexpected row: {}", line),
+            })
+            .collect();
+        assert!(rows
+            .windows(2)
+            .all(|pair| pair[0].address < pair[1].address));
+        let accounts: HashMap<Address, Account> = rows
+            .into_iter()
+            .map(|account| (account.address, account))
+            .collect();
+        assert_eq!(accounts, node.accounts);
+
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+        assert_eq!(csv_field("42"), "42");
+    }
+
+    #[test]
+    fn does_not_import_shorter_chain() {
+        let path = get_test_path("export-short.json");
+        let mut node = Node::new();
+        node.genesis();
+        write_chain(&path, &node.blocks).unwrap();
+
+        let mut other = Node::new();
+        other.adopt_chain(get_test_chain());
+        let is_imported = other.import_chain(read_chain(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert!(!is_imported);
+        assert_eq!(other.blocks, get_test_chain());
+    }
+
+    #[test]
+    fn loads_node_only_with_its_genesis_config() {
+        let path = get_test_path("genesis.json");
+        let config_path = get_test_path("genesis-config.json");
+        fs::write(
+            &config_path,
+            br#"{"timestamp": 1700000000, "accounts": [{"address": 3, "balance": 100,
+                "pub_key": "1111111111111111111111111111111111111111111111111111111111111111"}]}"#,
+        )
+        .unwrap();
+        let config = read_genesis_config(&config_path).unwrap();
+        fs::remove_file(&config_path).unwrap();
+        let mut node = Node::new();
+        node.genesis_from_config(&config).unwrap();
+
+        node.save_to_file(&path).unwrap();
+        let mut configured = Node::new();
+        configured.set_genesis(&config).unwrap();
+        let loaded = configured.load_chain_file(&path).unwrap();
+        let default_result = Node::load_from_file(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.blocks, node.blocks);
+        assert_eq!(loaded.accounts[&3].balance, 100);
+        assert!(default_result.is_err());
+    }
+
+    #[test]
+    fn does_not_read_genesis_config_with_duplicate_accounts() {
+        let path = get_test_path("duplicate-genesis-config.json");
+        let account = r#"{"address": 3, "balance": 100,
+            "pub_key": "1111111111111111111111111111111111111111111111111111111111111111"}"#;
+        fs::write(
+            &path,
+            format!(
+                r#"{{"timestamp": 0, "accounts": [{}, {}]}}"#,
+                account, account
+            ),
+        )
+        .unwrap();
+
+        let result = read_genesis_config(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn does_not_load_corrupted_file() {
+        let path = get_test_path("corrupted.json");
+        fs::write(&path, b"{\"blocks\": [{\"id\": 0,").unwrap();
+
+        let result = Node::load_from_file(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn does_not_load_tampered_accounts() {
+        let path = get_test_path("tampered-accounts.json");
+        let mut node = Node::new();
+        node.adopt_chain(get_test_chain());
+        node.accounts.get_mut(&7).unwrap().balance = 1000;
+
+        node.save_to_file(&path).unwrap();
+        let result = Node::load_from_file(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn keeps_previous_file_loadable_after_interrupted_write() {
+        let path = get_test_path("interrupted.json");
+        let mut node = Node::new();
+        node.genesis();
+        node.save_to_file(&path).unwrap();
+
+        // Simulate a crash after writing the temporary file but before the rename.
+        fs::write(get_temp_path(&path), b"[{\"id\": 1, \"hash\":").unwrap();
+        let loaded = Node::load_from_file(&path).unwrap();
+
+        assert_eq!(loaded.blocks, node.blocks);
+
+        node.save_to_file(&path).unwrap();
+        assert!(!get_temp_path(&path).exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn writes_atomically_over_existing_file() {
+        let path = get_test_path("overwrite.txt");
+        fs::write(&path, b"old").unwrap();
+
+        write_atomically(&path, b"new").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert!(!get_temp_path(&path).exists());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,234 @@
+use crate::crypto::derive_public_key;
+use crate::storage::write_private_atomically;
+use crate::{sign_transfer_on_chain, Account, Address, Data, Node, PrivateKey, PublicKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Private keys of the local accounts, kept apart from the chain file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Wallet {
+    keys: HashMap<Address, (PublicKey, PrivateKey)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalletError {
+    MissingKey(Address),
+    UnknownSender(Address),
+    KeyMismatch(Address),
+}
+
+/// Saved form of a key, the public key is derived again on load.
+#[derive(Serialize, Deserialize)]
+struct WalletKey {
+    address: Address,
+    private_key: PrivateKey,
+}
+
+impl Wallet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Wallet> {
+        let json = fs::read_to_string(path)?;
+        let keys = serde_json::from_str::<Vec<WalletKey>>(&json)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut wallet = Wallet::new();
+        for key in keys {
+            wallet.import_key(key.address, key.private_key);
+        }
+        Ok(wallet)
+    }
+
+    /// Writes the keys as JSON, readable only by the owner on Unix.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut keys: Vec<WalletKey> = self
+            .keys
+            .iter()
+            .map(|(&address, &(_, private_key))| WalletKey {
+                address,
+                private_key,
+            })
+            .collect();
+        keys.sort_by_key(|key| key.address);
+        let json = serde_json::to_string_pretty(&keys)?;
+        write_private_atomically(path, json.as_bytes())
+    }
+
+    /// Generates an account and keeps its private key, the account exists on the chain only
+    /// once a block announcing it is added.
+    pub fn new_account(&mut self) -> (Account, PrivateKey) {
+        let (account, private_key) = Account::new();
+        self.keys
+            .insert(account.address, (account.pub_key, private_key));
+        (account, private_key)
+    }
+
+    /// Keeps the key of an existing account, e.g. one created on another machine.
+    pub fn import_key(&mut self, address: Address, private_key: PrivateKey) -> PublicKey {
+        let pub_key = derive_public_key(&private_key);
+        self.keys.insert(address, (pub_key, private_key));
+        pub_key
+    }
+
+    pub fn addresses(&self) -> Vec<Address> {
+        let mut addresses: Vec<Address> = self.keys.keys().copied().collect();
+        addresses.sort_unstable();
+        addresses
+    }
+
+    /// Signs a transfer from an account of the wallet with the next nonce of the sender.
+    pub fn sign_transfer(
+        &self,
+        node: &Node,
+        sender: Address,
+        receiver: Address,
+        amount: u64,
+        fee: u64,
+    ) -> Result<Data, WalletError> {
+        let (pub_key, private_key) = self
+            .keys
+            .get(&sender)
+            .ok_or(WalletError::MissingKey(sender))?;
+        match node.pub_keys.get(&sender) {
+            None => return Err(WalletError::UnknownSender(sender)),
+            Some(key) if key != pub_key => return Err(WalletError::KeyMismatch(sender)),
+            Some(_) => {}
+        }
+        let nonce = node
+            .next_nonce(sender)
+            .ok_or(WalletError::UnknownSender(sender))?;
+        Ok(sign_transfer_on_chain(
+            node.chain_id,
+            private_key,
+            sender,
+            receiver,
+            amount,
+            fee,
+            nonce,
+        ))
+    }
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WalletError::MissingKey(address) => {
+                write!(f, "no key of account {} in the wallet", address)
+            }
+            WalletError::UnknownSender(address) => write!(f, "unknown sender {}", address),
+            WalletError::KeyMismatch(address) => {
+                write!(f, "wallet key does not match account {}", address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WalletError {}
+
+#[cfg(test)]
+mod wallet_tests {
+    use super::*;
+    use crate::{Block, GENESIS_PRIVATE_KEY};
+    use std::path::PathBuf;
+
+    fn get_test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ch-rust-wallet-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn saves_and_loads_keys() {
+        let path = get_test_path("persist.json");
+        let mut node = Node::new();
+        node.genesis();
+        let mut wallet = Wallet::new();
+        let (account, _) = wallet.new_account();
+        wallet.import_key(0, GENESIS_PRIVATE_KEY);
+
+        wallet.save(&path).unwrap();
+        let loaded = Wallet::load(&path);
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            fs::metadata(&path).unwrap().permissions().mode()
+        };
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), wallet);
+        assert_eq!(wallet.addresses(), {
+            let mut addresses = vec![0, account.address];
+            addresses.sort_unstable();
+            addresses
+        });
+        #[cfg(unix)]
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn signs_transfers_accepted_by_node() {
+        let mut node = Node::new();
+        node.genesis();
+        let mut wallet = Wallet::new();
+        wallet.import_key(0, GENESIS_PRIVATE_KEY);
+        let (account, _) = wallet.new_account();
+        assert_eq!(node.balance_of(account.address), None);
+        let block = Block::new(
+            1,
+            node.get_last_block().hash.clone(),
+            Data::Account(account.clone()),
+            node.compute_difficulty(),
+        );
+        assert_eq!(node.try_add_block(block), Ok(()));
+        let (receiver, _) = node.add_account();
+
+        let funding = wallet
+            .sign_transfer(&node, 0, account.address, 100, 0)
+            .unwrap();
+        assert_eq!(node.try_add_transfer(&funding), Ok(()));
+        let transfer = wallet
+            .sign_transfer(&node, account.address, receiver.address, 40, 0)
+            .unwrap();
+        assert_eq!(node.try_add_transfer(&transfer), Ok(()));
+        let again = wallet
+            .sign_transfer(&node, account.address, receiver.address, 40, 0)
+            .unwrap();
+        let block = Block::new(
+            2,
+            node.get_last_block().hash.clone(),
+            again,
+            node.compute_difficulty(),
+        );
+
+        assert_eq!(node.try_add_block(block), Ok(()));
+        assert_eq!(node.balance_of(account.address), Some(20));
+        assert_eq!(node.balance_of(receiver.address), Some(80));
+    }
+
+    #[test]
+    fn rejects_senders_without_matching_key() {
+        let mut node = Node::new();
+        node.genesis();
+        let mut wallet = Wallet::new();
+        let (other, _) = node.add_account();
+
+        assert_eq!(
+            wallet.sign_transfer(&node, 0, other.address, 1, 0),
+            Err(WalletError::MissingKey(0))
+        );
+        wallet.import_key(0, PrivateKey([0x01; 32]));
+        assert_eq!(
+            wallet.sign_transfer(&node, 0, other.address, 1, 0),
+            Err(WalletError::KeyMismatch(0))
+        );
+        wallet.import_key(7, GENESIS_PRIVATE_KEY);
+        assert_eq!(
+            wallet.sign_transfer(&node, 7, other.address, 1, 0),
+            Err(WalletError::UnknownSender(7))
+        );
+    }
+}
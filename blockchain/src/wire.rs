@@ -0,0 +1,364 @@
+use crate::p2p::{ChainResponse, LocalChainRequest, MAX_MESSAGE_BYTES};
+#[cfg(feature = "binary-wire")]
+use bincode::Options;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use node::Block;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{Read, Write};
+
+/// Bumped on incompatible changes of the envelope or of the messages.
+pub const WIRE_VERSION: u8 = 3;
+/// Version before compression, its messages are still decoded so nodes can be upgraded one by one.
+pub const LEGACY_WIRE_VERSION: u8 = 2;
+const HEADER_LEN: usize = 3;
+/// Smaller payloads are sent as they are, compressing them saves little.
+const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Codec of the outgoing messages.
+#[cfg(feature = "binary-wire")]
+pub const CODEC: Codec = Codec::Bincode;
+#[cfg(not(feature = "binary-wire"))]
+pub const CODEC: Codec = Codec::Json;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    #[cfg_attr(feature = "binary-wire", allow(dead_code))]
+    Json,
+    #[cfg(feature = "binary-wire")]
+    Bincode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+}
+
+/// Gossiped message, sent after a `[WIRE_VERSION, codec id, compression id]` header.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Message {
+    ChainResponse(ChainResponse),
+    ChainRequest(LocalChainRequest),
+    Block(Box<Block>),
+}
+
+#[derive(Debug)]
+pub enum WireError {
+    MissingHeader,
+    UnsupportedVersion(u8),
+    IncompatibleCodec(u8),
+    UnknownCompression(u8),
+    Malformed(String),
+}
+
+impl Codec {
+    fn id(self) -> u8 {
+        match self {
+            Codec::Json => 0,
+            #[cfg(feature = "binary-wire")]
+            Codec::Bincode => 1,
+        }
+    }
+
+    /// Compresses the payload if it is large enough for it to pay off.
+    pub fn encode(self, message: &Message) -> Vec<u8> {
+        let payload = self.serialize(message);
+        let compression = if payload.len() >= COMPRESSION_THRESHOLD {
+            Compression::Gzip
+        } else {
+            Compression::None
+        };
+        self.envelope(&payload, compression)
+    }
+
+    fn serialize(self, message: &Message) -> Vec<u8> {
+        match self {
+            Codec::Json => serde_json::to_vec(message).expect("Can jsonify message."),
+            #[cfg(feature = "binary-wire")]
+            Codec::Bincode => bincode::DefaultOptions::new()
+                .serialize(message)
+                .expect("Can serialize message."),
+        }
+    }
+
+    fn envelope(self, payload: &[u8], compression: Compression) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+        bytes.extend_from_slice(&[WIRE_VERSION, self.id(), compression.id()]);
+        match compression {
+            Compression::None => bytes.extend_from_slice(payload),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(bytes, flate2::Compression::default());
+                encoder.write_all(payload).expect("Can compress in memory.");
+                bytes = encoder.finish().expect("Can compress in memory.");
+            }
+        }
+        bytes
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> Result<Message, WireError> {
+        let (codec, compression, payload) = match bytes {
+            [LEGACY_WIRE_VERSION, codec, payload @ ..] => (*codec, Compression::None.id(), payload),
+            [WIRE_VERSION, codec, compression, payload @ ..] => (*codec, *compression, payload),
+            [version, _, ..] if *version != WIRE_VERSION => {
+                return Err(WireError::UnsupportedVersion(*version))
+            }
+            _ => return Err(WireError::MissingHeader),
+        };
+        if codec != self.id() {
+            return Err(WireError::IncompatibleCodec(codec));
+        }
+        let decompressed;
+        let payload = match Compression::from_id(compression) {
+            Some(Compression::None) => payload,
+            Some(Compression::Gzip) => {
+                decompressed = decompress(payload)?;
+                &decompressed[..]
+            }
+            None => return Err(WireError::UnknownCompression(compression)),
+        };
+
+        match self {
+            Codec::Json => {
+                serde_json::from_slice(payload).map_err(|e| WireError::Malformed(e.to_string()))
+            }
+            // The limit keeps a forged length prefix from allocating more than was received.
+            #[cfg(feature = "binary-wire")]
+            Codec::Bincode => bincode::DefaultOptions::new()
+                .with_limit(payload.len() as u64)
+                .deserialize(payload)
+                .map_err(|e| WireError::Malformed(e.to_string())),
+        }
+    }
+}
+
+impl Compression {
+    fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Gzip => 1,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Gzip),
+            _ => None,
+        }
+    }
+}
+
+/// Stops at the size of the largest message, so a small message cannot expand without bound.
+fn decompress(payload: &[u8]) -> Result<Vec<u8>, WireError> {
+    let mut decompressed = vec![];
+    GzDecoder::new(payload)
+        .take(MAX_MESSAGE_BYTES as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| WireError::Malformed(e.to_string()))?;
+    if decompressed.len() > MAX_MESSAGE_BYTES {
+        return Err(WireError::Malformed(format!(
+            "decompressed to over {} bytes",
+            MAX_MESSAGE_BYTES
+        )));
+    }
+    Ok(decompressed)
+}
+
+pub fn encode(message: &Message) -> Vec<u8> {
+    CODEC.encode(message)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Message, WireError> {
+    CODEC.decode(bytes)
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WireError::MissingHeader => write!(f, "message has no header"),
+            WireError::UnsupportedVersion(version) => {
+                write!(f, "unsupported wire version {}", version)
+            }
+            WireError::IncompatibleCodec(codec) => write!(f, "incompatible codec {}", codec),
+            WireError::UnknownCompression(compression) => {
+                write!(f, "unknown compression {}", compression)
+            }
+            WireError::Malformed(e) => write!(f, "malformed message, {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+#[cfg(test)]
+mod wire_tests {
+    use super::*;
+    use libp2p::PeerId;
+    use node::crypto::generate_keypair;
+    use node::{sign_transfer, sign_transfer_to_new_account, Account, Data, Node};
+
+    fn get_messages() -> Vec<Message> {
+        let mut node = Node::new();
+        node.genesis();
+        let (account, _) = Account::new();
+        let data = node.with_reward(Data::Account(account));
+        let genesis = node.get_last_block();
+        let block = Block::new(1, genesis.hash.clone(), data, node.compute_difficulty());
+        assert!(node.try_add_block(block).is_ok());
+        vec![
+            Message::ChainResponse(ChainResponse {
+                blocks: node.blocks.clone(),
+                receiver: "peer".to_string(),
+                from_height: 0,
+                chain_len: 2,
+            }),
+            Message::ChainRequest(LocalChainRequest {
+                from_peer_id: "peer".to_string(),
+                from_height: 1,
+                max_blocks: 32,
+            }),
+            Message::Block(Box::new(node.get_last_block().clone())),
+            Message::Block(Box::new(Block {
+                miner: Some(PeerId::random().to_string()),
+                ..node.get_last_block().clone()
+            })),
+            Message::Block(Box::new(Block::new(
+                2,
+                "hash".to_string(),
+                get_transfers(),
+                1,
+            ))),
+        ]
+    }
+
+    fn get_transfers() -> Data {
+        let (private_key, pub_key) = generate_keypair();
+        Data::Batch(vec![
+            sign_transfer(&private_key, 1, 2, 10, 1, 0),
+            sign_transfer_to_new_account(0, &private_key, 1, 3, pub_key, 10, 1, 1),
+        ])
+    }
+
+    #[test]
+    fn round_trips_each_message() {
+        for message in get_messages() {
+            let bytes = encode(&message);
+
+            assert_eq!(&bytes[..HEADER_LEN - 1], &[WIRE_VERSION, CODEC.id()]);
+            assert_eq!(decode(&bytes).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn round_trips_compressed_and_uncompressed_messages() {
+        for message in get_messages() {
+            let payload = CODEC.serialize(&message);
+            let compressed = CODEC.envelope(&payload, Compression::Gzip);
+            let uncompressed = CODEC.envelope(&payload, Compression::None);
+            let mut legacy = vec![LEGACY_WIRE_VERSION, CODEC.id()];
+            legacy.extend_from_slice(&payload);
+
+            assert_eq!(compressed[HEADER_LEN - 1], Compression::Gzip.id());
+            assert_eq!(uncompressed[HEADER_LEN - 1], Compression::None.id());
+            for bytes in [compressed, uncompressed, legacy] {
+                assert_eq!(decode(&bytes).unwrap(), message);
+            }
+        }
+    }
+
+    #[test]
+    fn compresses_large_payloads() {
+        let mut node = Node::new();
+        node.genesis();
+        let chain_response = Message::ChainResponse(ChainResponse {
+            blocks: vec![node.get_last_block().clone(); 32],
+            receiver: "peer".to_string(),
+            from_height: 0,
+            chain_len: 32,
+        });
+        let payload = CODEC.serialize(&chain_response);
+        let bytes = encode(&chain_response);
+
+        assert!(payload.len() >= COMPRESSION_THRESHOLD);
+        assert_eq!(bytes[HEADER_LEN - 1], Compression::Gzip.id());
+        assert!(bytes.len() < payload.len());
+        assert_eq!(decode(&bytes).unwrap(), chain_response);
+
+        let request = Message::ChainRequest(LocalChainRequest {
+            from_peer_id: "peer".to_string(),
+            from_height: 0,
+            max_blocks: 32,
+        });
+        assert_eq!(encode(&request)[HEADER_LEN - 1], Compression::None.id());
+    }
+
+    #[test]
+    fn rejects_oversized_or_unknown_compression() {
+        let bomb = vec![0; MAX_MESSAGE_BYTES + 1];
+        let bytes = CODEC.envelope(&bomb, Compression::Gzip);
+        assert!(bytes.len() < MAX_MESSAGE_BYTES / 100);
+        assert!(matches!(decode(&bytes), Err(WireError::Malformed(_))));
+
+        let mut bytes = CODEC.envelope(b"", Compression::None);
+        bytes[HEADER_LEN - 1] = 7;
+        assert!(matches!(
+            decode(&bytes),
+            Err(WireError::UnknownCompression(7))
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_envelope() {
+        let mut bytes = encode(&Message::ChainRequest(LocalChainRequest {
+            from_peer_id: "peer".to_string(),
+            from_height: 0,
+            max_blocks: 32,
+        }));
+
+        assert!(matches!(decode(&bytes[..1]), Err(WireError::MissingHeader)));
+        bytes[1] = 42;
+        assert!(matches!(
+            decode(&bytes),
+            Err(WireError::IncompatibleCodec(42))
+        ));
+        bytes[0] = WIRE_VERSION + 1;
+        assert!(matches!(
+            decode(&bytes),
+            Err(WireError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[cfg(feature = "binary-wire")]
+    #[test]
+    fn binary_codec_is_smaller_and_rejects_json() {
+        for message in get_messages() {
+            let json = Codec::Json.encode(&message);
+            let binary = Codec::Bincode.encode(&message);
+
+            assert!(binary.len() < json.len());
+            assert!(matches!(
+                Codec::Bincode.decode(&json),
+                Err(WireError::IncompatibleCodec(0))
+            ));
+        }
+    }
+
+    #[cfg(feature = "binary-wire")]
+    #[test]
+    fn rejects_forged_length_prefix() {
+        let mut bytes = Codec::Bincode.encode(&Message::ChainRequest(LocalChainRequest {
+            from_peer_id: "peer".to_string(),
+            from_height: 0,
+            max_blocks: 32,
+        }));
+        // Variant index, then the varint length of the peer id string.
+        bytes[HEADER_LEN + 1] = 250;
+
+        assert!(matches!(
+            Codec::Bincode.decode(&bytes),
+            Err(WireError::Malformed(_))
+        ));
+    }
+}